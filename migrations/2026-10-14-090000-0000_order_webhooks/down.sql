@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+drop table webhook_deliveries cascade;
+drop table order_webhooks cascade;
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+
+CREATE TABLE "order_webhooks" (
+  "id" serial PRIMARY KEY,
+  "url" text NOT NULL,
+  "event_mask" text NOT NULL DEFAULT '*', -- comma-separated statuses, or * for every status
+  "secret" text NOT NULL,
+  "failure_count" integer NOT NULL DEFAULT 0,
+  "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  "updated_at" TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TRIGGER update_order_webhooks_timestamp
+BEFORE UPDATE ON order_webhooks
+FOR EACH ROW
+EXECUTE FUNCTION diesel_set_updated_at();
+
+CREATE TABLE "webhook_deliveries" (
+  "id" serial PRIMARY KEY,
+  "webhook_id" integer NOT NULL,
+  "payload" text NOT NULL,
+  "status" text NOT NULL DEFAULT 'PENDING', -- PENDING, SENT, FAILED
+  "attempts" integer NOT NULL DEFAULT 0,
+  "last_error" text,
+  "next_attempt_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  "updated_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (webhook_id) REFERENCES order_webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_status_next_attempt_at_idx
+ON webhook_deliveries (status, next_attempt_at);
+
+CREATE TRIGGER update_webhook_deliveries_timestamp
+BEFORE UPDATE ON webhook_deliveries
+FOR EACH ROW
+EXECUTE FUNCTION diesel_set_updated_at();
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE "orders" ADD COLUMN "notes" text;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+ALTER TABLE "orders" ADD COLUMN "currency" VARCHAR(3) NOT NULL DEFAULT 'THB'; -- ISO 4217
+ALTER TABLE "payments" ADD COLUMN "currency" VARCHAR(3) NOT NULL DEFAULT 'THB'; -- ISO 4217
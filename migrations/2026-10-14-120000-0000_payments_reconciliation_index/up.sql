@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+CREATE INDEX payments_status_created_at_idx
+ON payments (status, created_at);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX orders_status_reserved_until_idx;
+ALTER TABLE "orders" DROP COLUMN "reserved_until";
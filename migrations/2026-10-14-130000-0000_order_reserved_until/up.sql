@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+ALTER TABLE "orders" ADD COLUMN "reserved_until" TIMESTAMPTZ; -- end of the inventory hold while RESERVED
+
+CREATE INDEX orders_status_reserved_until_idx
+ON orders (status, reserved_until);
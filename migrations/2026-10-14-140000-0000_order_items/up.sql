@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+CREATE TABLE "order_items" (
+  "order_id" integer NOT NULL,
+  "product_id" integer NOT NULL,
+  "quantity" integer NOT NULL,
+  "product_name" text NOT NULL, -- as reported by InventoryService when the order was placed
+  "unit_price_at_order" REAL NOT NULL,
+  "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (order_id) REFERENCES orders(id) ON DELETE CASCADE,
+  PRIMARY KEY ("order_id", "product_id")
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX payments_order_id_idempotency_key_idx;
+ALTER TABLE payments DROP COLUMN idempotency_key;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+ALTER TABLE payments ADD COLUMN idempotency_key VARCHAR(128);
+
+CREATE UNIQUE INDEX payments_order_id_idempotency_key_idx
+ON payments (order_id, idempotency_key);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE cart_items DROP COLUMN last_seen_unit_price;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE cart_items ADD COLUMN last_seen_unit_price REAL; -- unit price when the item was added, if known
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE payments DROP CONSTRAINT payments_status_check;
+ALTER TABLE orders DROP CONSTRAINT orders_status_check;
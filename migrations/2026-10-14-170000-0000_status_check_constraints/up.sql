@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- Added as NOT VALID first so the check of existing rows runs separately and names the constraint
+-- that failed if any row holds an unknown status.
+ALTER TABLE orders ADD CONSTRAINT orders_status_check CHECK (
+  status IN (
+    'PENDING', 'RESERVED', 'RESERVE_TIMEOUT', 'REJECTED', 'RETRYABLE', 'PAYMENT_PENDING',
+    'DELIVERY_PENDING', 'DELIVERED', 'CANCEL_PENDING', 'CANCELLED', 'EXPIRED'
+  )
+) NOT VALID;
+
+ALTER TABLE orders VALIDATE CONSTRAINT orders_status_check;
+
+ALTER TABLE payments ADD CONSTRAINT payments_status_check CHECK (
+  status IN ('PENDING', 'PAID', 'FAILED', 'CANCELLED')
+) NOT VALID;
+
+ALTER TABLE payments VALIDATE CONSTRAINT payments_status_check;
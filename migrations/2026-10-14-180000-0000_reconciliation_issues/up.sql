@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+CREATE TABLE "reconciliation_issues" (
+  "id" serial PRIMARY KEY,
+  "payment_id" UUID, -- NULL when no payment has the reported provider_ref
+  "provider_ref" varchar(128) NOT NULL,
+  "issue" varchar(32) NOT NULL, -- UNKNOWN_PAYMENT, AMOUNT_MISMATCH or STATUS_MISMATCH
+  "recorded_status" varchar(32),
+  "reported_status" varchar(32) NOT NULL,
+  "recorded_amount" REAL,
+  "reported_amount" REAL NOT NULL,
+  "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (payment_id) REFERENCES payments(id) ON DELETE CASCADE
+);
+
+CREATE INDEX reconciliation_issues_payment_id_idx
+ON reconciliation_issues (payment_id);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DELETE FROM carts WHERE patient_id IS NULL;
+DROP INDEX carts_session_token_idx;
+ALTER TABLE carts DROP CONSTRAINT carts_owner_check;
+ALTER TABLE carts DROP COLUMN session_token;
+ALTER TABLE carts ALTER COLUMN patient_id SET NOT NULL;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Guest carts belong to an opaque session token until a patient claims them
+ALTER TABLE carts ALTER COLUMN patient_id DROP NOT NULL;
+ALTER TABLE carts ADD COLUMN session_token UUID;
+ALTER TABLE carts ADD CONSTRAINT carts_owner_check
+CHECK (patient_id IS NOT NULL OR session_token IS NOT NULL);
+
+CREATE UNIQUE INDEX carts_session_token_idx
+ON carts (session_token);
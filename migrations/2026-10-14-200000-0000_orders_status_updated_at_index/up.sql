@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Lets the reservation timeout sweep find the oldest PENDING orders without scanning the table.
+-- Unpaid reservations are found through orders_status_reserved_until_idx.
+CREATE INDEX orders_status_updated_at_idx
+ON orders (status, updated_at)
+WHERE deleted_at IS NULL;
@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE payments DROP CONSTRAINT payments_status_check;
+UPDATE payments SET status = 'PAID' WHERE status IN ('PARTIALLY_REFUNDED', 'REFUNDED');
+ALTER TABLE payments ADD CONSTRAINT payments_status_check CHECK (
+  status IN ('PENDING', 'PAID', 'FAILED', 'CANCELLED')
+);
+
+ALTER TABLE payments DROP CONSTRAINT payments_refunded_amount_check;
+ALTER TABLE payments DROP COLUMN refunded_amount;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+ALTER TABLE payments ADD COLUMN refunded_amount REAL NOT NULL DEFAULT 0; -- total refunded so far
+
+ALTER TABLE payments ADD CONSTRAINT payments_refunded_amount_check CHECK (
+  refunded_amount >= 0 AND refunded_amount <= amount
+);
+
+ALTER TABLE payments DROP CONSTRAINT payments_status_check;
+ALTER TABLE payments ADD CONSTRAINT payments_status_check CHECK (
+  status IN ('PENDING', 'PAID', 'FAILED', 'CANCELLED', 'PARTIALLY_REFUNDED', 'REFUNDED')
+);
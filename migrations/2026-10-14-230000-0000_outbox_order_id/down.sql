@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER set_outbox_order_id ON outbox;
+DROP FUNCTION outbox_set_order_id();
+DROP FUNCTION outbox_payload_order_id(TEXT);
+ALTER TABLE "outbox" DROP COLUMN "order_id";
@@ -0,0 +1,36 @@
+-- Your SQL goes here
+
+ALTER TABLE "outbox" ADD COLUMN "order_id" INTEGER; -- order the event is about, if any
+
+-- Events are published through medbook_core, which only knows the payload, so the column is
+-- derived from the top-level order_id every order event carries.
+CREATE FUNCTION outbox_payload_order_id(payload TEXT) RETURNS INTEGER AS $$
+BEGIN
+  RETURN (payload::jsonb ->> 'order_id')::integer;
+EXCEPTION WHEN others THEN
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+CREATE FUNCTION outbox_set_order_id() RETURNS trigger AS $$
+BEGIN
+  IF NEW.order_id IS NULL THEN
+    NEW.order_id := outbox_payload_order_id(NEW.payload);
+  END IF;
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER set_outbox_order_id
+BEFORE INSERT ON outbox
+FOR EACH ROW
+EXECUTE FUNCTION outbox_set_order_id();
+
+-- Backfilling is not a change of the events, so it leaves updated_at alone.
+ALTER TABLE outbox DISABLE TRIGGER update_outbox_timestamp;
+UPDATE outbox SET order_id = outbox_payload_order_id(payload);
+ALTER TABLE outbox ENABLE TRIGGER update_outbox_timestamp;
+
+CREATE INDEX outbox_order_id_idx
+ON outbox (order_id, created_at)
+WHERE order_id IS NOT NULL;
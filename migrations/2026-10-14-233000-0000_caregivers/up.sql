@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+CREATE TABLE "caregivers" (
+  "caregiver_id" INTEGER NOT NULL, -- patient managing orders on behalf of patient_id
+  "patient_id" INTEGER NOT NULL,
+  "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (caregiver_id, patient_id),
+  CHECK (caregiver_id <> patient_id)
+);
+
+CREATE INDEX caregivers_patient_id_idx
+ON caregivers (patient_id);
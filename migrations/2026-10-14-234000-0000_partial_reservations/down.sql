@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE orders DROP CONSTRAINT orders_status_check;
+UPDATE orders SET status = 'RESERVED' WHERE status = 'PARTIALLY_RESERVED';
+ALTER TABLE orders ADD CONSTRAINT orders_status_check CHECK (
+  status IN (
+    'PENDING', 'RESERVED', 'RESERVE_TIMEOUT', 'REJECTED', 'RETRYABLE', 'PAYMENT_PENDING',
+    'DELIVERY_PENDING', 'DELIVERED', 'CANCEL_PENDING', 'CANCELLED', 'EXPIRED'
+  )
+);
+
+ALTER TABLE order_items DROP CONSTRAINT order_items_backordered_quantity_check;
+ALTER TABLE order_items DROP COLUMN backordered_quantity;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+ALTER TABLE order_items ADD COLUMN backordered_quantity INTEGER NOT NULL DEFAULT 0; -- units InventoryService could not reserve
+
+ALTER TABLE order_items ADD CONSTRAINT order_items_backordered_quantity_check CHECK (
+  backordered_quantity >= 0 AND backordered_quantity <= quantity
+);
+
+ALTER TABLE orders DROP CONSTRAINT orders_status_check;
+ALTER TABLE orders ADD CONSTRAINT orders_status_check CHECK (
+  status IN (
+    'PENDING', 'RESERVED', 'PARTIALLY_RESERVED', 'RESERVE_TIMEOUT', 'REJECTED', 'RETRYABLE',
+    'PAYMENT_PENDING', 'DELIVERY_PENDING', 'DELIVERED', 'CANCEL_PENDING', 'CANCELLED', 'EXPIRED'
+  )
+);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Lets the outbox stats find the last relayed and oldest pending events without scanning the table.
+CREATE INDEX outbox_status_updated_at_idx
+ON outbox (status, updated_at);
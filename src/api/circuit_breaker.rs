@@ -0,0 +1,182 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use medbook_core::app_error::AppError;
+
+use crate::settings::Settings;
+
+/// Breaker guarding calls to InventoryService.
+pub static INVENTORY_SERVICE: CircuitBreaker = CircuitBreaker::new("InventoryService");
+/// Breaker guarding calls to DeliveryService.
+pub static DELIVERY_SERVICE: CircuitBreaker = CircuitBreaker::new("DeliveryService");
+/// Every breaker, for reporting.
+pub static BREAKERS: [&CircuitBreaker; 2] = [&INVENTORY_SERVICE, &DELIVERY_SERVICE];
+
+/// Externally visible state of a [`CircuitBreaker`]. The discriminant is the value reported in
+/// metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through
+    Closed = 0,
+    /// Calls fail fast until the cooldown is over
+    Open = 1,
+    /// A single probe call is deciding whether to close again
+    HalfOpen = 2,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probe_expires: Instant },
+}
+
+/// Stops calling an upstream service after [`Settings::get_circuit_breaker_failure_threshold`]
+/// consecutive failures.
+///
+/// Once open, calls fail fast with `ServiceUnreachable` for
+/// [`Settings::get_circuit_breaker_cooldown_secs`]. The first call afterwards probes the service
+/// and closes the breaker again if it succeeds. A probe that never reports back is replaced by
+/// another one after the same cooldown.
+pub struct CircuitBreaker {
+    service: &'static str,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub const fn new(service: &'static str) -> Self {
+        Self {
+            service,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn service(&self) -> &'static str {
+        self.service
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.lock() {
+            BreakerState::Closed { .. } => CircuitState::Closed,
+            BreakerState::Open { .. } => CircuitState::Open,
+            BreakerState::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Decides whether a call may be made now. Must be followed by [`CircuitBreaker::record`] when
+    /// the call is made.
+    pub fn check(&self) -> Result<(), AppError> {
+        let mut state = self.lock();
+        let now = Instant::now();
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until }
+            | BreakerState::HalfOpen {
+                probe_expires: until,
+            } if now < until => Err(AppError::ServiceUnreachable(self.service.into())),
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                tracing::info!("Probing {} after circuit breaker cooldown", self.service);
+                *state = BreakerState::HalfOpen {
+                    probe_expires: now + cooldown(),
+                };
+                Ok(())
+            }
+        }
+    }
+
+    /// Records the outcome of a call allowed by [`CircuitBreaker::check`].
+    pub fn record(&self, success: bool) {
+        let mut state = self.lock();
+        *state = match (*state, success) {
+            (_, true) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, false)
+                if failures + 1 < Settings::get_circuit_breaker_failure_threshold() =>
+            {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, false) => {
+                if !matches!(*state, BreakerState::Open { .. }) {
+                    tracing::warn!("Circuit breaker for {} opened", self.service);
+                }
+                BreakerState::Open {
+                    until: Instant::now() + cooldown(),
+                }
+            }
+        };
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn cooldown() -> Duration {
+    Duration::from_secs(Settings::get_circuit_breaker_cooldown_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fail_until_open(breaker: &CircuitBreaker) {
+        for _ in 0..Settings::get_circuit_breaker_failure_threshold() {
+            assert!(breaker.check().is_ok());
+            breaker.record(false);
+        }
+    }
+
+    #[test]
+    fn breaker_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new("TestService");
+        fail_until_open(&breaker);
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(
+            breaker.check(),
+            Err(AppError::ServiceUnreachable(_))
+        ));
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let breaker = CircuitBreaker::new("TestService");
+        for _ in 1..Settings::get_circuit_breaker_failure_threshold() {
+            breaker.record(false);
+        }
+        breaker.record(true);
+        breaker.record(false);
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn breaker_lets_a_single_probe_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new("TestService");
+        fail_until_open(&breaker);
+        *breaker.lock() = BreakerState::Open {
+            until: Instant::now(),
+        };
+
+        assert!(breaker.check().is_ok());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.check().is_err());
+
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn failed_probe_opens_the_breaker_again() {
+        let breaker = CircuitBreaker::new("TestService");
+        *breaker.lock() = BreakerState::HalfOpen {
+            probe_expires: Instant::now() + cooldown(),
+        };
+        breaker.record(false);
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+}
@@ -1,60 +1,103 @@
-use anyhow::{Context, Result};
+use anyhow::Context;
 use medbook_core::app_error::{AppError, StdResponse};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::api::ApiUrls;
+use crate::api::{self, ApiUrls, circuit_breaker};
 
-#[derive(Serialize, Deserialize)]
-struct DeliveryAddress {
-    patient_id: i32,
+/// A delivery address as served by DeliveryService, with the fields orders rely on made mandatory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryAddress {
+    pub id: i32,
+    pub patient_id: i32,
+    pub recipient_name: String,
+    pub phone_number: String,
+    pub address_line: String,
+    pub city: String,
+    pub postal_code: String,
+    /// Any other fields DeliveryService sends, kept so nothing is lost when the address is stored
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
+}
+
+impl DeliveryAddress {
+    /// Parses and checks an address fetched from DeliveryService.
+    fn parse(value: Value) -> Result<Self, AppError> {
+        let address: DeliveryAddress = serde_json::from_value(value)
+            .map_err(|err| AppError::BadRequest(format!("Delivery address is invalid: {}", err)))?;
+
+        let required = [
+            ("recipient_name", &address.recipient_name),
+            ("phone_number", &address.phone_number),
+            ("address_line", &address.address_line),
+            ("city", &address.city),
+            ("postal_code", &address.postal_code),
+        ];
+        if let Some((field, _)) = required.iter().find(|(_, value)| value.trim().is_empty()) {
+            return Err(AppError::BadRequest(format!(
+                "Delivery address is invalid: `{}` must not be empty",
+                field
+            )));
+        }
+
+        Ok(address)
+    }
+
+    /// Converts the address back to JSON for storage in `orders.delivery_address`.
+    pub fn to_value(&self) -> Result<Value, AppError> {
+        Ok(serde_json::to_value(self).context("Failed to serialize delivery address")?)
+    }
 }
 
-pub async fn get_delivery_address_as_value(client: Client, id: i32) -> Result<Value> {
+/// Fetches a delivery address, telling a missing address apart from DeliveryService being down.
+async fn fetch_delivery_address(client: Client, id: i32) -> Result<Value, AppError> {
     let url = ApiUrls::get_delivery_service_url();
-    let delivery_address: StdResponse<Value, String> = client
-        .get(format!("{}/delivery-addresses/{}", url, id))
-        .send()
-        .await
-        .map_err(|_| AppError::ServiceUnreachable("DeliveryService".into()))?
-        .json()
-        .await
-        .context("Failed to parse JSON")?;
-
-    match delivery_address.data {
-        Some(delivery_address) => Ok(delivery_address),
-        None => Err(anyhow::anyhow!("Delivery address not found")),
+    circuit_breaker::DELIVERY_SERVICE.check()?;
+    let response = api::send(
+        "DeliveryService",
+        client.get(format!("{}/delivery-addresses/{}", url, id)),
+    )
+    .await;
+    circuit_breaker::DELIVERY_SERVICE.record(
+        response
+            .as_ref()
+            .is_ok_and(|response| !response.status().is_server_error()),
+    );
+    let response = response.map_err(|_| AppError::ServiceUnreachable("DeliveryService".into()))?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND => return Err(AppError::NotFound),
+        StatusCode::FORBIDDEN => return Err(AppError::ForbiddenResource),
+        status if status.is_server_error() => {
+            return Err(AppError::ServiceUnreachable("DeliveryService".into()));
+        }
+        status if !status.is_success() => {
+            return Err(AppError::Other(anyhow::anyhow!(
+                "DeliveryService responded with {}",
+                status
+            )));
+        }
+        _ => {}
     }
+
+    let delivery_address: StdResponse<Value, String> =
+        response.json().await.context("Failed to parse JSON")?;
+
+    delivery_address.data.ok_or(AppError::NotFound)
 }
 
-pub async fn get_delivery_address_as_value_with_ownership_check(
+/// Fetches a delivery address and checks that it is complete and belongs to `patient_id`.
+pub async fn get_delivery_address_with_ownership_check(
     client: Client,
     id: i32,
     patient_id: i32,
-) -> Result<Value> {
-    let url = ApiUrls::get_delivery_service_url();
-    let delivery_address: StdResponse<Value, String> = client
-        .get(format!("{}/delivery-addresses/{}", url, id))
-        .send()
-        .await
-        .map_err(|_| AppError::ServiceUnreachable("DeliveryService".into()))?
-        .json()
-        .await
-        .context("Failed to parse JSON")?;
-
-    match delivery_address.data {
-        Some(delivery_address) => {
-            let delivery_address_with_patient_id: DeliveryAddress =
-                serde_json::from_value(delivery_address.clone())
-                    .context("Failed to deserialize delivery address")?;
-
-            if delivery_address_with_patient_id.patient_id != patient_id {
-                return Err(anyhow::anyhow!("Delivery address patient IDs do not match"));
-            }
-
-            Ok(delivery_address)
-        }
-        None => Err(anyhow::anyhow!("Delivery address not found")),
+) -> Result<DeliveryAddress, AppError> {
+    let delivery_address = DeliveryAddress::parse(fetch_delivery_address(client, id).await?)?;
+
+    if delivery_address.patient_id != patient_id {
+        return Err(AppError::ForbiddenResource);
     }
+
+    Ok(delivery_address)
 }
@@ -1,3 +1,11 @@
+use std::time::Instant;
+
+use reqwest::{RequestBuilder, Response};
+use tracing::{Instrument, field};
+
+use crate::middleware::{REQUEST_ID_HEADER, current_request_id};
+
+pub mod circuit_breaker;
 pub mod deliveries;
 pub mod products;
 
@@ -24,3 +32,40 @@ impl ApiUrls {
             .unwrap_or("http://localhost:3000/inventory-service".to_string())
     }
 }
+
+/// Sends a request to `service` inside an `upstream_request` span recording the URL, the response
+/// status and how long the call took, or the error it failed with. The ID of the request being
+/// handled is forwarded in `X-Request-Id` so upstream logs can be correlated.
+pub async fn send(service: &'static str, request: RequestBuilder) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    if let Some(request_id) = current_request_id().and_then(|id| id.parse().ok()) {
+        request.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+
+    let span = tracing::info_span!(
+        "upstream_request",
+        service,
+        method = %request.method(),
+        url = %request.url(),
+        status = field::Empty,
+        duration_ms = field::Empty,
+        error = field::Empty,
+    );
+    let started_at = Instant::now();
+    let response = client.execute(request).instrument(span.clone()).await;
+    span.record("duration_ms", started_at.elapsed().as_millis() as u64);
+
+    span.in_scope(|| match &response {
+        Ok(response) => {
+            span.record("status", response.status().as_u16());
+            tracing::debug!("{} responded with {}", service, response.status());
+        }
+        Err(err) => {
+            span.record("error", field::display(err));
+            tracing::warn!("Request to {} failed: {}", service, err);
+        }
+    });
+
+    response
+}
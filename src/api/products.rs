@@ -1,43 +1,295 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{
+        LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use medbook_core::app_error::{AppError, StdResponse};
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::sync::{Semaphore, SemaphorePermit};
 use utoipa::ToSchema;
 
-use crate::api::ApiUrls;
+use crate::{
+    api::{self, ApiUrls, circuit_breaker},
+    settings::Settings,
+};
 
-#[derive(Serialize, Deserialize, ToSchema)]
-struct Product {
+/// Maximum number of product IDs sent to InventoryService in a single request.
+const PRODUCTS_BATCH_SIZE: usize = 100;
+
+/// Caps the requests in flight to InventoryService at
+/// [`Settings::get_inventory_max_concurrent_requests`], so our own spikes don't pile onto it.
+static INVENTORY_PERMITS: LazyLock<Semaphore> =
+    LazyLock::new(|| Semaphore::new(Settings::get_inventory_max_concurrent_requests()));
+/// Requests currently waiting for one of the [`INVENTORY_PERMITS`].
+static QUEUED_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// A request counted in [`QUEUED_REQUESTS`] until it is dropped, so requests whose future is
+/// dropped while waiting, e.g. when the client disconnects, leave the queue too.
+struct QueuedRequest;
+
+impl QueuedRequest {
+    /// Joins the queue, returning how many requests were queued already.
+    fn join() -> (Self, usize) {
+        (Self, QUEUED_REQUESTS.fetch_add(1, Ordering::SeqCst))
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        QUEUED_REQUESTS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits for a slot to call InventoryService. Fails with `ServiceUnreachable` without waiting when
+/// [`Settings::get_inventory_max_queued_requests`] requests are queued already, or after
+/// [`Settings::get_inventory_queue_timeout_ms`].
+async fn inventory_permit() -> Result<SemaphorePermit<'static>, AppError> {
+    let unreachable = || AppError::ServiceUnreachable("InventoryService".into());
+    if let Ok(permit) = INVENTORY_PERMITS.try_acquire() {
+        return Ok(permit);
+    }
+
+    let max_queued = Settings::get_inventory_max_queued_requests();
+    let (queued, already_queued) = QueuedRequest::join();
+    if already_queued >= max_queued {
+        tracing::warn!(
+            "Shedding InventoryService request, {} already queued",
+            max_queued
+        );
+        return Err(unreachable());
+    }
+
+    let timeout = Duration::from_millis(Settings::get_inventory_queue_timeout_ms());
+    let permit = tokio::time::timeout(timeout, INVENTORY_PERMITS.acquire()).await;
+    drop(queued);
+    match permit {
+        Ok(Ok(permit)) => Ok(permit),
+        Ok(Err(_)) => Err(unreachable()),
+        Err(_) => {
+            tracing::warn!(
+                "Timed out after {:?} waiting for an InventoryService slot",
+                timeout
+            );
+            Err(unreachable())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct ProductDetails {
     pub id: i32,
-    pub unit_price: f32,
+    /// Display name, empty if InventoryService does not report one
+    #[serde(default)]
+    pub name: String,
+    /// `None` for products InventoryService has no usable price for, which cannot be ordered
+    #[serde(default, deserialize_with = "lenient_price")]
+    pub unit_price: Option<f32>,
+    /// Quantity currently in stock, if InventoryService reports it
+    #[serde(default)]
+    pub available_quantity: Option<i32>,
 }
 
-pub async fn get_product_unit_prices(client: Client, ids: Vec<i32>) -> Result<HashMap<i32, f32>> {
+impl ProductDetails {
+    /// Whether `quantity` units can currently be supplied. Products without a reported stock level
+    /// are assumed to be available, products without a price never are.
+    pub fn can_supply(&self, quantity: i32) -> bool {
+        self.unit_price.is_some()
+            && self
+                .available_quantity
+                .is_none_or(|available| available >= quantity)
+    }
+}
+
+/// Reads a price sent as a number, a numeric string or `null`. Anything else, and negative or
+/// non-finite amounts, count as no price so a single misconfigured product doesn't fail the lookup
+/// of every other one.
+fn lenient_price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawPrice {
+        Number(f32),
+        Text(String),
+        Other(serde_json::Value),
+    }
+
+    let price = match Option::<RawPrice>::deserialize(deserializer)? {
+        Some(RawPrice::Number(price)) => Some(price),
+        Some(RawPrice::Text(price)) => price.trim().parse().ok(),
+        Some(RawPrice::Other(_)) | None => None,
+    };
+    Ok(price.filter(|price| price.is_finite() && *price >= 0.0))
+}
+
+/// Fetches details of the given products, deduplicating IDs and splitting them into batches of
+/// [`PRODUCTS_BATCH_SIZE`]. Unknown products are left out of the result, unpriced ones are kept
+/// with no `unit_price`.
+///
+/// List responses collect the product IDs of all their items and call this once, so each product is
+/// fetched a single time per request however many orders or carts share it.
+pub async fn get_products(client: Client, ids: Vec<i32>) -> Result<HashMap<i32, ProductDetails>> {
+    let mut products = HashMap::with_capacity(ids.len());
+    for batch in product_batches(ids) {
+        products.extend(
+            get_products_batch(client.clone(), &batch)
+                .await?
+                .into_iter()
+                .map(|p| (p.id, p)),
+        );
+    }
+
+    Ok(products)
+}
+
+/// Splits `ids` into the deduplicated batches [`get_products`] sends, one request each.
+fn product_batches(mut ids: Vec<i32>) -> Vec<Vec<i32>> {
+    ids.sort_unstable();
+    ids.dedup();
+    ids.chunks(PRODUCTS_BATCH_SIZE)
+        .map(<[i32]>::to_vec)
+        .collect()
+}
+
+async fn get_products_batch(client: Client, ids: &[i32]) -> Result<Vec<ProductDetails>> {
     let url = ApiUrls::get_inventory_service_url();
     let ids_query = ids
-        .into_iter()
+        .iter()
         .map(|id| id.to_string())
         .collect::<Vec<_>>()
         .join(",");
 
-    let products: StdResponse<Vec<Product>, String> = client
-        .get(format!("{}/products", url))
-        .query(&[("ids", ids_query)])
-        .send()
-        .await
+    let _permit = inventory_permit().await?;
+    circuit_breaker::INVENTORY_SERVICE.check()?;
+    // Only the call itself is bounded here, the wait for a slot has its own timeout.
+    let response = api::send(
+        "InventoryService",
+        client
+            .get(format!("{}/products", url))
+            .query(&[("ids", ids_query)])
+            .timeout(Duration::from_millis(
+                Settings::get_inventory_request_timeout_ms(),
+            )),
+    )
+    .await;
+    circuit_breaker::INVENTORY_SERVICE.record(
+        response
+            .as_ref()
+            .is_ok_and(|response| !response.status().is_server_error()),
+    );
+
+    let products: StdResponse<Vec<ProductDetails>, String> = response
         .map_err(|_| AppError::ServiceUnreachable("InventoryService".into()))?
         .json()
         .await
         .context("Failed to parse JSON")?;
 
     match products.data {
-        Some(products) => {
-            let unit_prices: HashMap<i32, f32> =
-                products.into_iter().map(|p| (p.id, p.unit_price)).collect();
-            Ok(unit_prices)
-        }
+        Some(products) => Ok(products),
         None => Err(anyhow::anyhow!("Products not found")),
     }
 }
+
+/// Current unit prices of the given products. Unknown and unpriced products are left out.
+pub async fn get_product_unit_prices(client: Client, ids: Vec<i32>) -> Result<HashMap<i32, f32>> {
+    let products = get_products(client, ids).await?;
+    Ok(products
+        .into_values()
+        .filter_map(|p| Some((p.id, p.unit_price?)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn products_shared_by_several_orders_are_requested_once() {
+        // Product IDs as collected across the items of three orders.
+        let ids = vec![3, 1, 2, 1, 3, 3, 2];
+
+        assert_eq!(product_batches(ids), vec![vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn large_lookups_are_split_into_batches() {
+        let ids: Vec<i32> = (1..=250).chain(1..=250).collect();
+        let batches = product_batches(ids);
+
+        assert_eq!(
+            batches.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![PRODUCTS_BATCH_SIZE, PRODUCTS_BATCH_SIZE, 50]
+        );
+    }
+
+    #[test]
+    fn empty_lookups_send_no_request() {
+        assert!(product_batches(Vec::new()).is_empty());
+    }
+
+    fn unit_price(json: &str) -> Option<f32> {
+        serde_json::from_str::<ProductDetails>(json)
+            .unwrap()
+            .unit_price
+    }
+
+    #[test]
+    fn numeric_prices_are_read_as_is() {
+        assert_eq!(unit_price(r#"{"id": 1, "unit_price": 12.5}"#), Some(12.5));
+    }
+
+    #[test]
+    fn numeric_string_prices_are_parsed() {
+        assert_eq!(
+            unit_price(r#"{"id": 1, "unit_price": "12.50"}"#),
+            Some(12.5)
+        );
+        assert_eq!(unit_price(r#"{"id": 1, "unit_price": " 3 "}"#), Some(3.0));
+    }
+
+    #[test]
+    fn null_or_missing_prices_mean_no_price() {
+        assert_eq!(unit_price(r#"{"id": 1, "unit_price": null}"#), None);
+        assert_eq!(unit_price(r#"{"id": 1}"#), None);
+    }
+
+    #[test]
+    fn unusable_prices_mean_no_price() {
+        for price in [r#""free""#, "-1", "true", r#"{"amount": 1}"#] {
+            let json = format!(r#"{{"id": 1, "unit_price": {}}}"#, price);
+            assert_eq!(unit_price(&json), None, "unit_price {}", price);
+        }
+    }
+
+    #[test]
+    fn unpriced_products_cannot_be_supplied() {
+        let product: ProductDetails =
+            serde_json::from_str(r#"{"id": 1, "unit_price": null, "available_quantity": 5}"#)
+                .unwrap();
+
+        assert!(!product.can_supply(1));
+    }
+
+    #[tokio::test]
+    async fn dropped_waiters_leave_the_queue() {
+        let busy = INVENTORY_PERMITS
+            .acquire_many(Settings::get_inventory_max_concurrent_requests() as u32)
+            .await
+            .unwrap();
+        let queued_before = QUEUED_REQUESTS.load(Ordering::SeqCst);
+
+        let waiter = tokio::spawn(inventory_permit());
+        while QUEUED_REQUESTS.load(Ordering::SeqCst) == queued_before {
+            tokio::task::yield_now().await;
+        }
+        waiter.abort();
+        let _ = waiter.await;
+
+        assert_eq!(QUEUED_REQUESTS.load(Ordering::SeqCst), queued_before);
+        drop(busy);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper, dsl::sum};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use medbook_core::{app_error::AppError, outbox};
+use reqwest::Client;
+
+use crate::{
+    events::{DeliveryOrderRequestEvent, PatientFlaggedForReviewEvent},
+    models::{OrderEntity, OrderItemEntity},
+    order_items, order_status, routing_keys,
+    schema::{orders, payments},
+    settings::Settings,
+};
+
+/// Amounts closer than this are considered equal, absorbing `f32` rounding.
+pub const AMOUNT_EPSILON: f32 = 0.005;
+
+/// Statuses of payments that were paid. What was not refunded of a PARTIALLY_REFUNDED payment still
+/// counts as paid.
+pub const PAID_PAYMENT_STATUSES: &[&str] = &["PAID", "PARTIALLY_REFUNDED"];
+
+/// Sums the line totals of an order's items.
+pub fn items_total(order_items: &[OrderItemEntity]) -> f32 {
+    order_items.iter().map(OrderItemEntity::line_total).sum()
+}
+
+/// Prices an order at the unit prices recorded when it was placed.
+pub async fn order_total(
+    conn: &mut AsyncPgConnection,
+    client: Client,
+    order: &OrderEntity,
+) -> Result<f32> {
+    let order_items = order_items::load(conn, client, std::slice::from_ref(order))
+        .await?
+        .remove(&order.id)
+        .unwrap_or_default();
+
+    Ok(items_total(&order_items))
+}
+
+/// Rejects an order of `patient_id` priced above [`Settings::get_max_order_total`], flagging the
+/// patient for review if [`Settings::get_flag_orders_over_max_total`] is on.
+///
+/// Call it outside of a transaction, which the rejection would roll back together with the flag.
+pub async fn enforce_max_order_total(
+    conn: &mut AsyncPgConnection,
+    patient_id: i32,
+    order_id: Option<i32>,
+    total_price: f32,
+    currency: &str,
+) -> Result<(), AppError> {
+    let Err(rejection) = check_max_order_total(patient_id, total_price, currency) else {
+        return Ok(());
+    };
+
+    let max_order_total = Settings::get_max_order_total(patient_id);
+    tracing::warn!(
+        patient_id,
+        order_id,
+        total_price,
+        max_order_total,
+        "Rejected order above the maximum order total"
+    );
+    if Settings::get_flag_orders_over_max_total() {
+        outbox::publish(
+            conn,
+            routing_keys::PATIENT_FLAGGED_FOR_REVIEW.into(),
+            PatientFlaggedForReviewEvent {
+                patient_id,
+                order_id,
+                total_price,
+                max_order_total,
+                currency: currency.into(),
+                timestamp: Utc::now(),
+            },
+        )
+        .await
+        .context("Failed to send outbox")?;
+    }
+
+    Err(rejection)
+}
+
+/// Rejects a total of `patient_id` above [`Settings::get_max_order_total`] like
+/// [`enforce_max_order_total`] does, but without logging or flagging anything, e.g. for previews.
+pub fn check_max_order_total(
+    patient_id: i32,
+    total_price: f32,
+    currency: &str,
+) -> Result<(), AppError> {
+    let max_order_total = Settings::get_max_order_total(patient_id);
+    if total_price <= max_order_total + AMOUNT_EPSILON {
+        return Ok(());
+    }
+
+    Err(AppError::BadRequest(format!(
+        "Order total of {:.2} {} exceeds the maximum of {:.2} {}",
+        total_price, currency, max_order_total, currency
+    )))
+}
+
+/// Sums the amounts of an order's payments in any of `statuses`, less what was refunded of them.
+pub async fn payments_sum(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    statuses: &[&str],
+) -> Result<f32> {
+    let total: Option<f32> = payments::table
+        .filter(payments::order_id.eq(order_id))
+        .filter(payments::status.eq_any(statuses))
+        .select(sum(payments::amount - payments::refunded_amount))
+        .get_result(conn)
+        .await
+        .context("Failed to sum order payments")?;
+
+    Ok(total.unwrap_or(0.0))
+}
+
+/// Sums what a patient paid across all their orders, less refunds, keyed by ISO 4217 currency.
+pub async fn patient_total_spent(
+    conn: &mut AsyncPgConnection,
+    patient_id: i32,
+) -> Result<HashMap<String, f32>> {
+    let totals = payments::table
+        .inner_join(orders::table)
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(payments::status.eq_any(PAID_PAYMENT_STATUSES))
+        .group_by(payments::currency)
+        .select((
+            payments::currency,
+            sum(payments::amount - payments::refunded_amount),
+        ))
+        .get_results::<(String, Option<f32>)>(conn)
+        .await
+        .context("Failed to sum paid payments")?
+        .into_iter()
+        .map(|(currency, total)| (currency, total.unwrap_or(0.0)))
+        .collect();
+
+    Ok(totals)
+}
+
+/// Hands a PAYMENT_PENDING order over to DeliveryService once its paid payments cover
+/// `total_price`. Call it in the transaction that marked one of the order's payments as paid.
+///
+/// Returns the order as it is afterwards and what is left to pay on it.
+pub async fn dispatch_if_paid(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    total_price: f32,
+) -> Result<(OrderEntity, f32)> {
+    let paid = payments_sum(conn, order_id, PAID_PAYMENT_STATUSES).await?;
+    let outstanding_balance = (total_price - paid).max(0.0);
+
+    let dispatched = if outstanding_balance > AMOUNT_EPSILON {
+        None
+    } else {
+        diesel::update(
+            orders::table
+                .find(order_id)
+                .filter(orders::status.eq("PAYMENT_PENDING")),
+        )
+        .set(orders::status.eq("DELIVERY_PENDING"))
+        .returning(OrderEntity::as_returning())
+        .get_result(conn)
+        .await
+        .optional()
+        .context("Failed to update order status")?
+    };
+
+    let Some(order) = dispatched else {
+        let current_order = orders::table
+            .find(order_id)
+            .get_result(conn)
+            .await
+            .context("Failed to get order")?;
+        return Ok((current_order, outstanding_balance));
+    };
+
+    order_status::status_changed(conn, Some("PAYMENT_PENDING"), &order).await?;
+    publish_delivery_request(conn, &order).await?;
+
+    Ok((order, outstanding_balance))
+}
+
+/// Asks DeliveryService to deliver `order` to its stored address.
+pub async fn publish_delivery_request(
+    conn: &mut AsyncPgConnection,
+    order: &OrderEntity,
+) -> Result<()> {
+    outbox::publish(
+        conn,
+        routing_keys::DELIVERY_ORDER_REQUEST.into(),
+        DeliveryOrderRequestEvent {
+            delivery_address: order.delivery_address.clone(),
+            order_id: order.id,
+            order_type: order.order_type.parse()?,
+            notes: order.notes.clone(),
+            currency: order.currency.clone(),
+            scheduled_for: order.scheduled_for,
+        },
+    )
+    .await
+    .context("Failed to send outbox")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::schema::carts;
+
+    fn item(product_id: i32, quantity: i32, unit_price_at_order: f32) -> OrderItemEntity {
+        OrderItemEntity {
+            order_id: 1,
+            product_id,
+            quantity,
+            product_name: format!("Product {}", product_id),
+            unit_price_at_order,
+            created_at: Utc::now(),
+            backordered_quantity: 0,
+        }
+    }
+
+    #[test]
+    fn line_total_multiplies_unit_price_by_quantity() {
+        assert_eq!(item(1, 3, 12.5).line_total(), 37.5);
+    }
+
+    #[test]
+    fn items_total_weights_each_line_by_its_quantity() {
+        let order_items = [item(1, 3, 12.5), item(2, 1, 4.0)];
+
+        assert_eq!(items_total(&order_items), 41.5);
+    }
+
+    #[test]
+    fn items_total_of_no_items_is_zero() {
+        assert_eq!(items_total(&[]), 0.0);
+    }
+
+    #[test]
+    fn multi_quantity_orders_are_charged_every_unit() {
+        let order_items = [item(1, 3, 19.99), item(2, 2, 0.5)];
+
+        assert!((items_total(&order_items) - 60.97).abs() < AMOUNT_EPSILON);
+    }
+
+    #[test]
+    fn totals_up_to_the_maximum_pass_the_check() {
+        let max_order_total = Settings::get_max_order_total(1);
+        assert!(check_max_order_total(1, max_order_total, "THB").is_ok());
+    }
+
+    #[test]
+    fn totals_above_the_maximum_fail_the_check() {
+        let max_order_total = Settings::get_max_order_total(1);
+        assert!(matches!(
+            check_max_order_total(1, max_order_total + 1.0, "THB"),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn total_spent_leaves_out_refunds_and_unpaid_payments() {
+        let conn = &mut crate::db::tests::connect_rolled_back().await;
+        let patient_id = -1112;
+        let cart_id: i32 = diesel::insert_into(carts::table)
+            .values(carts::patient_id.eq(patient_id))
+            .returning(carts::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        let order_id: i32 = diesel::insert_into(orders::table)
+            .values((
+                orders::cart_id.eq(cart_id),
+                orders::patient_id.eq(patient_id),
+            ))
+            .returning(orders::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        for (amount, refunded_amount, status) in [
+            (10.0_f32, 0.0_f32, "PAID"),
+            (8.0, 3.0, "PARTIALLY_REFUNDED"),
+            (4.0, 4.0, "REFUNDED"),
+            (20.0, 0.0, "PENDING"),
+        ] {
+            diesel::insert_into(payments::table)
+                .values((
+                    payments::order_id.eq(order_id),
+                    payments::amount.eq(amount),
+                    payments::refunded_amount.eq(refunded_amount),
+                    payments::status.eq(status),
+                    payments::currency.eq("THB"),
+                ))
+                .execute(conn)
+                .await
+                .unwrap();
+        }
+
+        let totals = patient_total_spent(conn, patient_id).await.unwrap();
+
+        assert_eq!(totals.len(), 1);
+        assert!((totals["THB"] - 15.0).abs() < AMOUNT_EPSILON);
+    }
+}
@@ -1 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, LazyLock, Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use lapin::{message::Delivery, options::BasicNackOptions};
+use medbook_core::app_state::AppState;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::settings::Settings;
+
 pub mod orders;
+pub mod payments;
+
+/// Signature shared by every consumer handler.
+pub type Handler = fn(Delivery, Arc<AppState>) -> BoxFuture<'static, Result<()>>;
+
+/// Registers a consumer handler behind [`supervise`], as a `(routing_key, handler)` pair.
+#[macro_export]
+macro_rules! supervised {
+    ($routing_key:expr, $handler:path) => {{
+        fn supervised(
+            delivery: ::lapin::message::Delivery,
+            state: ::std::sync::Arc<::medbook_core::app_state::AppState>,
+        ) -> ::futures::future::BoxFuture<'static, ::anyhow::Result<()>> {
+            ::std::boxed::Box::pin($crate::consumers::supervise(
+                $routing_key,
+                $handler,
+                delivery,
+                state,
+            ))
+        }
+        ($routing_key, supervised)
+    }};
+}
+
+/// Handlers that ran out of time, per routing key, for metrics.
+static TIMED_OUT_HANDLERS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+/// Messages whose payload did not match the expected event, per routing key, for metrics.
+static UNPARSEABLE_MESSAGES: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+/// Longest part of an unparseable payload that is logged.
+const PAYLOAD_SAMPLE_BYTES: usize = 512;
+
+/// Whether the consumers are running on a broker connection, for readiness.
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Shared by every consumer so a burst of events can't check out more DB connections than
+/// [`Settings::get_max_concurrent_consumers`], leaving the rest of the pool to the HTTP side.
+///
+/// This does not limit prefetch. The channel is opened by `medbook_core`'s `bootstrap`, which sets
+/// no QoS and doesn't expose it, so deliveries beyond the permits wait unacked in memory.
+static HANDLER_PERMITS: LazyLock<Semaphore> =
+    LazyLock::new(|| Semaphore::new(Settings::get_max_concurrent_consumers()));
+
+/// Waits until another consumer handler may run. Hold the permit until the message is acked.
+async fn handler_permit() -> Result<SemaphorePermit<'static>> {
+    HANDLER_PERMITS
+        .acquire()
+        .await
+        .context("Consumer handler semaphore was closed")
+}
+
+/// Runs `handler` for a message, giving up after [`Settings::get_consumer_handler_timeout_secs`].
+///
+/// A handler that runs out of time is dropped, rolling back its transaction, and the message is
+/// requeued so another replica can pick it up instead of the queue stalling behind it. Payloads a
+/// handler fails to parse are counted and logged, so contract drift with the publishing service
+/// shows up in metrics.
+pub async fn supervise(
+    routing_key: &'static str,
+    handler: Handler,
+    delivery: Delivery,
+    state: Arc<AppState>,
+) -> Result<()> {
+    let timeout = Duration::from_secs(Settings::get_consumer_handler_timeout_secs());
+    let unacked = delivery.clone();
+    match tokio::time::timeout(timeout, handler(delivery, state)).await {
+        Ok(Err(err)) if is_parse_error(&err) => {
+            let sample = &unacked.data[..unacked.data.len().min(PAYLOAD_SAMPLE_BYTES)];
+            tracing::error!(
+                routing_key,
+                delivery_tag = unacked.delivery_tag,
+                payload_bytes = unacked.data.len(),
+                payload_sample = %String::from_utf8_lossy(sample),
+                "Consumer received a payload it could not parse: {:?}",
+                err
+            );
+            *UNPARSEABLE_MESSAGES
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(routing_key)
+                .or_default() += 1;
+            Err(err)
+        }
+        Ok(result) => result,
+        Err(_) => {
+            tracing::error!(
+                routing_key,
+                delivery_tag = unacked.delivery_tag,
+                message_id = ?unacked.properties.message_id(),
+                "Consumer handler timed out after {:?}, requeueing the message",
+                timeout
+            );
+            *TIMED_OUT_HANDLERS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(routing_key)
+                .or_default() += 1;
+
+            unacked
+                .nack(BasicNackOptions {
+                    requeue: true,
+                    ..Default::default()
+                })
+                .await
+                .context("Failed to requeue timed out message")
+        }
+    }
+}
+
+/// Whether a handler failed because its message was not valid UTF-8 or not the expected JSON.
+fn is_parse_error(err: &anyhow::Error) -> bool {
+    err.is::<serde_json::Error>() || err.is::<std::str::Utf8Error>()
+}
+
+/// How many messages could not be parsed so far, per routing key.
+pub fn unparseable_messages() -> Vec<(&'static str, u64)> {
+    UNPARSEABLE_MESSAGES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|(routing_key, count)| (*routing_key, *count))
+        .collect()
+}
+
+/// How many handlers have timed out so far, per routing key.
+pub fn timed_out_handlers() -> Vec<(&'static str, u64)> {
+    TIMED_OUT_HANDLERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|(routing_key, count)| (*routing_key, *count))
+        .collect()
+}
+
+/// Records whether the consumers are running on a broker connection, as reported by
+/// [`is_connected`].
+pub fn set_connected(connected: bool) {
+    CONNECTED.store(connected, Ordering::SeqCst);
+}
+
+/// Whether the consumers are running on a broker connection, rather than waiting to reconnect.
+pub fn is_connected() -> bool {
+    CONNECTED.load(Ordering::SeqCst)
+}
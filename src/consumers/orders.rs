@@ -1,32 +1,112 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use diesel::ExpressionMethods;
-use diesel_async::RunQueryDsl;
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use futures::future::BoxFuture;
 use lapin::{message::Delivery, options::BasicAckOptions};
-use medbook_core::app_state::AppState;
+use medbook_core::{app_state::AppState, outbox};
 use medbook_events::{
-    DeliveryCreatedEvent, DeliverySuccessEvent, OrderCancelSuccessEvent, OrderRejectedEvent,
-    OrderReservedEvent,
+    DeliveryCreatedEvent, DeliverySuccessEvent, OrderCancelSuccessEvent, OrderCancelledEvent,
+    OrderItem, OrderRejectedEvent, OrderReservedEvent,
 };
 use tracing::info;
 
-use crate::schema::orders;
+use crate::{
+    db,
+    events::{OrderRetryableEvent, PartialReservationEvent, ProductRestockedEvent},
+    models::OrderEntity,
+    order_items, order_status, routing_keys,
+    schema::{self, orders},
+    settings::Settings,
+};
+
+/// Statuses of orders still waiting for InventoryService to answer their reservation request. A
+/// late answer still settles a timed out request. Answers for orders in any other status, e.g. a
+/// duplicate or one racing a conflicting answer, are acknowledged and otherwise ignored.
+const AWAITING_RESERVATION_STATUSES: &[&str] = &["PENDING", "RESERVE_TIMEOUT"];
 
 pub fn order_reserved(delivery: Delivery, state: Arc<AppState>) -> BoxFuture<'static, Result<()>> {
     Box::pin(async move {
-        let conn = &mut state.db_pool.get().await?;
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
         let payload: OrderReservedEvent = serde_json::from_str(str::from_utf8(&delivery.data)?)?;
         info!("Received event: {:?}", payload);
 
-        diesel::update(orders::table)
-            .filter(orders::id.eq(payload.order_id))
-            .set(orders::status.eq("RESERVED"))
-            .execute(conn)
-            .await?;
+        let reserved_until =
+            Utc::now() + chrono::Duration::minutes(Settings::get_reservation_hold_minutes());
+        if reserve_order(conn, payload.order_id, reserved_until).await? {
+            info!("Order #{} has been reserved", payload.order_id);
+        }
+
+        delivery.ack(BasicAckOptions::default()).await?;
+
+        Ok(())
+    })
+}
+
+/// Moves an order awaiting its reservation to RESERVED, held until `reserved_until`. Returns
+/// whether it did.
+async fn reserve_order(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    reserved_until: DateTime<Utc>,
+) -> Result<bool> {
+    conn.transaction(move |conn| {
+        Box::pin(async move {
+            let old_status = order_status::lock_status(conn, order_id).await?;
+            if old_status == "CANCELLED" {
+                info!("Order #{} was cancelled before being reserved", order_id);
+                let order: OrderEntity = orders::table.find(order_id).get_result(conn).await?;
+                let reserved_items = order_items::reserved_items(conn, &order).await?;
+                release_cancelled_reservation(conn, order_id, reserved_items).await?;
+                return Ok(false);
+            }
+            if !AWAITING_RESERVATION_STATUSES.contains(&old_status.as_str()) {
+                info!(
+                    "Order #{} is {}, ignoring its reservation",
+                    order_id, old_status
+                );
+                return Ok(false);
+            }
+
+            let order = diesel::update(orders::table.find(order_id))
+                .set((
+                    orders::status.eq("RESERVED"),
+                    orders::reserved_until.eq(reserved_until),
+                ))
+                .returning(OrderEntity::as_returning())
+                .get_result(conn)
+                .await?;
+
+            order_status::status_changed(conn, Some(&old_status), &order).await?;
+
+            Ok::<bool, anyhow::Error>(true)
+        })
+    })
+    .await
+}
+
+/// Holds an order whose items InventoryService could only partly reserve until the patient either
+/// goes ahead with what was reserved or cancels. Backordered units are recorded on the order items.
+pub fn order_partially_reserved(
+    delivery: Delivery,
+    state: Arc<AppState>,
+) -> BoxFuture<'static, Result<()>> {
+    Box::pin(async move {
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
+        let payload: PartialReservationEvent =
+            serde_json::from_str(str::from_utf8(&delivery.data)?)?;
+        info!("Received event: {:?}", payload);
 
-        info!("Order #{} has been reserved", payload.order_id);
+        let order_id = payload.order_id;
+        let reserved_until =
+            Utc::now() + chrono::Duration::minutes(Settings::get_reservation_hold_minutes());
+        if partially_reserve_order(conn, payload, reserved_until).await? {
+            info!("Order #{} has been partially reserved", order_id);
+        }
 
         delivery.ack(BasicAckOptions::default()).await?;
 
@@ -34,19 +114,63 @@ pub fn order_reserved(delivery: Delivery, state: Arc<AppState>) -> BoxFuture<'st
     })
 }
 
+/// Moves an order awaiting its reservation to PARTIALLY_RESERVED, held until `reserved_until`.
+/// Returns whether it did.
+async fn partially_reserve_order(
+    conn: &mut AsyncPgConnection,
+    payload: PartialReservationEvent,
+    reserved_until: DateTime<Utc>,
+) -> Result<bool> {
+    let order_id = payload.order_id;
+
+    conn.transaction(move |conn| {
+        Box::pin(async move {
+            let old_status = order_status::lock_status(conn, order_id).await?;
+            if old_status == "CANCELLED" {
+                info!(
+                    "Order #{} was cancelled before being partially reserved",
+                    order_id
+                );
+                release_cancelled_reservation(conn, order_id, payload.reserved_items).await?;
+                return Ok(false);
+            }
+            if !AWAITING_RESERVATION_STATUSES.contains(&old_status.as_str()) {
+                info!(
+                    "Order #{} is {}, ignoring its partial reservation",
+                    order_id, old_status
+                );
+                return Ok(false);
+            }
+
+            order_items::record_backorders(conn, order_id, &payload.backordered_items).await?;
+
+            let order = diesel::update(orders::table.find(order_id))
+                .set((
+                    orders::status.eq("PARTIALLY_RESERVED"),
+                    orders::reserved_until.eq(reserved_until),
+                ))
+                .returning(OrderEntity::as_returning())
+                .get_result(conn)
+                .await?;
+
+            order_status::status_changed(conn, Some(&old_status), &order).await?;
+
+            Ok::<bool, anyhow::Error>(true)
+        })
+    })
+    .await
+}
+
 pub fn order_rejected(delivery: Delivery, state: Arc<AppState>) -> BoxFuture<'static, Result<()>> {
     Box::pin(async move {
-        let conn = &mut state.db_pool.get().await?;
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
         let payload: OrderRejectedEvent = serde_json::from_str(str::from_utf8(&delivery.data)?)?;
         info!("Received event: {:?}", payload);
 
-        diesel::update(orders::table)
-            .filter(orders::id.eq(payload.order_id))
-            .set(orders::status.eq("REJECTED"))
-            .execute(conn)
-            .await?;
-
-        info!("Order #{} has been rejected", payload.order_id);
+        if reject_order(conn, payload.order_id).await? {
+            info!("Order #{} has been rejected", payload.order_id);
+        }
 
         delivery.ack(BasicAckOptions::default()).await?;
 
@@ -54,23 +178,78 @@ pub fn order_rejected(delivery: Delivery, state: Arc<AppState>) -> BoxFuture<'st
     })
 }
 
+/// Moves an order awaiting its reservation to REJECTED. Returns whether it did.
+async fn reject_order(conn: &mut AsyncPgConnection, order_id: i32) -> Result<bool> {
+    conn.transaction(move |conn| {
+        Box::pin(async move {
+            let old_status = order_status::lock_status(conn, order_id).await?;
+            // Orders cancelled while PENDING stay cancelled rather than becoming retryable.
+            // Nothing was reserved, so there is nothing to release.
+            if !AWAITING_RESERVATION_STATUSES.contains(&old_status.as_str()) {
+                info!(
+                    "Order #{} is {}, ignoring its rejection",
+                    order_id, old_status
+                );
+                return Ok(false);
+            }
+
+            let order = diesel::update(orders::table.find(order_id))
+                .set(orders::status.eq("REJECTED"))
+                .returning(OrderEntity::as_returning())
+                .get_result(conn)
+                .await?;
+
+            order_status::status_changed(conn, Some(&old_status), &order).await?;
+
+            Ok::<bool, anyhow::Error>(true)
+        })
+    })
+    .await
+}
+
+/// Asks InventoryService again to release what it reserved for an order the patient cancelled while
+/// it was PENDING. The cancel request sent then may have reached InventoryService before the
+/// reservation did, leaving `reserved_items` held with nobody to release them.
+async fn release_cancelled_reservation(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    reserved_items: Vec<OrderItem>,
+) -> Result<()> {
+    outbox::publish(
+        conn,
+        routing_keys::INVENTORY_CANCEL_ORDER.into(),
+        OrderCancelledEvent {
+            order_id,
+            order_items: reserved_items,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
 pub fn order_cancel_success(
     delivery: Delivery,
     state: Arc<AppState>,
 ) -> BoxFuture<'static, Result<()>> {
     Box::pin(async move {
-        let conn = &mut state.db_pool.get().await?;
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
         let payload: OrderCancelSuccessEvent =
             serde_json::from_str(str::from_utf8(&delivery.data)?)?;
         info!("Received event: {:?}", payload);
 
-        diesel::update(orders::table)
-            .filter(orders::id.eq(payload.order_id))
-            .set(orders::status.eq("CANCELLED"))
-            .execute(conn)
-            .await?;
+        let cancelled_order =
+            order_status::set_status(conn, payload.order_id, &["CANCEL_PENDING"], "CANCELLED")
+                .await?;
 
-        info!("Order #{} has been cancelled", payload.order_id);
+        match cancelled_order {
+            Some(_) => info!("Order #{} has been cancelled", payload.order_id),
+            None => info!(
+                "Order #{} is not awaiting a cancellation, ignoring it",
+                payload.order_id
+            ),
+        }
 
         delivery.ack(BasicAckOptions::default()).await?;
 
@@ -83,15 +262,27 @@ pub fn delivery_created(
     state: Arc<AppState>,
 ) -> BoxFuture<'static, Result<()>> {
     Box::pin(async move {
-        let conn = &mut state.db_pool.get().await?;
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
         let payload: DeliveryCreatedEvent = serde_json::from_str(str::from_utf8(&delivery.data)?)?;
         info!("Received event: {:?}", payload);
 
-        diesel::update(orders::table)
-            .filter(orders::id.eq(payload.order_id))
-            .set(orders::delivery_id.eq(payload.delivery_id))
-            .execute(conn)
-            .await?;
+        let order_id = payload.order_id;
+        let delivery_id = payload.delivery_id;
+        conn.transaction(move |conn| {
+            Box::pin(async move {
+                // Takes the same order lock as the status handlers, so a delivery ID can't land in
+                // the middle of a concurrent status change of the order.
+                order_status::lock_status(conn, order_id).await?;
+                diesel::update(orders::table.find(order_id))
+                    .set(orders::delivery_id.eq(delivery_id))
+                    .execute(conn)
+                    .await?;
+
+                Ok::<(), anyhow::Error>(())
+            })
+        })
+        .await?;
 
         info!(
             "Delivery {} for Order #{} has been successfully created",
@@ -109,19 +300,92 @@ pub fn delivery_success(
     state: Arc<AppState>,
 ) -> BoxFuture<'static, Result<()>> {
     Box::pin(async move {
-        let conn = &mut state.db_pool.get().await?;
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
         let payload: DeliverySuccessEvent = serde_json::from_str(str::from_utf8(&delivery.data)?)?;
         info!("Received event: {:?}", payload);
 
-        diesel::update(orders::table)
-            .filter(orders::id.eq(payload.order_id))
-            .set(orders::status.eq("DELIVERED"))
-            .execute(conn)
+        let delivered_order =
+            order_status::set_status(conn, payload.order_id, &["DELIVERY_PENDING"], "DELIVERED")
+                .await?;
+
+        match delivered_order {
+            Some(_) => info!(
+                "Order #{} has been successfully delivered",
+                payload.order_id
+            ),
+            None => info!(
+                "Order #{} is not awaiting a delivery, ignoring it",
+                payload.order_id
+            ),
+        }
+
+        delivery.ack(BasicAckOptions::default()).await?;
+
+        Ok(())
+    })
+}
+
+pub fn product_restocked(
+    delivery: Delivery,
+    state: Arc<AppState>,
+) -> BoxFuture<'static, Result<()>> {
+    Box::pin(async move {
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
+        let payload: ProductRestockedEvent = serde_json::from_str(str::from_utf8(&delivery.data)?)?;
+        info!("Received event: {:?}", payload);
+
+        let product_id = payload.product_id;
+        let rejected_since =
+            Utc::now() - chrono::Duration::days(Settings::get_retryable_window_days());
+
+        let retryable_orders = conn
+            .transaction(move |conn| {
+                Box::pin(async move {
+                    use schema::order_items;
+
+                    let orders_with_product = order_items::table
+                        .filter(order_items::product_id.eq(product_id))
+                        .select(order_items::order_id);
+
+                    // `updated_at` marks when the order was rejected, its status hasn't changed
+                    // since.
+                    let retryable_orders: Vec<OrderEntity> = diesel::update(
+                        orders::table
+                            .filter(orders::status.eq("REJECTED"))
+                            .filter(orders::deleted_at.is_null())
+                            .filter(orders::updated_at.ge(rejected_since))
+                            .filter(orders::id.eq_any(orders_with_product)),
+                    )
+                    .set(orders::status.eq("RETRYABLE"))
+                    .returning(OrderEntity::as_returning())
+                    .get_results(conn)
+                    .await?;
+
+                    for order in &retryable_orders {
+                        order_status::status_changed(conn, Some("REJECTED"), order).await?;
+                        outbox::publish(
+                            conn,
+                            routing_keys::ORDER_RETRYABLE.into(),
+                            OrderRetryableEvent {
+                                order_id: order.id,
+                                patient_id: order.patient_id,
+                                product_id,
+                            },
+                        )
+                        .await?;
+                    }
+
+                    Ok::<Vec<OrderEntity>, anyhow::Error>(retryable_orders)
+                })
+            })
             .await?;
 
         info!(
-            "Order #{} has been successfully delivered",
-            payload.order_id
+            "{} rejected orders became retryable after Product #{} was restocked",
+            retryable_orders.len(),
+            product_id
         );
 
         delivery.ack(BasicAckOptions::default()).await?;
@@ -129,3 +393,39 @@ pub fn delivery_success(
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::tests::connect_rolled_back, schema::carts};
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn a_reservation_racing_a_rejection_leaves_the_order_rejected() {
+        let conn = &mut connect_rolled_back().await;
+        let patient_id = -1134;
+        let cart_id: i32 = diesel::insert_into(carts::table)
+            .values(carts::patient_id.eq(patient_id))
+            .returning(carts::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        let order_id: i32 = diesel::insert_into(orders::table)
+            .values((
+                orders::cart_id.eq(cart_id),
+                orders::patient_id.eq(patient_id),
+            ))
+            .returning(orders::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        // Whichever answer takes the order lock first wins, the other one is ignored.
+        assert!(reject_order(conn, order_id).await.unwrap());
+        assert!(!reserve_order(conn, order_id, Utc::now()).await.unwrap());
+
+        let order: OrderEntity = orders::table.find(order_id).get_result(conn).await.unwrap();
+        assert_eq!(order.status, "REJECTED");
+        assert_eq!(order.reserved_until, None);
+    }
+}
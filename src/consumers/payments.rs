@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use futures::future::BoxFuture;
+use lapin::{message::Delivery, options::BasicAckOptions};
+use medbook_core::app_state::AppState;
+use tracing::{info, warn};
+
+use crate::{
+    billing, db,
+    events::ReconcilePaymentEvent,
+    models::{CreateReconciliationIssueEntity, OrderEntity, OrderItemEntity, PaymentEntity},
+    order_status,
+    schema::{order_items, orders, payments, reconciliation_issues},
+};
+
+/// Applies a provider's settlement report to the payment it refers to.
+///
+/// Only a PENDING payment is settled from a report. Reports about unknown payments, with a
+/// different amount, or contradicting an already settled payment are recorded in
+/// `reconciliation_issues` and leave the payment as it is.
+pub fn payment_reconcile(
+    delivery: Delivery,
+    state: Arc<AppState>,
+) -> BoxFuture<'static, Result<()>> {
+    Box::pin(async move {
+        let _permit = super::handler_permit().await?;
+        let conn = &mut db::acquire(&state.db_pool).await?;
+        let payload: ReconcilePaymentEvent = serde_json::from_str(str::from_utf8(&delivery.data)?)?;
+        info!("Received event: {:?}", payload);
+
+        // Priced before the transaction. Orders whose items were never recorded are priced through
+        // InventoryService, which must not be called while the order is locked.
+        let order: Option<OrderEntity> = payments::table
+            .filter(payments::provider_ref.eq(&payload.provider_ref))
+            .inner_join(orders::table)
+            .select(OrderEntity::as_select())
+            .get_result(conn)
+            .await
+            .optional()
+            .context("Failed to get order")?;
+        let priced_total = match &order {
+            Some(order) => billing::order_total(conn, state.http_client.clone(), order).await?,
+            None => 0.0,
+        };
+
+        conn.transaction(move |conn| {
+            Box::pin(async move {
+                let Some(order) = order else {
+                    return record_issue(conn, None, &payload, "UNKNOWN_PAYMENT").await;
+                };
+
+                // Orders are locked before their payments everywhere, so concurrent payment
+                // updates of the same order can't deadlock.
+                order_status::lock_status(conn, order.id).await?;
+                let payment: PaymentEntity = payments::table
+                    .filter(payments::provider_ref.eq(&payload.provider_ref))
+                    .filter(payments::order_id.eq(order.id))
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .context("Failed to get payment")?;
+                if (payment.amount - payload.amount).abs() > billing::AMOUNT_EPSILON {
+                    return record_issue(conn, Some(&payment), &payload, "AMOUNT_MISMATCH").await;
+                }
+
+                match (payment.status.as_str(), payload.status.as_str()) {
+                    (recorded, reported) if recorded == reported => Ok(()),
+                    ("PENDING", "PAID") => {
+                        diesel::update(payments::table.find(payment.id))
+                            .set(payments::status.eq("PAID"))
+                            .execute(conn)
+                            .await
+                            .context("Failed to update payment status")?;
+
+                        // The items may have been edited since they were priced, so the recorded
+                        // ones are summed again under the lock.
+                        let order_items: Vec<OrderItemEntity> = order_items::table
+                            .filter(order_items::order_id.eq(order.id))
+                            .get_results(conn)
+                            .await
+                            .context("Failed to get order items")?;
+                        let total_price = if order_items.is_empty() {
+                            priced_total
+                        } else {
+                            billing::items_total(&order_items)
+                        };
+                        billing::dispatch_if_paid(conn, order.id, total_price).await?;
+
+                        info!("Payment {} was settled by its provider", payment.id);
+                        Ok(())
+                    }
+                    ("PENDING", "FAILED") => {
+                        diesel::update(payments::table.find(payment.id))
+                            .set((
+                                payments::status.eq("FAILED"),
+                                payments::failure_reason
+                                    .eq("Reported as failed by the payment provider"),
+                            ))
+                            .execute(conn)
+                            .await
+                            .context("Failed to update payment status")?;
+
+                        info!("Payment {} was failed by its provider", payment.id);
+                        Ok(())
+                    }
+                    _ => record_issue(conn, Some(&payment), &payload, "STATUS_MISMATCH").await,
+                }
+            })
+        })
+        .await?;
+
+        delivery.ack(BasicAckOptions::default()).await?;
+
+        Ok(())
+    })
+}
+
+async fn record_issue(
+    conn: &mut AsyncPgConnection,
+    payment: Option<&PaymentEntity>,
+    report: &ReconcilePaymentEvent,
+    issue: &str,
+) -> Result<()> {
+    warn!(
+        "Payment reconciliation issue {} for provider ref {}",
+        issue, report.provider_ref
+    );
+
+    diesel::insert_into(reconciliation_issues::table)
+        .values(CreateReconciliationIssueEntity {
+            payment_id: payment.map(|payment| payment.id),
+            provider_ref: report.provider_ref.clone(),
+            issue: issue.into(),
+            recorded_status: payment.map(|payment| payment.status.clone()),
+            reported_status: report.status.clone(),
+            recorded_amount: payment.map(|payment| payment.amount),
+            reported_amount: report.amount,
+        })
+        .execute(conn)
+        .await
+        .context("Failed to record reconciliation issue")?;
+
+    Ok(())
+}
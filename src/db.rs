@@ -0,0 +1,277 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use diesel::result::DatabaseErrorKind;
+use diesel_async::{
+    AsyncPgConnection,
+    pooled_connection::bb8::{Pool, PooledConnection},
+    scoped_futures::ScopedBoxFuture,
+};
+use medbook_core::{aliases::DieselError, app_error::AppError};
+
+use crate::settings::Settings;
+
+/// Times a serializable transaction is retried after a serialization failure before giving up.
+const MAX_SERIALIZATION_RETRIES: u32 = 3;
+/// Pause before the first retry of a serializable transaction, doubled on every further one.
+const SERIALIZATION_RETRY_BACKOFF_MS: u64 = 20;
+
+/// Checks a connection out of `pool`, giving up after [`Settings::get_db_acquire_timeout_ms`].
+///
+/// Saturated pools shed load with `ServiceUnreachable("database")` instead of queueing callers
+/// indefinitely.
+pub async fn acquire(
+    pool: &Pool<AsyncPgConnection>,
+) -> Result<PooledConnection<'_, AsyncPgConnection>, AppError> {
+    let timeout = Duration::from_millis(Settings::get_db_acquire_timeout_ms());
+    match tokio::time::timeout(timeout, pool.get()).await {
+        Ok(conn) => Ok(conn.context("Failed to obtain a DB connection pool")?),
+        Err(_) => Err(acquire_timed_out(pool)),
+    }
+}
+
+/// Same as [`acquire`], for connections that must outlive the borrow of the pool.
+pub async fn acquire_owned(
+    pool: &Pool<AsyncPgConnection>,
+) -> Result<PooledConnection<'static, AsyncPgConnection>, AppError> {
+    let timeout = Duration::from_millis(Settings::get_db_acquire_timeout_ms());
+    match tokio::time::timeout(timeout, pool.get_owned()).await {
+        Ok(conn) => Ok(conn.context("Failed to obtain a DB connection pool")?),
+        Err(_) => Err(acquire_timed_out(pool)),
+    }
+}
+
+/// Runs `transaction` at the SERIALIZABLE isolation level, for operations that move money.
+///
+/// Postgres aborts one of two conflicting serializable transactions instead of letting both see a
+/// stale snapshot. The aborted one is run again from scratch, up to [`MAX_SERIALIZATION_RETRIES`]
+/// times with a growing pause, so callers only see the failure once retrying stops helping. The
+/// closure is called once per attempt and must not have side effects outside the transaction.
+pub async fn serializable<T, F>(
+    conn: &mut AsyncPgConnection,
+    mut transaction: F,
+) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: for<'r> FnMut(
+            &'r mut AsyncPgConnection,
+        ) -> ScopedBoxFuture<'static, 'r, Result<T, AppError>>
+        + Send,
+{
+    let mut attempt = 0;
+    loop {
+        let result = conn
+            .build_transaction()
+            .serializable()
+            .run(|conn| transaction(conn))
+            .await;
+        match result {
+            Err(err) if attempt < MAX_SERIALIZATION_RETRIES && is_serialization_failure(&err) => {
+                let backoff = Duration::from_millis(SERIALIZATION_RETRY_BACKOFF_MS << attempt);
+                attempt += 1;
+                tracing::info!(
+                    "Retrying serializable transaction in {:?} (attempt {})",
+                    backoff,
+                    attempt
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_serialization_failure(err: &AppError) -> bool {
+    let AppError::Other(err) = err else {
+        return false;
+    };
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<DieselError>(),
+            Some(DieselError::DatabaseError(
+                DatabaseErrorKind::SerializationFailure,
+                _
+            ))
+        )
+    })
+}
+
+fn acquire_timed_out(pool: &Pool<AsyncPgConnection>) -> AppError {
+    let state = pool.state();
+    tracing::warn!(
+        "Timed out waiting for a DB connection ({} open, {} idle)",
+        state.connections,
+        state.idle_connections
+    );
+    AppError::ServiceUnreachable("database".into())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use diesel::{
+        ExpressionMethods, QueryDsl, SelectableHelper,
+        result::{DatabaseErrorKind, Error as DieselError},
+    };
+    use diesel_async::{AsyncConnection, RunQueryDsl};
+
+    use super::*;
+    use crate::{
+        models::OrderEntity,
+        schema::{orders, payments},
+    };
+
+    /// Connects to the migrated database at `DATABASE_URL`. Tests using it are ignored by default
+    /// and run with `cargo test -- --ignored`.
+    pub(crate) async fn connect() -> AsyncPgConnection {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        AsyncPgConnection::establish(&url)
+            .await
+            .expect("Failed to connect to DATABASE_URL")
+    }
+
+    /// Same as [`connect`], inside a transaction rolled back when the connection is dropped.
+    pub(crate) async fn connect_rolled_back() -> AsyncPgConnection {
+        let mut conn = connect().await;
+        conn.begin_test_transaction()
+            .await
+            .expect("Failed to begin test transaction");
+        conn
+    }
+
+    fn is_check_violation(err: &DieselError) -> bool {
+        matches!(
+            err,
+            DieselError::DatabaseError(DatabaseErrorKind::CheckViolation, _)
+        )
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn orders_reject_unknown_statuses() {
+        let conn = &mut connect_rolled_back().await;
+
+        let result = diesel::insert_into(orders::table)
+            .values((
+                orders::cart_id.eq(1),
+                orders::patient_id.eq(1),
+                orders::status.eq("SHIPPED"),
+            ))
+            .execute(conn)
+            .await;
+
+        assert!(result.is_err_and(|err| is_check_violation(&err)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn payments_reject_unknown_statuses() {
+        let conn = &mut connect_rolled_back().await;
+        let order_id: i32 = diesel::insert_into(orders::table)
+            .values((orders::cart_id.eq(1), orders::patient_id.eq(1)))
+            .returning(orders::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        let result = diesel::insert_into(payments::table)
+            .values((
+                payments::order_id.eq(order_id),
+                payments::amount.eq(1.0_f32),
+                payments::status.eq("SETTLED"),
+            ))
+            .execute(conn)
+            .await;
+
+        assert!(result.is_err_and(|err| is_check_violation(&err)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn orders_default_to_pickup() {
+        let conn = &mut connect_rolled_back().await;
+
+        let order_type: String = diesel::insert_into(orders::table)
+            .values((orders::cart_id.eq(1), orders::patient_id.eq(1)))
+            .returning(orders::order_type)
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        assert_eq!(order_type, "PICKUP");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn orders_reject_a_null_order_type() {
+        let conn = &mut connect_rolled_back().await;
+
+        let result = diesel::sql_query(
+            "INSERT INTO orders (cart_id, patient_id, order_type) VALUES (1, 1, NULL)",
+        )
+        .execute(conn)
+        .await;
+
+        assert!(result.is_err_and(|err| matches!(
+            err,
+            DieselError::DatabaseError(DatabaseErrorKind::NotNullViolation, _)
+        )));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn orders_are_read_with_their_address_snapshot() {
+        let conn = &mut connect_rolled_back().await;
+        // An address DeliveryService may since have changed or deleted.
+        let address = serde_json::json!({ "id": 42, "patient_id": 1, "address": "1 Main Road" });
+        let order_id: i32 = diesel::insert_into(orders::table)
+            .values((
+                orders::cart_id.eq(1),
+                orders::patient_id.eq(1),
+                orders::delivery_address.eq(address.clone()),
+            ))
+            .returning(orders::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        let order: OrderEntity = orders::table
+            .find(order_id)
+            .select(OrderEntity::as_select())
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        assert_eq!(order.delivery_address, Some(address));
+    }
+
+    fn database_error(kind: DatabaseErrorKind) -> DieselError {
+        DieselError::DatabaseError(kind, Box::new("could not serialize access".to_string()))
+    }
+
+    #[test]
+    fn serialization_failures_are_retried() {
+        let err = AppError::Other(database_error(DatabaseErrorKind::SerializationFailure).into());
+
+        assert!(is_serialization_failure(&err));
+    }
+
+    #[test]
+    fn serialization_failures_with_context_are_retried() {
+        let err = anyhow::Error::from(database_error(DatabaseErrorKind::SerializationFailure))
+            .context("Failed to create payment");
+
+        assert!(is_serialization_failure(&AppError::Other(err)));
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let unique_violation =
+            AppError::Other(database_error(DatabaseErrorKind::UniqueViolation).into());
+
+        assert!(!is_serialization_failure(&unique_violation));
+        assert!(!is_serialization_failure(&AppError::NotFound));
+        assert!(!is_serialization_failure(&AppError::Conflict(
+            "Order is not awaiting payment".into()
+        )));
+    }
+}
@@ -0,0 +1,23 @@
+use axum::http::{HeaderMap, header};
+use sha2::{Digest, Sha256};
+
+/// Builds a weak ETag from the parts of a resource that change whenever its representation does.
+pub fn weak_etag(parts: &[String]) -> String {
+    let digest = Sha256::digest(parts.join("|").as_bytes());
+    format!("W/\"{}\"", hex::encode(&digest[..16]))
+}
+
+/// Whether the client's `If-None-Match` already matches `etag`, using weak comparison.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(if_none_match) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
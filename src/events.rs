@@ -0,0 +1,133 @@
+//! Events that are not (yet) part of the shared `medbook_events` crate.
+//! Extended versions of shared events stay wire-compatible with the originals, only adding fields.
+
+use chrono::{DateTime, Utc};
+use medbook_events::OrderItem;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::OrderType;
+
+/// Asks DeliveryService to deliver a paid order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryOrderRequestEvent {
+    pub delivery_address: Option<Value>,
+    pub order_id: i32,
+    pub order_type: OrderType,
+    /// Patient's delivery instructions, e.g. "leave at door"
+    pub notes: Option<String>,
+    /// ISO 4217 currency the order was priced in
+    pub currency: String,
+    /// When the patient wants the order delivered, `None` for as soon as possible
+    pub scheduled_for: Option<DateTime<Utc>>,
+}
+
+/// Sent by InventoryService when it could only reserve some of the items of an order. What it did
+/// reserve stays held until the order is cancelled or expires.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartialReservationEvent {
+    pub order_id: i32,
+    /// Units reserved per product
+    pub reserved_items: Vec<OrderItem>,
+    /// Units per product that could not be reserved
+    pub backordered_items: Vec<OrderItem>,
+}
+
+/// Sent by InventoryService when a product is back in stock.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductRestockedEvent {
+    pub product_id: i32,
+}
+
+/// Sent by a payment provider to report how a payment was settled on its side.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReconcilePaymentEvent {
+    /// The provider's reference of the payment, as stored in `payments.provider_ref`
+    pub provider_ref: String,
+    /// Payment status as seen by the provider, e.g. PAID or FAILED
+    pub status: String,
+    pub amount: f32,
+}
+
+/// Tells the patient app that a rejected order can be re-reserved.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderRetryableEvent {
+    pub order_id: i32,
+    pub patient_id: i32,
+    pub product_id: i32,
+}
+
+/// Tells the patient app that InventoryService never answered an order's reservation request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderReserveTimeoutEvent {
+    pub order_id: i32,
+    pub patient_id: i32,
+}
+
+/// Published on every order status change, for notification fan-out.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderStatusChangedEvent {
+    pub order_id: i32,
+    pub patient_id: i32,
+    /// `None` when the order has just been created
+    pub old_status: Option<String>,
+    pub new_status: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Asks for a patient's account to be reviewed after an order above their total cap was rejected.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PatientFlaggedForReviewEvent {
+    pub patient_id: i32,
+    /// `None` when the order was rejected before being created
+    pub order_id: Option<i32>,
+    pub total_price: f32,
+    pub max_order_total: f32,
+    /// ISO 4217 currency of both amounts
+    pub currency: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Email a patient is sent about one of their orders. Which mailer renders and sends it is up to
+/// the notification service.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTemplate {
+    OrderCreated,
+    /// Asks the patient to go ahead with the reserved items or cancel
+    OrderPartiallyReserved,
+    OrderPaid,
+    OrderDelivered,
+}
+
+/// Asks for an email to be sent to a patient, with what is needed to render its template.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailNotificationEvent {
+    pub patient_id: i32,
+    pub order_id: i32,
+    pub template: EmailTemplate,
+    /// Total of the order at the prices recorded when it was placed
+    pub total_price: f32,
+    /// ISO 4217 currency of `total_price`
+    pub currency: String,
+    /// Number of units across every item of the order
+    pub item_count: i64,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Asks the payment provider to refund part or all of a payment.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaymentRefundRequestedEvent {
+    pub payment_id: Uuid,
+    pub order_id: i32,
+    pub provider: String,
+    pub provider_ref: Option<String>,
+    /// Amount to refund with this request
+    pub amount: f32,
+    /// Total refunded on the payment once this request is honored
+    pub refunded_amount: f32,
+    /// ISO 4217 currency of both amounts
+    pub currency: String,
+    pub timestamp: DateTime<Utc>,
+}
@@ -0,0 +1,164 @@
+use axum::{
+    body::Bytes,
+    extract::{
+        FromRequest, FromRequestParts, Path, Request, path::ErrorKind, rejection::PathRejection,
+    },
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+};
+use medbook_core::app_error::{AppError, StdResponse};
+use serde::{Serialize, de::DeserializeOwned};
+use utoipa::ToSchema;
+
+/// A single problem with a request body, addressed by its path, e.g. `cart_items[2].quantity`.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Field-level rules checked after a request body has been deserialized.
+pub trait Validate {
+    /// Returns every rule the value breaks. Bodies without rules can rely on the default.
+    fn validate(&self) -> Vec<FieldError> {
+        Vec::new()
+    }
+}
+
+/// Drop-in replacement for [`axum::Json`] that rejects malformed or invalid bodies with a 400
+/// listing the offending fields, so clients can map errors back to form inputs.
+pub struct ValidatedJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidatedJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        Ok(Self(parse_validated(&bytes)?))
+    }
+}
+
+/// Deserializes and validates a JSON body the way [`ValidatedJson`] does, for extractors that need
+/// the raw bytes first.
+pub fn parse_validated<T: DeserializeOwned + Validate>(bytes: &[u8]) -> Result<T, Response> {
+    let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+    let value: T = serde_path_to_error::deserialize(deserializer).map_err(|err| {
+        let field = match err.path().to_string() {
+            path if path == "." => String::new(),
+            path => path,
+        };
+        validation_error(vec![FieldError::new(field, err.into_inner().to_string())])
+    })?;
+
+    let errors = value.validate();
+    if !errors.is_empty() {
+        return Err(validation_error(errors));
+    }
+
+    Ok(value)
+}
+
+fn validation_error(errors: Vec<FieldError>) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        StdResponse {
+            data: Some(errors),
+            message: Some("Request body is invalid".to_string()),
+        },
+    )
+        .into_response()
+}
+
+/// Drop-in replacement for [`axum::extract::Path`] that answers malformed path parameters, such as
+/// a non-numeric or out-of-range ID, with a 400 naming the parameter.
+pub struct ValidatedPath<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ValidatedPath<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(value)) => Ok(Self(value)),
+            Err(PathRejection::FailedToDeserializePathParams(err)) => {
+                Err(AppError::BadRequest(match err.kind() {
+                    ErrorKind::ParseErrorAtKey { key, .. } => {
+                        format!("invalid {} format", key.replace('_', " "))
+                    }
+                    _ => "invalid path parameter format".to_string(),
+                }))
+            }
+            Err(err) => Err(AppError::Other(anyhow::anyhow!(err.body_text()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::Body, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn order_id(path: Result<ValidatedPath<i32>, AppError>) -> String {
+        match path {
+            Ok(ValidatedPath(id)) => id.to_string(),
+            Err(AppError::BadRequest(message)) => message,
+            Err(_) => "unexpected rejection".to_string(),
+        }
+    }
+
+    async fn extract_order_id(uri: &str) -> String {
+        let app = Router::new().route("/orders/{order_id}", get(order_id));
+        let req = axum::http::Request::builder()
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn validated_path_extracts_well_formed_parameters() {
+        assert_eq!(extract_order_id("/orders/42").await, "42");
+    }
+
+    #[tokio::test]
+    async fn validated_path_names_the_malformed_parameter() {
+        assert_eq!(
+            extract_order_id("/orders/abc").await,
+            "invalid order id format"
+        );
+    }
+
+    #[tokio::test]
+    async fn validated_path_rejects_out_of_range_parameters() {
+        assert_eq!(
+            extract_order_id("/orders/99999999999").await,
+            "invalid order id format"
+        );
+    }
+}
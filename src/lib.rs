@@ -1,5 +1,20 @@
 pub mod api;
+pub mod billing;
 pub mod consumers;
+pub mod db;
+pub mod etag;
+pub mod events;
+pub mod extract;
+pub mod middleware;
 pub mod models;
+pub mod money;
+pub mod order_items;
+pub mod order_status;
+pub mod pagination;
 pub mod routes;
+pub mod routing_keys;
 pub mod schema;
+pub mod security;
+pub mod settings;
+pub mod sweeper;
+pub mod webhooks;
@@ -1,60 +1,422 @@
-use anyhow::Result;
-use axum::Router;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    http::{HeaderName, HeaderValue, Method, StatusCode, header},
+    routing,
+};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{
+    AsyncConnection, AsyncPgConnection, RunQueryDsl,
+    pooled_connection::{AsyncDieselConnectionManager, bb8::Pool},
+};
 use diesel_migrations::{EmbeddedMigrations, embed_migrations};
+use lapin::{
+    BasicProperties, Connection,
+    options::{BasicPublishOptions, ConfirmSelectOptions},
+};
 use medbook_core::{
+    app_state::AppState,
     bootstrap::{self, bootstrap},
     config, db, swagger,
 };
-use medbook_orderservice::{consumers, routes};
+use medbook_orderservice::{
+    consumers::{self, Handler},
+    middleware, order_items, pagination, routes, routing_keys,
+    schema::outbox,
+    settings::Settings,
+    supervised, sweeper, webhooks,
+};
+use tower_http::cors::{Any, CorsLayer};
+use utoipa::openapi::OpenApi;
 
 /// Migrations embedded into the binary which helps with streamlining image building process
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
+/// Where the raw OpenAPI document is served for client generators. Kept apart from the paths
+/// registered by the swagger UI so the two can't collide.
+const OPENAPI_JSON_PATH: &str = "/openapi.json";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     bootstrap::init_tracing();
     bootstrap::init_env();
 
-    let routes = routes::payments::routes_with_openapi()
-        .merge(routes::patients::carts::routes_with_openapi())
+    let patient_routes = routes::patients::carts::routes_with_openapi()
         .merge(routes::patients::orders::routes_with_openapi())
-        .merge(routes::orders::routes_with_openapi());
+        .merge(routes::patients::price_quote::routes_with_openapi())
+        .merge(routes::guests::carts::routes_with_openapi())
+        .layer(cors_layer()?);
+
+    let routes = routes::payments::routes_with_openapi()
+        .merge(patient_routes)
+        .merge(routes::orders::routes_with_openapi())
+        .merge(routes::admin::carts::routes_with_openapi())
+        .merge(routes::admin::orders::routes_with_openapi())
+        .merge(routes::admin::outbox::routes_with_openapi())
+        .merge(routes::admin::patients::routes_with_openapi())
+        .merge(routes::admin::webhooks::routes_with_openapi())
+        .merge(routes::metrics::routes_with_openapi())
+        .merge(routes::health::routes_with_openapi());
 
     let mut openapi = routes.get_openapi().clone();
     openapi.info = utoipa::openapi::InfoBuilder::new()
         .title("MedBook OrderService API")
         .version("1.0.0")
         .build();
+    let openapi_json = openapi_json_route(&openapi)?;
     let swagger_ui = swagger::create_swagger_ui(openapi)?;
 
-    let app = Router::new().merge(routes).merge(swagger_ui);
+    let app = Router::new()
+        .merge(routes)
+        .merge(swagger_ui)
+        .merge(openapi_json)
+        .layer(axum::middleware::from_fn(middleware::request_timeout))
+        .layer(axum::middleware::from_fn(middleware::request_id));
 
     tracing::info!("Running migrations...");
     let config = config::load()?;
     let migrations_count = db::run_migrations_blocking(MIGRATIONS, &config.database.url).await?;
     tracing::info!("Run {} new migrations successfully", migrations_count);
 
-    tracing::info!("Bootstrapping...");
-    bootstrap(
-        "OrderService",
-        app,
-        &[
-            ("orders.order_rejected", consumers::orders::order_rejected),
-            ("orders.order_reserved", consumers::orders::order_reserved),
-            (
-                "orders.delivery_created",
-                consumers::orders::delivery_created,
-            ),
-            (
-                "orders.delivery_success",
-                consumers::orders::delivery_success,
-            ),
-            (
-                "orders.order_cancelled",
-                consumers::orders::order_cancel_success,
-            ),
-        ],
-    )
-    .await?;
+    if std::env::args().nth(1).as_deref() == Some(BACKFILL_ORDER_ITEMS_COMMAND) {
+        return backfill_order_items(&config.database.url).await;
+    }
+
+    tracing::info!("Starting background workers...");
+    let background_pool = Pool::builder()
+        .max_size(2)
+        .build(AsyncDieselConnectionManager::<AsyncPgConnection>::new(
+            &config.database.url,
+        ))
+        .await?;
+    let background_client = reqwest::Client::new();
+    tokio::spawn(webhooks::run_relay(
+        background_pool.clone(),
+        background_client,
+    ));
+    tokio::spawn(sweeper::run_sweeper(background_pool.clone()));
+
+    let consumers: &[(&'static str, Handler)] = &[
+        supervised!(
+            routing_keys::ORDER_REJECTED,
+            consumers::orders::order_rejected
+        ),
+        supervised!(
+            routing_keys::ORDER_RESERVED,
+            consumers::orders::order_reserved
+        ),
+        supervised!(
+            routing_keys::ORDER_PARTIALLY_RESERVED,
+            consumers::orders::order_partially_reserved
+        ),
+        supervised!(
+            routing_keys::DELIVERY_CREATED,
+            consumers::orders::delivery_created
+        ),
+        supervised!(
+            routing_keys::DELIVERY_SUCCESS,
+            consumers::orders::delivery_success
+        ),
+        supervised!(
+            routing_keys::ORDER_CANCELLED,
+            consumers::orders::order_cancel_success
+        ),
+        supervised!(
+            routing_keys::PRODUCT_RESTOCKED,
+            consumers::orders::product_restocked
+        ),
+        supervised!(
+            routing_keys::PAYMENT_RECONCILE,
+            consumers::payments::payment_reconcile
+        ),
+    ];
+    run_with_reconnect(app, consumers).await?;
+
+    tracing::info!("HTTP server and consumers stopped");
+    flush_outbox(&background_pool).await;
+    tracing::info!("Shutdown complete");
+    Ok(())
+}
+
+/// Runs the HTTP server and consumers until they stop on their own, starting them again with
+/// exponential backoff whenever the broker connection fails.
+///
+/// `bootstrap` owns the broker connection and only reports its loss by returning an error, taking
+/// its HTTP server down with it. While waiting to reconnect, HTTP requests are answered with 503 by
+/// [`serve_unavailable`] and [`consumers::is_connected`] reads false, so readiness fails. Errors
+/// that don't come from the broker, e.g. invalid configuration, would only fail the same way again
+/// and are returned instead. The backoff starts over once a run outlived the longest wait.
+async fn run_with_reconnect(
+    app: Router<AppState>,
+    consumers: &[(&'static str, Handler)],
+) -> Result<()> {
+    let initial_backoff = Duration::from_millis(Settings::get_amqp_reconnect_initial_backoff_ms());
+    let max_backoff = Duration::from_secs(Settings::get_amqp_reconnect_max_backoff_secs());
+    let mut backoff = initial_backoff;
+    let mut attempt = 0u32;
+
+    loop {
+        tracing::info!("Bootstrapping...");
+        let started_at = Instant::now();
+        consumers::set_connected(true);
+        let result = bootstrap("OrderService", app.clone(), consumers).await;
+        consumers::set_connected(false);
+        let Err(err) = result else {
+            return Ok(());
+        };
+        if !is_broker_error(&err) {
+            return Err(err.context("OrderService stopped"));
+        }
+
+        if started_at.elapsed() > max_backoff {
+            backoff = initial_backoff;
+            attempt = 0;
+        }
+        attempt += 1;
+        tracing::error!(
+            "Consumers stopped ({:?}), reconnection attempt {} in {:?}",
+            err,
+            attempt,
+            backoff
+        );
+        serve_unavailable(backoff).await;
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
+
+/// Whether `err` comes from the connection to RabbitMQ, which reconnecting can fix.
+fn is_broker_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<lapin::Error>())
+}
+
+/// Answers every HTTP request with 503 for `duration`, while `bootstrap` is down and not serving,
+/// so clients and probes see the service as unavailable rather than gone.
+async fn serve_unavailable(duration: Duration) {
+    let listener = match tokio::net::TcpListener::bind(("0.0.0.0", Settings::get_port())).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::warn!("Failed to serve 503s while reconnecting: {:?}", err);
+            tokio::time::sleep(duration).await;
+            return;
+        }
+    };
+    let app = Router::new().fallback(|| async {
+        middleware::error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "OrderService is reconnecting to RabbitMQ, please try again shortly".into(),
+        )
+    });
+
+    if let Err(err) = axum::serve(listener, app)
+        .with_graceful_shutdown(tokio::time::sleep(duration))
+        .await
+    {
+        tracing::warn!("Serving 503s while reconnecting failed: {:?}", err);
+    }
+}
+
+/// Command running the one-off backfill of order items instead of the service, e.g.
+/// `server backfill-order-items`.
+const BACKFILL_ORDER_ITEMS_COMMAND: &str = "backfill-order-items";
+
+/// Persists the items of every order placed before order items were snapshotted, then returns.
+///
+/// Orders whose products are gone from InventoryService or have no price are skipped and logged
+/// rather than snapshotted with made-up details, and keep being shown from their cart. Running it
+/// again only picks up orders that are still missing items.
+async fn backfill_order_items(database_url: &str) -> Result<()> {
+    let conn = &mut AsyncPgConnection::establish(database_url)
+        .await
+        .context("Failed to connect to the database")?;
+    let client = reqwest::Client::new();
+
+    let mut after_order_id = 0;
+    let (mut backfilled, mut skipped) = (0, Vec::new());
+    loop {
+        let batch = order_items::backfill(conn, client.clone(), after_order_id).await?;
+        let Some(last_order_id) = batch.last_order_id else {
+            break;
+        };
+        after_order_id = last_order_id;
+        backfilled += batch.backfilled;
+        skipped.extend(batch.skipped);
+        tracing::info!("Backfilled items of {} orders so far", backfilled);
+    }
+
+    if !skipped.is_empty() {
+        tracing::warn!(
+            order_ids = ?skipped,
+            "Skipped {} orders with products InventoryService no longer prices",
+            skipped.len()
+        );
+    }
+    tracing::info!("Backfilled items of {} orders", backfilled);
     Ok(())
 }
+
+/// Upper bound on the shutdown outbox flush, so a slow database or broker cannot hold up the exit.
+const SHUTDOWN_OUTBOX_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most outbox events published by the shutdown flush.
+const SHUTDOWN_OUTBOX_BATCH_SIZE: i64 = 500;
+
+/// Publishes the outbox events still waiting to be relayed when the service stops, in one pass
+/// bounded by [`SHUTDOWN_OUTBOX_TIMEOUT`], and logs what is left for the next instance.
+///
+/// The `medbook_core` relay has stopped with `bootstrap` by then, so the pass opens its own
+/// connection to `AMQP_URL`. Events that can't be sent in time stay PENDING.
+async fn flush_outbox(pool: &Pool<AsyncPgConnection>) {
+    let relayed = AtomicUsize::new(0);
+    let result = tokio::time::timeout(
+        SHUTDOWN_OUTBOX_TIMEOUT,
+        relay_pending_events(pool, &relayed),
+    )
+    .await;
+    let relayed = relayed.load(Ordering::SeqCst);
+
+    match result {
+        Ok(Ok(0)) => tracing::info!("Outbox is drained"),
+        Ok(Ok(left)) => tracing::warn!(
+            relayed_events = relayed,
+            pending_events = left,
+            "Stopping with undelivered outbox events, the next instance will relay them"
+        ),
+        Ok(Err(err)) => tracing::error!(
+            relayed_events = relayed,
+            "Failed to flush the outbox on shutdown: {:?}",
+            err
+        ),
+        Err(_) => tracing::error!(
+            relayed_events = relayed,
+            "Timed out flushing the outbox on shutdown after {:?}",
+            SHUTDOWN_OUTBOX_TIMEOUT
+        ),
+    }
+}
+
+/// Publishes up to [`SHUTDOWN_OUTBOX_BATCH_SIZE`] PENDING outbox events, oldest first, the way the
+/// `medbook_core` relay does: to the default exchange with the event type as routing key, marking
+/// each one SENT once the broker confirmed it. Counts them in `relayed` as it goes and returns how
+/// many events are still PENDING.
+async fn relay_pending_events(
+    pool: &Pool<AsyncPgConnection>,
+    relayed: &AtomicUsize,
+) -> Result<i64> {
+    let conn = &mut pool
+        .get()
+        .await
+        .context("Failed to obtain a DB connection")?;
+    let events: Vec<(i32, String, String)> = outbox::table
+        .filter(outbox::status.eq("PENDING"))
+        .order_by(outbox::id.asc())
+        .limit(SHUTDOWN_OUTBOX_BATCH_SIZE)
+        .select((outbox::id, outbox::event_type, outbox::payload))
+        .get_results(conn)
+        .await
+        .context("Failed to get pending outbox events")?;
+
+    if !events.is_empty() {
+        let connection = Connection::connect(&Settings::get_amqp_url(), Default::default())
+            .await
+            .context("Failed to connect to RabbitMQ")?;
+        let channel = connection
+            .create_channel()
+            .await
+            .context("Failed to open a channel")?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .context("Failed to enable publisher confirms")?;
+
+        for (id, event_type, payload) in events {
+            channel
+                .basic_publish(
+                    "",
+                    &event_type,
+                    BasicPublishOptions::default(),
+                    payload.as_bytes(),
+                    BasicProperties::default(),
+                )
+                .await
+                .context("Failed to publish outbox event")?
+                .await
+                .context("Outbox event was not confirmed")?;
+            diesel::update(outbox::table.find(id))
+                .set(outbox::status.eq("SENT"))
+                .execute(conn)
+                .await
+                .context("Failed to mark outbox event as sent")?;
+            relayed.fetch_add(1, Ordering::SeqCst);
+        }
+
+        if let Err(err) = connection.close(0, "Outbox flushed".into()).await {
+            tracing::debug!("Failed to close the shutdown broker connection: {:?}", err);
+        }
+    }
+
+    outbox::table
+        .filter(outbox::status.eq("PENDING"))
+        .count()
+        .get_result(conn)
+        .await
+        .context("Failed to count pending outbox events")
+}
+
+/// Serves the merged OpenAPI document as JSON at [`OPENAPI_JSON_PATH`]. It is serialized once, as
+/// the routes can't change at runtime.
+fn openapi_json_route(openapi: &OpenApi) -> Result<Router<AppState>> {
+    let document = openapi
+        .to_pretty_json()
+        .context("Failed to serialize OpenAPI document")?;
+
+    Ok(Router::new().route(
+        OPENAPI_JSON_PATH,
+        routing::get(move || {
+            let document = document.clone();
+            async move { ([(header::CONTENT_TYPE, "application/json")], document) }
+        }),
+    ))
+}
+
+/// Builds the CORS policy of the patient-facing routes from the `CORS_*` settings.
+/// A `*` origin allows any origin, but never together with credentials.
+fn cors_layer() -> Result<CorsLayer> {
+    let methods = Settings::get_cors_allowed_methods()
+        .iter()
+        .map(|method| method.to_uppercase().parse::<Method>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid CORS_ALLOWED_METHODS")?;
+    let headers = Settings::get_cors_allowed_headers()
+        .iter()
+        .map(|header| header.parse::<HeaderName>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid CORS_ALLOWED_HEADERS")?;
+    let layer = CorsLayer::new()
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers([
+            header::ETAG,
+            header::LINK,
+            pagination::TOTAL_COUNT_HEADER,
+            middleware::REQUEST_ID_HEADER,
+        ]);
+
+    let origins = Settings::get_cors_allowed_origins();
+    if origins.iter().any(|origin| origin == "*") {
+        return Ok(layer.allow_origin(Any));
+    }
+
+    let origins = origins
+        .iter()
+        .map(|origin| origin.parse::<HeaderValue>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid CORS_ALLOWED_ORIGINS")?;
+
+    Ok(layer
+        .allow_origin(origins)
+        .allow_credentials(Settings::get_cors_allow_credentials()))
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{HeaderName, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use medbook_core::app_error::{AppError, StdResponse};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::settings::Settings;
+
+/// Header carrying the shared admin key used by internal tooling.
+pub const ADMIN_KEY_HEADER: &str = "x-admin-key";
+
+/// Header carrying the ID correlating a request across services.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request ID accepted from a caller. Longer ones are replaced by a generated ID.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// ID of the request being handled by the current task, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Tags every request with an ID, reusing the caller's `X-Request-Id` when it sent a usable one.
+///
+/// The ID is echoed in the response, attached to the request's tracing span and available to
+/// outgoing calls through [`current_request_id`].
+pub async fn request_id(req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.uri().path()
+    );
+    let header_value = HeaderValue::from_str(&request_id).ok();
+    let mut response = REQUEST_ID
+        .scope(request_id, next.run(req))
+        .instrument(span)
+        .await;
+    if let Some(header_value) = header_value {
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, header_value);
+    }
+    response
+}
+
+/// Guards admin-only routes behind the `ADMIN_API_KEY` shared secret.
+/// Requests are rejected when the key is missing or wrong, and always when no key is configured.
+pub async fn admins_authorization(req: Request, next: Next) -> Result<Response, AppError> {
+    let expected = std::env::var("ADMIN_API_KEY")
+        .ok()
+        .filter(|key| !key.is_empty());
+    let provided = req
+        .headers()
+        .get(ADMIN_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    match (expected, provided) {
+        (Some(expected), Some(provided)) if keys_match(&expected, provided) => {
+            Ok(next.run(req).await)
+        }
+        _ => Err(AppError::ForbiddenResource),
+    }
+}
+
+/// Whether `provided` equals `expected`. Every byte is compared, so the time taken does not show
+/// how much of the key a caller got right.
+fn keys_match(expected: &str, provided: &str) -> bool {
+    let (expected, provided) = (expected.as_bytes(), provided.as_bytes());
+    expected.len() == provided.len()
+        && expected
+            .iter()
+            .zip(provided)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Rejects oversized or non-JSON bodies on mutating requests before any handler deserializes them.
+///
+/// The body is buffered up to [`Settings::get_max_request_body_bytes`], so chunked uploads without
+/// a `Content-Length` are bounded too.
+pub async fn json_body_guard(req: Request, next: Next) -> Response {
+    if !matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH) {
+        return next.run(req).await;
+    }
+
+    let max_bytes = Settings::get_max_request_body_bytes();
+    let (parts, body) = req.into_parts();
+    let bytes = match to_bytes(body, max_bytes).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Request body must be at most {} bytes", max_bytes),
+            );
+        }
+    };
+
+    let is_json = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    if !bytes.is_empty() && !is_json {
+        return error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Request body must be application/json".into(),
+        );
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+/// Answers with 504 when a handler takes longer than [`Settings::get_request_timeout_secs`] to
+/// produce a response. Streamed bodies are not bounded once their headers have been sent.
+pub async fn request_timeout(req: Request, next: Next) -> Response {
+    let timeout = Duration::from_secs(Settings::get_request_timeout_secs());
+    match tokio::time::timeout(timeout, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => error_response(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!(
+                "Request did not complete within {} seconds",
+                timeout.as_secs()
+            ),
+        ),
+    }
+}
+
+/// Renders an error that has no `AppError` counterpart through the standard response envelope.
+pub fn error_response(status: StatusCode, message: String) -> Response {
+    (
+        status,
+        StdResponse::<(), String> {
+            data: None,
+            message: Some(message),
+        },
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, routing::post};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn guarded(content_type: &str, body: Vec<u8>) -> StatusCode {
+        let app = Router::new()
+            .route("/", post(|| async { StatusCode::OK }))
+            .layer(axum::middleware::from_fn(json_body_guard));
+        let req = axum::http::Request::builder()
+            .method(Method::POST)
+            .uri("/")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn json_body_guard_accepts_json_within_the_limit() {
+        let status = guarded("application/json; charset=utf-8", b"{}".to_vec()).await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn json_body_guard_rejects_oversized_bodies() {
+        let body = vec![b' '; Settings::get_max_request_body_bytes() + 1];
+
+        assert_eq!(
+            guarded("application/json", body).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn json_body_guard_rejects_other_content_types() {
+        let status = guarded("text/plain", b"{}".to_vec()).await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn only_equal_keys_match() {
+        assert!(keys_match("admin-key", "admin-key"));
+        assert!(!keys_match("admin-key", "admin-kez"));
+        assert!(!keys_match("admin-key", "admin"));
+        assert!(!keys_match("admin-key", ""));
+    }
+}
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use diesel::{
     Selectable,
@@ -15,9 +17,13 @@ use uuid::Uuid;
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct CartEntity {
     pub id: i32,
-    pub patient_id: i32,
+    /// `None` for guest carts that have not been claimed yet
+    pub patient_id: Option<i32>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Secret a guest presents to access their cart
+    #[serde(skip_serializing)]
+    pub session_token: Option<Uuid>,
 }
 
 #[derive(Queryable, Selectable, Serialize, Debug, ToSchema)]
@@ -30,6 +36,10 @@ pub struct CartItemEntity {
     pub quantity: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Unit price when the item was added to the cart, if InventoryService could be reached
+    #[serde(with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    pub last_seen_unit_price: Option<f32>,
 }
 
 #[derive(Insertable, Deserialize, Debug)]
@@ -38,12 +48,19 @@ pub struct CreateCartEntity {
     pub patient_id: i32,
 }
 
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::carts)]
+pub struct CreateGuestCartEntity {
+    pub session_token: Uuid,
+}
+
 #[derive(Insertable, Deserialize, Debug)]
 #[diesel(table_name = crate::schema::cart_items)]
 pub struct CreateCartItemEntity {
     pub cart_id: i32,
     pub product_id: i32,
     pub quantity: i32,
+    pub last_seen_unit_price: Option<f32>,
 }
 
 // Orders
@@ -58,10 +75,49 @@ pub struct OrderEntity {
     pub status: String,
     pub order_type: String,
     pub delivery_id: Option<Uuid>,
+    /// Full copy of the DeliveryService address taken when it was chosen. Orders are always read
+    /// from it, so later edits or deletion of the address in DeliveryService don't affect them.
     pub delivery_address: Option<Value>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
+    pub notes: Option<String>,
+    pub currency: String,
+    /// Until when InventoryService holds the items of a RESERVED order. Unpaid orders expire then.
+    pub reserved_until: Option<DateTime<Utc>>,
+    /// When the patient wants a DELIVERY order delivered, `None` for as soon as possible
+    pub scheduled_for: Option<DateTime<Utc>>,
+}
+
+/// How an order reaches the patient. Stored in `orders.order_type` in SCREAMING_SNAKE_CASE.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderType {
+    /// Collected by the patient at the pharmacy
+    Pickup,
+    /// Shipped to the patient's delivery address
+    Delivery,
+}
+
+impl OrderType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Pickup => "PICKUP",
+            OrderType::Delivery => "DELIVERY",
+        }
+    }
+}
+
+impl FromStr for OrderType {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_uppercase().as_str() {
+            "PICKUP" => Ok(OrderType::Pickup),
+            "DELIVERY" => Ok(OrderType::Delivery),
+            _ => Err(anyhow::anyhow!("Unknown order type {}", value)),
+        }
+    }
 }
 
 #[derive(Insertable, Debug)]
@@ -73,6 +129,44 @@ pub struct CreateOrderEntity {
     pub cart_id: i32,
     pub status: String,
     pub order_type: String,
+    pub notes: Option<String>,
+    pub currency: String,
+    pub scheduled_for: Option<DateTime<Utc>>,
+}
+
+/// A product of an order, with its name and price as they were when the order was placed.
+#[derive(Queryable, Selectable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::order_items)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct OrderItemEntity {
+    pub order_id: i32,
+    pub product_id: i32,
+    pub quantity: i32,
+    pub product_name: String,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub unit_price_at_order: f32,
+    pub created_at: DateTime<Utc>,
+    /// Units InventoryService could not reserve, while the order is PARTIALLY_RESERVED
+    pub backordered_quantity: i32,
+}
+
+impl OrderItemEntity {
+    /// Price of the whole line, i.e. `quantity` units at the price recorded when ordering.
+    pub fn line_total(&self) -> f32 {
+        self.quantity as f32 * self.unit_price_at_order
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::order_items)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateOrderItemEntity {
+    pub order_id: i32,
+    pub product_id: i32,
+    pub quantity: i32,
+    pub product_name: String,
+    pub unit_price_at_order: f32,
 }
 
 #[derive(Queryable, Serialize, Selectable, Debug, Clone, ToSchema)]
@@ -81,6 +175,8 @@ pub struct CreateOrderEntity {
 pub struct PaymentEntity {
     pub id: Uuid,
     pub order_id: i32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
     pub amount: f32,
     pub status: String,
     pub provider: String,
@@ -88,6 +184,13 @@ pub struct PaymentEntity {
     pub failure_reason: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub currency: String,
+    /// Client-chosen key making payment creation safe to retry, unique per order
+    pub idempotency_key: Option<String>,
+    /// Part of `amount` refunded so far
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub refunded_amount: f32,
 }
 
 #[derive(Insertable, Serialize, Deserialize, Debug)]
@@ -98,4 +201,88 @@ pub struct CreatePaymentEntity {
     pub amount: f32,
     pub provider: String,
     pub status: String,
+    pub currency: String,
+    pub idempotency_key: Option<String>,
+}
+
+/// A settlement report from a payment provider that disagreed with the recorded payment, kept for
+/// manual review instead of overwriting the payment.
+#[derive(Queryable, Selectable, Identifiable, Serialize, Debug, ToSchema)]
+#[diesel(table_name = crate::schema::reconciliation_issues)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ReconciliationIssueEntity {
+    pub id: i32,
+    pub payment_id: Option<Uuid>,
+    pub provider_ref: String,
+    pub issue: String,
+    pub recorded_status: Option<String>,
+    pub reported_status: String,
+    #[serde(with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    pub recorded_amount: Option<f32>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub reported_amount: f32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::reconciliation_issues)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateReconciliationIssueEntity {
+    pub payment_id: Option<Uuid>,
+    pub provider_ref: String,
+    pub issue: String,
+    pub recorded_status: Option<String>,
+    pub reported_status: String,
+    pub recorded_amount: Option<f32>,
+    pub reported_amount: f32,
+}
+
+// Webhooks
+
+#[derive(Queryable, Selectable, Identifiable, Serialize, Debug, Clone, ToSchema)]
+#[diesel(table_name = crate::schema::order_webhooks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct OrderWebhookEntity {
+    pub id: i32,
+    pub url: String,
+    pub event_mask: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub failure_count: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::order_webhooks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateOrderWebhookEntity {
+    pub url: String,
+    pub event_mask: String,
+    pub secret: String,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookDeliveryEntity {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::schema::webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CreateWebhookDeliveryEntity {
+    pub webhook_id: i32,
+    pub payload: String,
 }
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+use utoipa::IntoParams;
+
+use crate::settings::Settings;
+
+/// How amounts in a currency are displayed, from [`Settings::get_currency_format`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrencyFormat {
+    pub symbol: String,
+    pub decimals: usize,
+}
+
+/// Query parameter opting into display-formatted amounts next to the raw ones.
+#[derive(Deserialize, IntoParams, Debug, Clone, Copy, Default)]
+#[into_params(parameter_in = Query)]
+pub struct FormatParams {
+    /// Also return amounts formatted for display, e.g. "฿19.99"
+    #[serde(default)]
+    pub formatted: bool,
+}
+
+impl FormatParams {
+    /// Formats `amount` if formatting was asked for.
+    pub fn format(&self, amount: f32, currency: &str) -> Option<String> {
+        self.formatted.then(|| format_amount(amount, currency))
+    }
+}
+
+/// Formats `amount` for display with the symbol and decimal places configured for `currency`.
+/// Currencies without a configured format are shown with two decimals and their ISO code.
+pub fn format_amount(amount: f32, currency: &str) -> String {
+    let sign = if amount < 0.0 { "-" } else { "" };
+    match Settings::get_currency_format(currency) {
+        Some(format) => format!(
+            "{}{}{:.*}",
+            sign,
+            format.symbol,
+            format.decimals,
+            amount.abs()
+        ),
+        None => format!("{}{:.2} {}", sign, amount.abs(), currency),
+    }
+}
+
+/// `amount` as a plain decimal string with the decimal places configured for `currency`, e.g.
+/// `"19.99"` in THB and `"1200"` in JPY. Currencies without a configured format get two decimals.
+pub fn amount_string(amount: f32, currency: &str) -> String {
+    let decimals = Settings::get_currency_format(currency).map_or(2, |format| format.decimals);
+    format!("{:.*}", decimals, amount)
+}
+
+/// Serde helpers sending an amount as a string, e.g. `"19.99"`, so clients that parse JSON numbers
+/// as floats don't alter it. The field carries no currency, so amounts get the decimal places of
+/// [`Settings::get_default_currency`], the currency orders are priced in. Amounts are read from
+/// strings and, for older clients, from numbers. Use with
+/// `#[serde(with = "crate::money::as_string")]` and document the field with
+/// `#[schema(value_type = String)]`.
+pub mod as_string {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(amount: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&amount_string(*amount, &Settings::get_default_currency()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+        match RawAmount::deserialize(deserializer)? {
+            RawAmount::Number(amount) => Ok(amount),
+            RawAmount::Text(amount) => amount
+                .trim()
+                .parse()
+                .map_err(|_| D::Error::custom(format!("{:?} is not a valid amount", amount))),
+        }
+    }
+}
+
+/// [`as_string`] for optional amounts, which are sent as `null` when missing.
+pub mod option_as_string {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        amount: &Option<f32>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match amount {
+            Some(amount) => as_string::serialize(amount, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<f32>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "as_string")] f32);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(amount)| amount))
+    }
+}
+
+/// Serializes amounts keyed by ISO 4217 currency like [`as_string`] does, each with the decimal
+/// places of its own currency, for `#[serde(serialize_with = "crate::money::map_as_string")]`.
+pub fn map_as_string<K: Serialize + AsRef<str>, S: Serializer>(
+    amounts: &HashMap<K, f32>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        amounts
+            .iter()
+            .map(|(currency, amount)| (currency, amount_string(*amount, currency.as_ref()))),
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAmount {
+    Number(f32),
+    Text(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Priced {
+        #[serde(with = "as_string")]
+        amount: f32,
+        #[serde(with = "option_as_string", default)]
+        discount: Option<f32>,
+    }
+
+    #[test]
+    fn amount_strings_use_the_decimals_of_their_currency() {
+        assert_eq!(amount_string(19.5, "THB"), "19.50");
+        assert_eq!(amount_string(1200.4, "JPY"), "1200");
+    }
+
+    #[test]
+    fn amount_strings_of_unknown_currencies_have_two_decimals() {
+        assert_eq!(amount_string(3.0, "XYZ"), "3.00");
+    }
+
+    #[test]
+    fn amounts_are_sent_with_the_default_currency_decimals() {
+        let priced = Priced {
+            amount: 19.5,
+            discount: Some(2.0),
+        };
+        assert_eq!(
+            serde_json::to_string(&priced).unwrap(),
+            r#"{"amount":"19.50","discount":"2.00"}"#
+        );
+    }
+
+    #[test]
+    fn missing_optional_amounts_are_sent_as_null() {
+        let priced = Priced {
+            amount: 1.0,
+            discount: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&priced).unwrap(),
+            r#"{"amount":"1.00","discount":null}"#
+        );
+    }
+
+    #[test]
+    fn amounts_are_read_from_strings_and_numbers() {
+        let priced: Priced =
+            serde_json::from_str(r#"{"amount":" 19.99 ","discount":3.5}"#).unwrap();
+        assert_eq!(
+            priced,
+            Priced {
+                amount: 19.99,
+                discount: Some(3.5),
+            }
+        );
+        let priced: Priced = serde_json::from_str(r#"{"amount":4}"#).unwrap();
+        assert_eq!(priced.discount, None);
+    }
+
+    #[test]
+    fn malformed_amounts_are_rejected() {
+        let error = serde_json::from_str::<Priced>(r#"{"amount":"ten"}"#).unwrap_err();
+        assert!(error.to_string().contains(r#""ten" is not a valid amount"#));
+    }
+
+    #[test]
+    fn amount_maps_are_sent_with_the_decimals_of_each_currency() {
+        #[derive(Serialize)]
+        struct Totals {
+            #[serde(serialize_with = "map_as_string")]
+            totals: HashMap<&'static str, f32>,
+        }
+
+        let totals = Totals {
+            totals: HashMap::from([("JPY", 1200.0)]),
+        };
+        assert_eq!(
+            serde_json::to_string(&totals).unwrap(),
+            r#"{"totals":{"JPY":"1200"}}"#
+        );
+    }
+}
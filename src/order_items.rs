@@ -0,0 +1,557 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use diesel::{
+    ExpressionMethods, QueryDsl, SelectableHelper,
+    dsl::{exists, not},
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use medbook_core::app_error::AppError;
+use medbook_events::OrderItem;
+use reqwest::Client;
+
+use crate::{
+    api::products::{ProductDetails, get_products},
+    models::{CartItemEntity, CreateOrderItemEntity, OrderEntity, OrderItemEntity},
+    schema::{cart_items, order_items, orders},
+};
+
+/// Maximum number of orders backfilled in a single [`backfill`] batch.
+const BACKFILL_BATCH_SIZE: i64 = 100;
+
+/// Fails with BadRequest listing the products of `cart_items` that are missing from `products`, or
+/// that InventoryService has no price for.
+pub fn ensure_products_exist(
+    cart_items: &[CartItemEntity],
+    products: &HashMap<i32, ProductDetails>,
+) -> Result<(), AppError> {
+    let missing_products: Vec<String> = cart_items
+        .iter()
+        .filter(|item| !products.contains_key(&item.product_id))
+        .map(|item| item.product_id.to_string())
+        .collect();
+    if !missing_products.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "Products no longer exist: {}",
+            missing_products.join(", ")
+        )));
+    }
+
+    let unpriced_products: Vec<String> = cart_items
+        .iter()
+        .filter(|item| products[&item.product_id].unit_price.is_none())
+        .map(|item| item.product_id.to_string())
+        .collect();
+    if !unpriced_products.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "Products are currently unavailable: {}",
+            unpriced_products.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Fails with BadRequest listing the products of `cart_items` that InventoryService reports as
+/// short of the requested quantity, with what is left of each. Products without a reported stock
+/// level pass, like in [`ProductDetails::can_supply`].
+pub fn ensure_in_stock(
+    cart_items: &[CartItemEntity],
+    products: &HashMap<i32, ProductDetails>,
+) -> Result<(), AppError> {
+    let deficient_products: Vec<String> = cart_items
+        .iter()
+        .filter_map(|item| {
+            let product = products.get(&item.product_id)?;
+            match product.available_quantity {
+                Some(available) if !product.can_supply(item.quantity) => Some(format!(
+                    "{} ({} requested, {} available)",
+                    item.product_id, item.quantity, available
+                )),
+                _ => None,
+            }
+        })
+        .collect();
+    if !deficient_products.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "Products out of stock: {}",
+            deficient_products.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Records `cart_items` with the current name and unit price from `products` as the items of an
+/// order, replacing any earlier snapshot. Fails if a product is missing from `products`.
+pub async fn snapshot(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    cart_items: &[CartItemEntity],
+    products: &HashMap<i32, ProductDetails>,
+) -> Result<Vec<OrderItemEntity>, AppError> {
+    ensure_products_exist(cart_items, products)?;
+
+    let new_items: Vec<CreateOrderItemEntity> = cart_items
+        .iter()
+        .map(|item| {
+            let product = &products[&item.product_id];
+            CreateOrderItemEntity {
+                order_id,
+                product_id: item.product_id,
+                quantity: item.quantity,
+                product_name: product.name.clone(),
+                unit_price_at_order: product.unit_price.unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    diesel::delete(order_items::table.filter(order_items::order_id.eq(order_id)))
+        .execute(conn)
+        .await
+        .context("Failed to delete order items")?;
+
+    let items = diesel::insert_into(order_items::table)
+        .values(new_items)
+        .returning(OrderItemEntity::as_returning())
+        .get_results(conn)
+        .await
+        .context("Failed to create order items")?;
+
+    Ok(items)
+}
+
+/// Removes a product from the recorded items of an order. Returns the items left, ordered by
+/// product ID, or `None` if the order has no such item.
+pub async fn remove(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    product_id: i32,
+) -> Result<Option<Vec<OrderItemEntity>>> {
+    let removed = diesel::delete(
+        order_items::table
+            .filter(order_items::order_id.eq(order_id))
+            .filter(order_items::product_id.eq(product_id)),
+    )
+    .execute(conn)
+    .await
+    .context("Failed to delete order item")?;
+    if removed == 0 {
+        return Ok(None);
+    }
+
+    let items = order_items::table
+        .filter(order_items::order_id.eq(order_id))
+        .order_by(order_items::product_id.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?;
+
+    Ok(Some(items))
+}
+
+/// Records how many units of each product InventoryService could not reserve for an order, capped
+/// at the ordered quantity. Products not in `backordered_items` are fully reserved, products not on
+/// the order are ignored.
+pub async fn record_backorders(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    backordered_items: &[OrderItem],
+) -> Result<()> {
+    let quantities: HashMap<i32, i32> = order_items::table
+        .filter(order_items::order_id.eq(order_id))
+        .select((order_items::product_id, order_items::quantity))
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?
+        .into_iter()
+        .collect();
+
+    diesel::update(order_items::table.filter(order_items::order_id.eq(order_id)))
+        .set(order_items::backordered_quantity.eq(0))
+        .execute(conn)
+        .await
+        .context("Failed to reset backordered quantities")?;
+
+    for item in backordered_items {
+        let Some(&quantity) = quantities.get(&item.product_id) else {
+            continue;
+        };
+        diesel::update(
+            order_items::table
+                .filter(order_items::order_id.eq(order_id))
+                .filter(order_items::product_id.eq(item.product_id)),
+        )
+        .set(order_items::backordered_quantity.eq(item.quantity.clamp(0, quantity)))
+        .execute(conn)
+        .await
+        .context("Failed to record backordered quantity")?;
+    }
+
+    Ok(())
+}
+
+/// Drops the backordered units of an order from its items, removing items left without any.
+/// Returns the remaining items, ordered by product ID.
+pub async fn drop_backorders(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+) -> Result<Vec<OrderItemEntity>> {
+    diesel::delete(
+        order_items::table
+            .filter(order_items::order_id.eq(order_id))
+            .filter(order_items::backordered_quantity.ge(order_items::quantity)),
+    )
+    .execute(conn)
+    .await
+    .context("Failed to delete backordered order items")?;
+
+    diesel::update(order_items::table.filter(order_items::order_id.eq(order_id)))
+        .set((
+            order_items::quantity.eq(order_items::quantity - order_items::backordered_quantity),
+            order_items::backordered_quantity.eq(0),
+        ))
+        .execute(conn)
+        .await
+        .context("Failed to update order items")?;
+
+    let items = order_items::table
+        .filter(order_items::order_id.eq(order_id))
+        .order_by(order_items::product_id.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?;
+
+    Ok(items)
+}
+
+/// Sets the quantities of products already recorded on an order, leaving its other items as they
+/// are. Returns every item of the order, ordered by product ID, or `None` if one of the products is
+/// not on the order. Quantities set before that product was reached are not reverted, so roll the
+/// transaction back on `None`.
+pub async fn set_quantities(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    quantities: &[(i32, i32)],
+) -> Result<Option<Vec<OrderItemEntity>>> {
+    for &(product_id, quantity) in quantities {
+        let updated = diesel::update(
+            order_items::table
+                .filter(order_items::order_id.eq(order_id))
+                .filter(order_items::product_id.eq(product_id)),
+        )
+        .set(order_items::quantity.eq(quantity))
+        .execute(conn)
+        .await
+        .context("Failed to update order item")?;
+        if updated == 0 {
+            return Ok(None);
+        }
+    }
+
+    let items = order_items::table
+        .filter(order_items::order_id.eq(order_id))
+        .order_by(order_items::product_id.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?;
+
+    Ok(Some(items))
+}
+
+/// Loads the items of `orders`, keyed by order ID and ordered by product ID.
+///
+/// Orders placed before items were snapshotted have none recorded until [`backfill`] reaches them,
+/// or ever if one of their products is gone. Their items are derived from the cart at the current
+/// InventoryService prices instead, with a single product lookup shared by all of them. Orders that
+/// all have snapshots need no lookup.
+pub async fn load(
+    conn: &mut AsyncPgConnection,
+    client: Client,
+    orders: &[OrderEntity],
+) -> Result<HashMap<i32, Vec<OrderItemEntity>>> {
+    let order_ids: Vec<i32> = orders.iter().map(|order| order.id).collect();
+    let snapshots: Vec<OrderItemEntity> = order_items::table
+        .filter(order_items::order_id.eq_any(&order_ids))
+        .order_by((order_items::order_id.asc(), order_items::product_id.asc()))
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?;
+
+    let mut items: HashMap<i32, Vec<OrderItemEntity>> = HashMap::new();
+    for item in snapshots {
+        items.entry(item.order_id).or_default().push(item);
+    }
+
+    let legacy_orders: Vec<&OrderEntity> = orders
+        .iter()
+        .filter(|order| !items.contains_key(&order.id))
+        .collect();
+    if legacy_orders.is_empty() {
+        return Ok(items);
+    }
+
+    items.extend(derive_from_carts(conn, client, &legacy_orders).await?);
+    Ok(items)
+}
+
+/// Prices the cart items of `orders` at the current InventoryService prices, as stand-ins for the
+/// items of orders placed before items were snapshotted. Products InventoryService no longer knows
+/// are shown without a name or price.
+async fn derive_from_carts(
+    conn: &mut AsyncPgConnection,
+    client: Client,
+    orders: &[&OrderEntity],
+) -> Result<HashMap<i32, Vec<OrderItemEntity>>> {
+    let mut items = legacy_items(conn, client, orders).await?;
+
+    Ok(orders
+        .iter()
+        .map(|order| {
+            let order_items = items
+                .remove(&order.id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|item| OrderItemEntity {
+                    order_id: order.id,
+                    product_id: item.product_id,
+                    quantity: item.quantity,
+                    product_name: item
+                        .product
+                        .as_ref()
+                        .map(|product| product.name.clone())
+                        .unwrap_or_default(),
+                    unit_price_at_order: item
+                        .product
+                        .and_then(|product| product.unit_price)
+                        .unwrap_or(0.0),
+                    created_at: order.created_at,
+                    backordered_quantity: 0,
+                })
+                .collect();
+            (order.id, order_items)
+        })
+        .collect())
+}
+
+/// A cart item of an order placed before items were snapshotted, with what InventoryService
+/// currently reports about its product.
+struct LegacyItem {
+    product_id: i32,
+    quantity: i32,
+    /// `None` if InventoryService no longer knows the product
+    product: Option<ProductDetails>,
+}
+
+/// Loads the cart items of `orders`, keyed by order ID, with a single product lookup shared by all
+/// of them.
+async fn legacy_items(
+    conn: &mut AsyncPgConnection,
+    client: Client,
+    orders: &[&OrderEntity],
+) -> Result<HashMap<i32, Vec<LegacyItem>>> {
+    let cart_ids: Vec<i32> = orders.iter().map(|order| order.cart_id).collect();
+    let cart_items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq_any(&cart_ids))
+        .order_by(cart_items::product_id.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+
+    let product_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(client, product_ids).await?;
+
+    Ok(orders
+        .iter()
+        .map(|order| {
+            let items = cart_items
+                .iter()
+                .filter(|item| item.cart_id == order.cart_id)
+                .map(|item| LegacyItem {
+                    product_id: item.product_id,
+                    quantity: item.quantity,
+                    product: products.get(&item.product_id).cloned(),
+                })
+                .collect();
+            (order.id, items)
+        })
+        .collect())
+}
+
+/// The snapshot of a legacy order's items, or `None` if one of its products is gone from
+/// InventoryService or has no price. Such orders are not snapshotted with made-up details, which
+/// would fix a wrong total for good.
+fn snapshot_legacy_items(
+    order_id: i32,
+    items: Vec<LegacyItem>,
+) -> Option<Vec<CreateOrderItemEntity>> {
+    items
+        .into_iter()
+        .map(|item| {
+            let product = item.product?;
+            Some(CreateOrderItemEntity {
+                order_id,
+                product_id: item.product_id,
+                quantity: item.quantity,
+                unit_price_at_order: product.unit_price?,
+                product_name: product.name,
+            })
+        })
+        .collect()
+}
+
+/// The products and quantities reserved for an order, as sent to InventoryService. Backordered
+/// units of a PARTIALLY_RESERVED order are left out.
+pub async fn reserved_items(
+    conn: &mut AsyncPgConnection,
+    order: &OrderEntity,
+) -> Result<Vec<OrderItem>> {
+    let items: Vec<(i32, i32)> = order_items::table
+        .filter(order_items::order_id.eq(order.id))
+        .filter(order_items::quantity.gt(order_items::backordered_quantity))
+        .select((
+            order_items::product_id,
+            order_items::quantity - order_items::backordered_quantity,
+        ))
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?;
+
+    // Orders placed before items were snapshotted reserved their cart.
+    let items = if items.is_empty() {
+        cart_items::table
+            .filter(cart_items::cart_id.eq(order.cart_id))
+            .select((cart_items::product_id, cart_items::quantity))
+            .get_results(conn)
+            .await
+            .context("Failed to get cart items")?
+    } else {
+        items
+    };
+
+    Ok(items
+        .into_iter()
+        .map(|(product_id, quantity)| OrderItem {
+            product_id,
+            quantity,
+        })
+        .collect())
+}
+
+/// Outcome of a [`backfill`] batch.
+#[derive(Debug, Default)]
+pub struct BackfillBatch {
+    /// Highest order ID the batch looked at, to continue after. `None` once no order is left.
+    pub last_order_id: Option<i32>,
+    /// Number of orders whose items were persisted
+    pub backfilled: usize,
+    /// Orders left without items because one of their products is gone from InventoryService or
+    /// has no price. [`load`] keeps deriving their items from the cart.
+    pub skipped: Vec<i32>,
+}
+
+/// Persists the items of up to [`BACKFILL_BATCH_SIZE`] orders placed before items were snapshotted,
+/// priced as [`load`] shows them, starting after order `after_order_id`. Run through the one-off
+/// `backfill-order-items` command until no order is left.
+pub async fn backfill(
+    conn: &mut AsyncPgConnection,
+    client: Client,
+    after_order_id: i32,
+) -> Result<BackfillBatch> {
+    let legacy_orders: Vec<OrderEntity> = orders::table
+        .filter(orders::id.gt(after_order_id))
+        .filter(not(exists(
+            order_items::table.filter(order_items::order_id.eq(orders::id)),
+        )))
+        .filter(exists(
+            cart_items::table.filter(cart_items::cart_id.eq(orders::cart_id)),
+        ))
+        .order_by(orders::id.asc())
+        .limit(BACKFILL_BATCH_SIZE)
+        .select(OrderEntity::as_select())
+        .get_results(conn)
+        .await
+        .context("Failed to get orders without items")?;
+    let Some(last_order) = legacy_orders.last() else {
+        return Ok(BackfillBatch::default());
+    };
+
+    let mut batch = BackfillBatch {
+        last_order_id: Some(last_order.id),
+        ..BackfillBatch::default()
+    };
+    let legacy_orders: Vec<&OrderEntity> = legacy_orders.iter().collect();
+    let mut new_items = Vec::new();
+    for (order_id, items) in legacy_items(conn, client, &legacy_orders).await? {
+        match snapshot_legacy_items(order_id, items) {
+            Some(items) => {
+                new_items.extend(items);
+                batch.backfilled += 1;
+            }
+            None => batch.skipped.push(order_id),
+        }
+    }
+    batch.skipped.sort_unstable();
+
+    if !new_items.is_empty() {
+        diesel::insert_into(order_items::table)
+            .values(new_items)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await
+            .context("Failed to backfill order items")?;
+    }
+
+    Ok(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_item(product_id: i32, product: Option<(&str, Option<f32>)>) -> LegacyItem {
+        LegacyItem {
+            product_id,
+            quantity: 2,
+            product: product.map(|(name, unit_price)| ProductDetails {
+                id: product_id,
+                name: name.into(),
+                unit_price,
+                available_quantity: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn legacy_items_are_snapshotted_at_current_prices() {
+        let items = snapshot_legacy_items(
+            7,
+            vec![
+                legacy_item(1, Some(("Paracetamol", Some(3.5)))),
+                legacy_item(2, Some(("Ibuprofen", Some(4.0)))),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].order_id, 7);
+        assert_eq!(items[0].product_name, "Paracetamol");
+        assert_eq!(items[0].unit_price_at_order, 3.5);
+        assert_eq!(items[1].quantity, 2);
+    }
+
+    #[test]
+    fn orders_with_a_missing_product_are_not_snapshotted() {
+        let items = vec![
+            legacy_item(1, Some(("Paracetamol", Some(3.5)))),
+            legacy_item(2, None),
+        ];
+        assert!(snapshot_legacy_items(7, items).is_none());
+    }
+
+    #[test]
+    fn orders_with_an_unpriced_product_are_not_snapshotted() {
+        let items = vec![legacy_item(1, Some(("Paracetamol", None)))];
+        assert!(snapshot_legacy_items(7, items).is_none());
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use medbook_core::outbox;
+
+use crate::{
+    billing,
+    events::{EmailNotificationEvent, EmailTemplate, OrderStatusChangedEvent},
+    models::{OrderEntity, OrderItemEntity},
+    routing_keys,
+    schema::{order_items, orders},
+    webhooks,
+};
+
+/// Runs the side effects of an order status change that has already been written.
+///
+/// Call it on the same connection (and transaction) as the update, with the row returned by it,
+/// so the side effects are committed or rolled back together with the new status. `old_status` is
+/// `None` for newly created orders, whose items must have been recorded already.
+pub async fn status_changed(
+    conn: &mut AsyncPgConnection,
+    old_status: Option<&str>,
+    order: &OrderEntity,
+) -> Result<()> {
+    webhooks::enqueue_status_change(conn, order).await?;
+
+    if let Some(template) = email_template(old_status, &order.status) {
+        publish_email(conn, order, template).await?;
+    }
+
+    outbox::publish(
+        conn,
+        routing_keys::ORDER_STATUS_CHANGED.into(),
+        OrderStatusChangedEvent {
+            order_id: order.id,
+            patient_id: order.patient_id,
+            old_status: old_status.map(str::to_string),
+            new_status: order.status.clone(),
+            timestamp: Utc::now(),
+        },
+    )
+    .await
+}
+
+/// The email the patient is sent when their order goes from `old_status` to `new_status`, if any.
+fn email_template(old_status: Option<&str>, new_status: &str) -> Option<EmailTemplate> {
+    match (old_status, new_status) {
+        (None, _) => Some(EmailTemplate::OrderCreated),
+        (Some(_), "PARTIALLY_RESERVED") => Some(EmailTemplate::OrderPartiallyReserved),
+        (Some("PAYMENT_PENDING"), "DELIVERY_PENDING") => Some(EmailTemplate::OrderPaid),
+        (Some(_), "DELIVERED") => Some(EmailTemplate::OrderDelivered),
+        _ => None,
+    }
+}
+
+async fn publish_email(
+    conn: &mut AsyncPgConnection,
+    order: &OrderEntity,
+    template: EmailTemplate,
+) -> Result<()> {
+    // Orders placed before items were snapshotted are reported as empty rather than priced through
+    // InventoryService from inside the transaction.
+    let items: Vec<OrderItemEntity> = order_items::table
+        .filter(order_items::order_id.eq(order.id))
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?;
+
+    outbox::publish(
+        conn,
+        routing_keys::NOTIFICATIONS_EMAIL.into(),
+        EmailNotificationEvent {
+            patient_id: order.patient_id,
+            order_id: order.id,
+            template,
+            total_price: billing::items_total(&items),
+            currency: order.currency.clone(),
+            item_count: items.iter().map(|item| i64::from(item.quantity)).sum(),
+            timestamp: Utc::now(),
+        },
+    )
+    .await
+}
+
+/// Locks an order row for the rest of the transaction and returns its current status.
+///
+/// Every consumer handler and endpoint that changes an order takes this lock before anything else,
+/// so concurrent updates of the same order run one after the other.
+pub async fn lock_status(conn: &mut AsyncPgConnection, order_id: i32) -> Result<String> {
+    orders::table
+        .find(order_id)
+        .select(orders::status)
+        .for_update()
+        .get_result(conn)
+        .await
+        .context("Failed to get order status")
+}
+
+/// Moves an order from one of the `from` statuses to `status` and runs the status-change side
+/// effects in one transaction. Orders in any other status, e.g. because a conflicting event was
+/// handled first, are left as they are and `None` is returned.
+pub async fn set_status(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    from: &'static [&'static str],
+    status: &str,
+) -> Result<Option<OrderEntity>> {
+    let status = status.to_string();
+
+    conn.transaction(move |conn| {
+        Box::pin(async move {
+            let old_status = lock_status(conn, order_id).await?;
+            if !from.contains(&old_status.as_str()) {
+                return Ok(None);
+            }
+
+            let order = diesel::update(orders::table.find(order_id))
+                .set(orders::status.eq(status))
+                .returning(OrderEntity::as_returning())
+                .get_result(conn)
+                .await
+                .context("Failed to update order status")?;
+
+            status_changed(conn, Some(&old_status), &order).await?;
+
+            Ok::<Option<OrderEntity>, anyhow::Error>(Some(order))
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel_async::SimpleAsyncConnection;
+
+    use super::*;
+    use crate::{
+        db::tests::{connect, connect_rolled_back},
+        schema::carts,
+    };
+
+    /// Inserts a PENDING order of a new cart of `patient_id`. Returns the cart and order IDs.
+    async fn insert_order(conn: &mut AsyncPgConnection, patient_id: i32) -> (i32, i32) {
+        let cart_id: i32 = diesel::insert_into(carts::table)
+            .values(carts::patient_id.eq(patient_id))
+            .returning(carts::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        let order_id: i32 = diesel::insert_into(orders::table)
+            .values((
+                orders::cart_id.eq(cart_id),
+                orders::patient_id.eq(patient_id),
+            ))
+            .returning(orders::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        (cart_id, order_id)
+    }
+
+    /// Starts a transaction on `conn` that gives up on locks held for longer than 200ms.
+    async fn begin_impatient(conn: &mut AsyncPgConnection) {
+        conn.batch_execute("BEGIN; SET LOCAL lock_timeout = '200ms'")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn lock_status_serializes_changes_of_an_order() {
+        // The lock is only observable across committed connections, so the fixtures are committed
+        // too and deleted at the end.
+        let setup = &mut connect().await;
+        let (cart_id, order_id) = insert_order(setup, -1134).await;
+        let first = &mut connect().await;
+        let second = &mut connect().await;
+
+        first.batch_execute("BEGIN").await.unwrap();
+        lock_status(first, order_id).await.unwrap();
+
+        // A second handler of the same order can't get past the lock while the first one runs...
+        begin_impatient(second).await;
+        assert!(lock_status(second, order_id).await.is_err());
+        second.batch_execute("ROLLBACK").await.unwrap();
+
+        // ...and gets it as soon as the first one commits.
+        first.batch_execute("COMMIT").await.unwrap();
+        begin_impatient(second).await;
+        assert_eq!(lock_status(second, order_id).await.unwrap(), "PENDING");
+        second.batch_execute("ROLLBACK").await.unwrap();
+
+        // Deleting the cart deletes its order too.
+        diesel::delete(carts::table.find(cart_id))
+            .execute(setup)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn set_status_leaves_orders_in_other_statuses_alone() {
+        let conn = &mut connect_rolled_back().await;
+        let (_, order_id) = insert_order(conn, -1134).await;
+
+        let order = set_status(conn, order_id, &["CANCEL_PENDING"], "CANCELLED")
+            .await
+            .unwrap();
+
+        assert!(order.is_none());
+        assert_eq!(lock_status(conn, order_id).await.unwrap(), "PENDING");
+    }
+}
@@ -0,0 +1,201 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Uri, header};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Header carrying the number of items across all pages.
+pub const TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Page-based pagination query parameters shared by the list endpoints. Handlers resolve them with
+/// [`PaginationParams::clamp`] before use.
+#[derive(Deserialize, IntoParams, Debug, Clone, Copy, Default)]
+#[into_params(parameter_in = Query)]
+pub struct PaginationParams {
+    /// 1-based page number (defaults to 1)
+    pub page: Option<i64>,
+    /// Number of items per page (defaults to `DEFAULT_PER_PAGE`, at most `MAX_PER_PAGE`)
+    pub per_page: Option<i64>,
+}
+
+/// Page sizes every list endpoint shares, from
+/// [`crate::settings::Settings::get_pagination_limits`].
+#[derive(Debug, Clone, Copy)]
+pub struct PaginationLimits {
+    pub default_per_page: i64,
+    pub max_per_page: i64,
+}
+
+impl PaginationParams {
+    /// Fills in and bounds the requested page with `limits`.
+    pub fn clamp(self, limits: PaginationLimits) -> Pagination {
+        Pagination {
+            page: self.page.unwrap_or(1).max(1),
+            per_page: self
+                .per_page
+                .unwrap_or(limits.default_per_page)
+                .clamp(1, limits.max_per_page),
+        }
+    }
+}
+
+/// A validated page of a list endpoint.
+#[derive(Debug, Clone, Copy)]
+pub struct Pagination {
+    page: i64,
+    per_page: i64,
+}
+
+impl Pagination {
+    pub fn page(&self) -> i64 {
+        self.page
+    }
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page - 1).saturating_mul(self.per_page)
+    }
+
+    /// Wraps the `items` of the requested page, out of `total_count` across all pages.
+    pub fn response<T>(&self, items: Vec<T>, total_count: i64) -> PaginatedResponse<T> {
+        let (page, per_page) = (self.page, self.per_page);
+        let total_pages = (total_count + per_page - 1) / per_page;
+        PaginatedResponse {
+            items,
+            page,
+            per_page,
+            total_count,
+            total_pages,
+            has_next: page < total_pages,
+            has_prev: page > 1,
+        }
+    }
+
+    /// Builds the `X-Total-Count` header and an RFC 8288 `Link` header with `first`, `last`, `prev`
+    /// and `next` relations for the request at `uri`. Links keep every other query parameter.
+    pub fn headers(&self, uri: &Uri, total_count: i64) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(total_count));
+
+        let (page, per_page) = (self.page(), self.per_page());
+        let last_page = ((total_count + per_page - 1) / per_page).max(1);
+
+        let mut links = vec![(1, "first"), (last_page, "last")];
+        if page > 1 {
+            links.push(((page - 1).min(last_page), "prev"));
+        }
+        if page < last_page {
+            links.push((page + 1, "next"));
+        }
+
+        let link = links
+            .into_iter()
+            .map(|(page, rel)| format!("<{}>; rel=\"{}\"", page_uri(uri, page, per_page), rel))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.insert(header::LINK, link);
+        }
+
+        headers
+    }
+}
+
+/// A page of a list endpoint, sent as the `data` of a `StdResponse`.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct PaginatedResponse<T> {
+    pub items: Vec<T>,
+    /// 1-based number of this page
+    pub page: i64,
+    pub per_page: i64,
+    /// Number of items across all pages
+    pub total_count: i64,
+    /// Number of pages, 0 when there are no items
+    pub total_pages: i64,
+    pub has_next: bool,
+    pub has_prev: bool,
+}
+
+/// Relative URI of `uri` with its `page` and `per_page` query parameters replaced.
+fn page_uri(uri: &Uri, page: i64, per_page: i64) -> String {
+    let mut query: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && key != "page" && key != "per_page"
+        })
+        .collect();
+
+    let page = format!("page={}", page);
+    let per_page = format!("per_page={}", per_page);
+    query.push(&page);
+    query.push(&per_page);
+
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: PaginationLimits = PaginationLimits {
+        default_per_page: 20,
+        max_per_page: 100,
+    };
+
+    fn page(page: i64, per_page: i64) -> Pagination {
+        PaginationParams {
+            page: Some(page),
+            per_page: Some(per_page),
+        }
+        .clamp(LIMITS)
+    }
+
+    #[test]
+    fn clamp_fills_in_defaults() {
+        let pagination = PaginationParams::default().clamp(LIMITS);
+
+        assert_eq!((pagination.page(), pagination.per_page()), (1, 20));
+        assert_eq!(pagination.offset(), 0);
+    }
+
+    #[test]
+    fn clamp_bounds_the_requested_page() {
+        let pagination = page(0, 1000);
+        assert_eq!((pagination.page(), pagination.per_page()), (1, 100));
+
+        let pagination = page(3, 0);
+        assert_eq!((pagination.page(), pagination.per_page()), (3, 1));
+        assert_eq!(pagination.offset(), 2);
+    }
+
+    #[test]
+    fn response_of_a_middle_page_links_both_ways() {
+        let response = page(2, 10).response(vec![0; 10], 25);
+
+        assert_eq!(response.total_pages, 3);
+        assert!(response.has_next);
+        assert!(response.has_prev);
+    }
+
+    #[test]
+    fn response_of_the_last_page_has_no_next() {
+        let response = page(3, 10).response(vec![0; 5], 25);
+
+        assert_eq!((response.page, response.per_page), (3, 10));
+        assert!(!response.has_next);
+        assert!(response.has_prev);
+    }
+
+    #[test]
+    fn response_without_items_has_no_pages() {
+        let response = page(1, 10).response(Vec::<i32>::new(), 0);
+
+        assert_eq!(response.total_pages, 0);
+        assert!(!response.has_next);
+        assert!(!response.has_prev);
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use medbook_core::{
+    aliases::DieselError,
+    app_error::{AppError, StdResponse},
+    app_state::AppState,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{
+    api::products::get_products,
+    db,
+    extract::ValidatedPath,
+    middleware,
+    models::{CartEntity, CartItemEntity},
+    money::FormatParams,
+    routes::patients::carts::GetCartRes,
+    schema::{cart_items, carts},
+};
+
+/// Defines admin routes for inspecting carts on behalf of support.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
+        "/admin/carts",
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(get_cart))
+            .route_layer(axum::middleware::from_fn(middleware::admins_authorization)),
+    )
+}
+
+#[derive(Serialize, ToSchema)]
+struct AdminCartRes {
+    /// Patient owning the cart, `null` for guest carts that have not been claimed yet
+    pub patient_id: Option<i32>,
+    #[serde(flatten)]
+    pub cart: GetCartRes,
+}
+
+/// Get any cart with its items and total, whoever owns it, e.g. to diagnose a patient's issue.
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tags = ["Carts"],
+    params(
+        ("id" = i32, Path, description = "Cart ID to fetch"),
+        FormatParams
+    ),
+    responses(
+        (status = 200, description = "Get cart successfully", body = StdResponse<AdminCartRes, String>),
+        (status = 404, description = "Cart not found")
+    )
+)]
+async fn get_cart(
+    ValidatedPath(id): ValidatedPath<i32>,
+    Query(format): Query<FormatParams>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let cart: CartEntity =
+        carts::table
+            .find(id)
+            .get_result(conn)
+            .await
+            .map_err(|err| match err {
+                DieselError::NotFound => AppError::NotFound,
+                _ => AppError::Other(err.into()),
+            })?;
+
+    let cart_items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq(cart.id))
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+
+    let cart_item_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(state.http_client, cart_item_ids).await?;
+
+    Ok(StdResponse {
+        data: Some(AdminCartRes {
+            patient_id: cart.patient_id,
+            cart: GetCartRes::new(cart, cart_items, &products).with_format(format),
+        }),
+        message: Some("Get cart successfully"),
+    })
+}
@@ -0,0 +1,5 @@
+pub mod carts;
+pub mod orders;
+pub mod outbox;
+pub mod patients;
+pub mod webhooks;
@@ -0,0 +1,192 @@
+use anyhow::Context;
+use axum::{extract::State, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl, Queryable};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use medbook_core::{
+    aliases::DieselError,
+    app_error::{AppError, StdResponse},
+    app_state::AppState,
+};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{
+    billing, db,
+    extract::ValidatedPath,
+    middleware,
+    models::OrderEntity,
+    schema::{orders, outbox},
+};
+
+/// Defines admin routes for recovering stuck orders.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
+        "/admin/orders",
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(resend_delivery_request))
+            .routes(utoipa_axum::routes!(get_order_events))
+            .route_layer(axum::middleware::from_fn(middleware::admins_authorization)),
+    )
+}
+
+/// Publish the delivery request of a paid order again, e.g. when it was lost and the order is
+/// stuck in DELIVERY_PENDING.
+///
+/// Only orders DeliveryService has not created a delivery for yet can be resent, so a delivery is
+/// never requested twice.
+#[utoipa::path(
+    post,
+    path = "/{id}/resend-delivery-request",
+    tags = ["Orders"],
+    params(
+        ("id" = i32, Path, description = "Order ID to resend the delivery request of")
+    ),
+    responses(
+        (status = 200, description = "Resent delivery request successfully", body = StdResponse<OrderEntity, String>),
+        (status = 404, description = "Order not found"),
+        (status = 409, description = "Order is not DELIVERY_PENDING or already has a delivery")
+    )
+)]
+async fn resend_delivery_request(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let order = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let order: OrderEntity = orders::table
+                    .find(id)
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                if order.status != "DELIVERY_PENDING" {
+                    return Err(AppError::Conflict(format!(
+                        "Order in {} status is not waiting for a delivery",
+                        order.status
+                    )));
+                }
+                if let Some(delivery_id) = order.delivery_id {
+                    return Err(AppError::Conflict(format!(
+                        "Order already has delivery {}",
+                        delivery_id
+                    )));
+                }
+
+                billing::publish_delivery_request(conn, &order).await?;
+
+                Ok::<OrderEntity, AppError>(order)
+            })
+        })
+        .await?;
+
+    tracing::warn!(order_id = order.id, "Manually resent delivery request");
+
+    Ok(StdResponse {
+        data: Some(order),
+        message: Some("Resent delivery request successfully"),
+    })
+}
+
+/// An outbox row published about an order.
+#[derive(Queryable)]
+struct OutboxRow {
+    id: i32,
+    event_type: String,
+    payload: String,
+    status: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OrderEventRes {
+    pub id: i32,
+    /// Routing key the event was published with
+    pub event_type: String,
+    /// Event body as it was sent, or the raw text if it is not valid JSON
+    pub payload: Value,
+    /// Delivery status of the outbox row
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<OutboxRow> for OrderEventRes {
+    fn from(row: OutboxRow) -> Self {
+        Self {
+            payload: serde_json::from_str(&row.payload).unwrap_or(Value::String(row.payload)),
+            id: row.id,
+            event_type: row.event_type,
+            status: row.status,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// List the outbox events published about an order, oldest first, e.g. to check exactly which
+/// reserve request was sent to InventoryService.
+///
+/// Events are only listed as long as the outbox keeps them.
+#[utoipa::path(
+    get,
+    path = "/{id}/events",
+    tags = ["Orders"],
+    params(
+        ("id" = i32, Path, description = "Order ID whose events to list")
+    ),
+    responses(
+        (status = 200, description = "Get order events successfully", body = StdResponse<Vec<OrderEventRes>, String>),
+        (status = 404, description = "Order not found")
+    )
+)]
+async fn get_order_events(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let order_id: i32 = orders::table
+        .find(id)
+        .select(orders::id)
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+
+    let rows: Vec<OutboxRow> = outbox::table
+        .filter(outbox::order_id.eq(order_id))
+        .order_by((outbox::created_at.asc(), outbox::id.asc()))
+        .select((
+            outbox::id,
+            outbox::event_type,
+            outbox::payload,
+            outbox::status,
+            outbox::created_at,
+            outbox::updated_at,
+        ))
+        .get_results(conn)
+        .await
+        .context("Failed to get order events")?;
+
+    Ok(StdResponse {
+        data: Some(
+            rows.into_iter()
+                .map(OrderEventRes::from)
+                .collect::<Vec<_>>(),
+        ),
+        message: Some("Get order events successfully"),
+    })
+}
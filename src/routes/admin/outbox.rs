@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use axum::{extract::State, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use diesel::{
+    ExpressionMethods, QueryDsl,
+    dsl::{count_star, max, min},
+};
+use diesel_async::RunQueryDsl;
+use medbook_core::{
+    app_error::{AppError, StdResponse},
+    app_state::AppState,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{db, middleware, schema::outbox};
+
+/// Status the `medbook_core` relay gives outbox events once they are published to RabbitMQ.
+const RELAYED_STATUS: &str = "SENT";
+
+/// Defines admin routes for inspecting the outbox.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
+        "/admin/outbox",
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(get_outbox_stats))
+            .route_layer(axum::middleware::from_fn(middleware::admins_authorization)),
+    )
+}
+
+#[derive(Serialize, ToSchema)]
+struct OutboxStatsRes {
+    /// Number of outbox events per status, e.g. PENDING, SENT or FAILED
+    pub counts_by_status: HashMap<String, i64>,
+    /// When the oldest event still waiting to be relayed was published, `null` if none is
+    pub oldest_pending_at: Option<DateTime<Utc>>,
+    /// How long the oldest pending event has been waiting, in seconds
+    pub oldest_pending_age_secs: Option<i64>,
+    /// When the most recent relayed event still in the outbox was published. `null` if none is, so
+    /// once relayed events are pruned this falls back to an older time or `null` even while the
+    /// relay is healthy; check `counts_by_status` and `oldest_pending_age_secs` in that case
+    pub last_relayed_at: Option<DateTime<Utc>>,
+}
+
+/// Get the state of the outbox backlog, to diagnose events not reaching other services.
+///
+/// Complements the Prometheus metrics with when things last moved. Relayed events pruned from the
+/// outbox are neither counted nor considered for `last_relayed_at`. The lookups by status use
+/// `outbox_status_updated_at_idx`.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tags = ["Metrics"],
+    responses(
+        (status = 200, description = "Get outbox stats successfully", body = StdResponse<OutboxStatsRes, String>)
+    )
+)]
+async fn get_outbox_stats(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let counts_by_status: HashMap<String, i64> = outbox::table
+        .group_by(outbox::status)
+        .select((outbox::status, count_star()))
+        .get_results::<(String, i64)>(conn)
+        .await
+        .context("Failed to count outbox events")?
+        .into_iter()
+        .collect();
+
+    let oldest_pending_at: Option<DateTime<Utc>> = outbox::table
+        .filter(outbox::status.eq("PENDING"))
+        .select(min(outbox::created_at))
+        .get_result(conn)
+        .await
+        .context("Failed to get oldest pending outbox event")?;
+
+    let last_relayed_at: Option<DateTime<Utc>> = outbox::table
+        .filter(outbox::status.eq(RELAYED_STATUS))
+        .select(max(outbox::updated_at))
+        .get_result(conn)
+        .await
+        .context("Failed to get last relayed outbox event")?;
+
+    Ok(StdResponse {
+        data: Some(OutboxStatsRes {
+            counts_by_status,
+            oldest_pending_age_secs: oldest_pending_at
+                .map(|created_at| (Utc::now() - created_at).num_seconds().max(0)),
+            oldest_pending_at,
+            last_relayed_at,
+        }),
+        message: Some("Get outbox stats successfully"),
+    })
+}
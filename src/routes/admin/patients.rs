@@ -0,0 +1,107 @@
+use anyhow::Context;
+use axum::{extract::State, response::IntoResponse};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use medbook_core::{
+    app_error::{AppError, StdResponse},
+    app_state::AppState,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{
+    db, extract::ValidatedPath, middleware, models::OrderEntity,
+    routes::patients::orders::cancel_patient_order, schema::orders,
+};
+
+/// Statuses of orders that are already cancelled or never went through, so they are not reported.
+const ALREADY_CANCELLED_STATUSES: &[&str] = &["CANCELLED", "CANCEL_PENDING", "REJECTED"];
+
+/// Defines admin routes acting on behalf of a patient.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
+        "/admin/patients",
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(cancel_patient_orders))
+            .route_layer(axum::middleware::from_fn(middleware::admins_authorization)),
+    )
+}
+
+#[derive(Serialize, ToSchema)]
+struct SkippedOrder {
+    pub order_id: i32,
+    pub status: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CancelPatientOrdersRes {
+    pub cancelled_count: usize,
+    pub cancelled_orders: Vec<OrderEntity>,
+    pub skipped_orders: Vec<SkippedOrder>,
+}
+
+/// Cancel every in-flight order of a patient, e.g. when offboarding them.
+///
+/// Orders are cancelled the same way a patient would cancel them. Orders that can no longer be
+/// cancelled are reported back instead. Either all cancellations are applied or none are.
+#[utoipa::path(
+    post,
+    path = "/{patient_id}/cancel-orders",
+    tags = ["Orders"],
+    params(
+        ("patient_id" = i32, Path, description = "Patient whose orders should be cancelled")
+    ),
+    responses(
+        (status = 200, description = "Cancelled patient orders successfully", body = StdResponse<CancelPatientOrdersRes, String>)
+    )
+)]
+async fn cancel_patient_orders(
+    ValidatedPath(patient_id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let result = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let candidates: Vec<OrderEntity> = orders::table
+                    .filter(orders::patient_id.eq(patient_id))
+                    .filter(orders::deleted_at.is_null())
+                    .filter(orders::status.ne_all(ALREADY_CANCELLED_STATUSES))
+                    .order_by(orders::id.asc())
+                    .for_update()
+                    .get_results(conn)
+                    .await
+                    .context("Failed to get patient orders")?;
+
+                let mut cancelled_orders = Vec::new();
+                let mut skipped_orders = Vec::new();
+                for order in candidates {
+                    // cancel_patient_order decides which statuses can still be cancelled.
+                    match cancel_patient_order(conn, order.id, patient_id).await {
+                        Ok(cancelled_order) => cancelled_orders.push(cancelled_order),
+                        Err(AppError::NotFound) => skipped_orders.push(SkippedOrder {
+                            order_id: order.id,
+                            reason: format!("Order in {} status cannot be cancelled", order.status),
+                            status: order.status,
+                        }),
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Ok::<CancelPatientOrdersRes, AppError>(CancelPatientOrdersRes {
+                    cancelled_count: cancelled_orders.len(),
+                    cancelled_orders,
+                    skipped_orders,
+                })
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(result),
+        message: Some("Cancelled patient orders successfully"),
+    })
+}
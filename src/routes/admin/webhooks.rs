@@ -0,0 +1,160 @@
+use anyhow::Context;
+use axum::{Json, extract::State, response::IntoResponse};
+use diesel::{QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::RunQueryDsl;
+use medbook_core::{
+    aliases::DieselError,
+    app_error::{AppError, StdResponse},
+    app_state::AppState,
+};
+use serde::Deserialize;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{
+    db,
+    extract::ValidatedPath,
+    middleware,
+    models::{CreateOrderWebhookEntity, OrderWebhookEntity},
+    schema::order_webhooks,
+};
+
+/// Defines admin routes for managing order status webhooks.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
+        "/admin/webhooks",
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(get_webhooks))
+            .routes(utoipa_axum::routes!(register_webhook))
+            .routes(utoipa_axum::routes!(unregister_webhook))
+            .route_layer(axum::middleware::from_fn(middleware::admins_authorization)),
+    )
+}
+
+/// List all registered order webhooks.
+#[utoipa::path(
+    get,
+    path = "/",
+    tags = ["Webhooks"],
+    responses(
+        (status = 200, description = "List all webhooks", body = StdResponse<Vec<OrderWebhookEntity>, String>)
+    )
+)]
+async fn get_webhooks(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let webhooks: Vec<OrderWebhookEntity> = order_webhooks::table
+        .get_results(conn)
+        .await
+        .context("Failed to get webhooks")?;
+
+    Ok(StdResponse {
+        data: Some(webhooks),
+        message: Some("Get webhooks successfully"),
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RegisterWebhookReq {
+    /// Absolute http(s) URL the status changes are POSTed to
+    pub url: String,
+    /// Statuses to be notified about. Omit or leave empty to receive every status change.
+    pub events: Option<Vec<String>>,
+    /// Shared secret used to sign each request body with HMAC-SHA256
+    pub secret: String,
+}
+
+/// Register a webhook to be called whenever an order changes status.
+#[utoipa::path(
+    post,
+    path = "/",
+    tags = ["Webhooks"],
+    request_body = RegisterWebhookReq,
+    responses(
+        (status = 200, description = "Registered webhook successfully", body = StdResponse<OrderWebhookEntity, String>)
+    )
+)]
+async fn register_webhook(
+    State(state): State<AppState>,
+    Json(body): Json<RegisterWebhookReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let url = reqwest::Url::parse(&body.url)
+        .map_err(|_| AppError::BadRequest(format!("{} is not a valid URL", body.url)))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(AppError::BadRequest(
+            "Webhook URL must use http or https".into(),
+        ));
+    }
+
+    if body.secret.is_empty() {
+        return Err(AppError::BadRequest(
+            "Webhook secret must not be empty".into(),
+        ));
+    }
+
+    let events: Vec<String> = body
+        .events
+        .unwrap_or_default()
+        .into_iter()
+        .map(|event| event.trim().to_uppercase())
+        .filter(|event| !event.is_empty())
+        .collect();
+    let event_mask = if events.is_empty() {
+        "*".to_string()
+    } else {
+        events.join(",")
+    };
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let webhook = diesel::insert_into(order_webhooks::table)
+        .values(CreateOrderWebhookEntity {
+            url: url.to_string(),
+            event_mask,
+            secret: body.secret,
+        })
+        .returning(OrderWebhookEntity::as_returning())
+        .get_result(conn)
+        .await
+        .context("Failed to register webhook")?;
+
+    Ok(StdResponse {
+        data: Some(webhook),
+        message: Some("Registered webhook successfully"),
+    })
+}
+
+/// Unregister a webhook. Pending deliveries for it are dropped.
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    tags = ["Webhooks"],
+    params(
+        ("id" = i32, Path, description = "Webhook ID to unregister")
+    ),
+    responses(
+        (status = 200, description = "Unregistered webhook successfully", body = StdResponse<OrderWebhookEntity, String>)
+    )
+)]
+async fn unregister_webhook(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let webhook: QueryResult<OrderWebhookEntity> = diesel::delete(order_webhooks::table.find(id))
+        .returning(OrderWebhookEntity::as_returning())
+        .get_result(conn)
+        .await;
+
+    match webhook {
+        Ok(webhook) => Ok(StdResponse {
+            data: Some(webhook),
+            message: Some("Unregistered webhook successfully"),
+        }),
+        Err(err) => match err {
+            DieselError::NotFound => Err(AppError::NotFound),
+            _ => Err(AppError::Other(err.into())),
+        },
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use medbook_core::{
+    aliases::DieselError,
+    app_error::{AppError, StdResponse},
+    app_state::AppState,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use uuid::Uuid;
+
+use crate::{
+    api::products::get_products,
+    db,
+    extract::ValidatedJson,
+    middleware::json_body_guard,
+    models::{CartEntity, CartItemEntity, CreateGuestCartEntity},
+    money::FormatParams,
+    routes::patients::carts::{
+        CreateCartReq, GetCartRes, UpdateCartRes, aggregate_cart_items, ensure_within_cart_limit,
+        insert_cart_items, last_seen_prices, replace_cart_items,
+    },
+    schema::{cart_items, carts},
+};
+
+/// Header carrying the session token of a guest cart.
+pub const SESSION_TOKEN_HEADER: &str = "x-cart-session";
+
+/// Defines the cart routes of guests who have not logged in yet. They are authorized by the
+/// session token of their cart instead of a patient login.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
+        "/guest/carts",
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(create_guest_cart))
+            .routes(utoipa_axum::routes!(get_guest_cart))
+            .routes(utoipa_axum::routes!(update_guest_cart))
+            .route_layer(axum::middleware::from_fn(json_body_guard)),
+    )
+}
+
+/// Reads the session token from [`SESSION_TOKEN_HEADER`].
+fn session_token(headers: &HeaderMap) -> Result<Uuid, AppError> {
+    let value = headers
+        .get(SESSION_TOKEN_HEADER)
+        .ok_or_else(|| AppError::BadRequest(format!("Missing {} header", SESSION_TOKEN_HEADER)))?;
+
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| Uuid::parse_str(value.trim()).ok())
+        .ok_or_else(|| AppError::BadRequest(format!("{} must be a UUID", SESSION_TOKEN_HEADER)))
+}
+
+/// Loads the unclaimed guest cart with `session_token`.
+async fn find_guest_cart(
+    conn: &mut AsyncPgConnection,
+    session_token: Uuid,
+) -> Result<CartEntity, AppError> {
+    carts::table
+        .filter(carts::session_token.eq(session_token))
+        .filter(carts::patient_id.is_null())
+        .for_update()
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })
+}
+
+#[derive(Serialize, ToSchema)]
+struct CreateGuestCartRes {
+    /// Send this back in the `X-Cart-Session` header to access the cart later
+    pub session_token: Uuid,
+    pub cart: CartEntity,
+    pub cart_items: Vec<CartItemEntity>,
+}
+
+/// Create a cart for a guest. The returned session token is the only way to access it until a
+/// patient claims it through `POST /patients/carts/claim`.
+#[utoipa::path(
+    post,
+    path = "/",
+    tags = ["Carts"],
+    request_body = CreateCartReq,
+    responses(
+        (status = 200, description = "Created guest cart successfully", body = StdResponse<CreateGuestCartRes, String>)
+    )
+)]
+async fn create_guest_cart(
+    State(state): State<AppState>,
+    ValidatedJson(body): ValidatedJson<CreateCartReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let items = aggregate_cart_items(&body.cart_items)?;
+    if items.is_empty() {
+        return Err(AppError::BadRequest(
+            "Cart must contain at least one item with a positive quantity".into(),
+        ));
+    }
+    ensure_within_cart_limit(items.len())?;
+
+    let product_ids = items.iter().map(|(product_id, _)| *product_id).collect();
+    let unit_prices = last_seen_prices(state.http_client, product_ids).await;
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let session_token = Uuid::new_v4();
+    let (cart, cart_items) = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let cart: CartEntity = diesel::insert_into(carts::table)
+                    .values(CreateGuestCartEntity { session_token })
+                    .returning(CartEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to create cart")?;
+
+                let cart_items = insert_cart_items(conn, cart.id, items, &unit_prices).await?;
+
+                Ok::<(CartEntity, Vec<CartItemEntity>), anyhow::Error>((cart, cart_items))
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(CreateGuestCartRes {
+            session_token,
+            cart,
+            cart_items,
+        }),
+        message: Some("Created guest cart successfully"),
+    })
+}
+
+/// Get the guest cart of the session token in `X-Cart-Session`.
+#[utoipa::path(
+    get,
+    path = "/",
+    tags = ["Carts"],
+    params(
+        ("x-cart-session" = Uuid, Header, description = "Session token of the guest cart"),
+        FormatParams
+    ),
+    responses(
+        (status = 200, description = "Get guest cart successfully", body = StdResponse<GetCartRes, String>),
+        (status = 404, description = "No unclaimed guest cart has this session token")
+    )
+)]
+async fn get_guest_cart(
+    Query(format): Query<FormatParams>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let session_token = session_token(&headers)?;
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let cart: CartEntity = carts::table
+        .filter(carts::session_token.eq(session_token))
+        .filter(carts::patient_id.is_null())
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+
+    let cart_items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq(cart.id))
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+
+    let cart_item_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(state.http_client, cart_item_ids).await?;
+
+    Ok(StdResponse {
+        data: Some(GetCartRes::new(cart, cart_items, &products).with_format(format)),
+        message: Some("Get guest cart successfully"),
+    })
+}
+
+/// Replace the contents of the guest cart of the session token in `X-Cart-Session`.
+#[utoipa::path(
+    patch,
+    path = "/",
+    tags = ["Carts"],
+    params(
+        ("x-cart-session" = Uuid, Header, description = "Session token of the guest cart")
+    ),
+    request_body = CreateCartReq,
+    responses(
+        (status = 200, description = "Updated guest cart successfully", body = StdResponse<UpdateCartRes, String>),
+        (status = 404, description = "No unclaimed guest cart has this session token")
+    )
+)]
+async fn update_guest_cart(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidatedJson(body): ValidatedJson<CreateCartReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let session_token = session_token(&headers)?;
+
+    let distinct_items: HashSet<i32> = body.cart_items.iter().map(|item| item.product_id).collect();
+    ensure_within_cart_limit(distinct_items.len())?;
+
+    let unit_prices =
+        last_seen_prices(state.http_client, distinct_items.into_iter().collect()).await;
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let updated = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let cart = find_guest_cart(conn, session_token).await?;
+                Ok::<UpdateCartRes, AppError>(
+                    replace_cart_items(conn, cart.id, &body.cart_items, &unit_prices).await?,
+                )
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(updated),
+        message: Some("Updated guest cart successfully"),
+    })
+}
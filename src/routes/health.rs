@@ -0,0 +1,54 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use diesel_async::RunQueryDsl;
+use medbook_core::{app_error::StdResponse, app_state::AppState};
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{consumers, db, middleware::error_response};
+
+/// Defines the readiness probe route.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(utoipa_axum::routes!(get_ready))
+}
+
+/// Readiness probe for the orchestrator.
+///
+/// Migrations have already run by the time the HTTP server accepts requests, so this checks that
+/// the consumers are running on a broker connection and that a database connection can be checked
+/// out and used.
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tags = ["Health"],
+    responses(
+        (status = 200, description = "Service is ready", body = StdResponse<(), String>),
+        (status = 503, description = "Consumers or database are unreachable", body = StdResponse<(), String>)
+    )
+)]
+async fn get_ready(State(state): State<AppState>) -> Response {
+    if !consumers::is_connected() {
+        return not_ready("Consumers are not connected to RabbitMQ");
+    }
+
+    let conn = &mut match db::acquire(&state.db_pool).await {
+        Ok(conn) => conn,
+        Err(_) => return not_ready("No database connection is available"),
+    };
+    if let Err(err) = diesel::sql_query("SELECT 1").execute(conn).await {
+        tracing::warn!("Readiness check query failed: {:?}", err);
+        return not_ready("Database did not answer");
+    }
+
+    StdResponse::<(), &str> {
+        data: None,
+        message: Some("Ready"),
+    }
+    .into_response()
+}
+
+fn not_ready(reason: &str) -> Response {
+    error_response(StatusCode::SERVICE_UNAVAILABLE, reason.into())
+}
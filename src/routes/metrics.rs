@@ -0,0 +1,86 @@
+use std::fmt::Write;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use medbook_core::app_state::AppState;
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{api::circuit_breaker, consumers};
+
+/// Defines the metrics scrape route.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(utoipa_axum::routes!(get_metrics))
+}
+
+/// Current service gauges in the Prometheus text exposition format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tags = ["Metrics"],
+    responses(
+        (status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain")
+    )
+)]
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let pool = state.db_pool.state();
+    let in_use = pool.connections.saturating_sub(pool.idle_connections);
+
+    let mut body = String::new();
+    for (name, help, value) in [
+        (
+            "orderservice_db_pool_connections",
+            "Open connections in the DB pool",
+            pool.connections,
+        ),
+        (
+            "orderservice_db_pool_connections_in_use",
+            "DB pool connections currently checked out",
+            in_use,
+        ),
+        (
+            "orderservice_db_pool_connections_idle",
+            "DB pool connections available for checkout",
+            pool.idle_connections,
+        ),
+    ] {
+        let _ = writeln!(body, "# HELP {name} {help}");
+        let _ = writeln!(body, "# TYPE {name} gauge");
+        let _ = writeln!(body, "{name} {value}");
+    }
+
+    let name = "orderservice_circuit_breaker_state";
+    let _ = writeln!(
+        body,
+        "# HELP {name} Circuit breaker state per upstream service (0 closed, 1 open, 2 half-open)"
+    );
+    let _ = writeln!(body, "# TYPE {name} gauge");
+    for breaker in circuit_breaker::BREAKERS {
+        let _ = writeln!(
+            body,
+            "{name}{{service=\"{}\"}} {}",
+            breaker.service(),
+            breaker.state() as u8
+        );
+    }
+
+    let name = "orderservice_consumer_handler_timeouts_total";
+    let _ = writeln!(
+        body,
+        "# HELP {name} Consumer handlers that timed out and had their message requeued"
+    );
+    let _ = writeln!(body, "# TYPE {name} counter");
+    for (routing_key, count) in consumers::timed_out_handlers() {
+        let _ = writeln!(body, "{name}{{routing_key=\"{routing_key}\"}} {count}");
+    }
+
+    let name = "orderservice_consumer_unparseable_messages_total";
+    let _ = writeln!(
+        body,
+        "# HELP {name} Consumed messages whose payload did not match the expected event"
+    );
+    let _ = writeln!(body, "# TYPE {name} counter");
+    for (routing_key, count) in consumers::unparseable_messages() {
+        let _ = writeln!(body, "{name}{{routing_key=\"{routing_key}\"}} {count}");
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
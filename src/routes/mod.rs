@@ -1,3 +1,7 @@
+pub mod admin;
+pub mod guests;
+pub mod health;
+pub mod metrics;
 pub mod orders;
 pub mod patients;
 pub mod payments;
@@ -1,39 +1,107 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, pin::pin};
 
 use anyhow::{Context, Result};
 use axum::{
-    extract::{Path, State},
+    Json,
+    body::Body,
+    extract::{OriginalUri, Query, State},
+    http::header,
     response::IntoResponse,
 };
-use diesel::{ExpressionMethods, QueryDsl, QueryResult};
-use diesel_async::RunQueryDsl;
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, Queryable, dsl::sum, pg::Pg};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures::StreamExt;
 use medbook_core::app_error::StdResponse;
 use medbook_core::{aliases::DieselError, app_error::AppError, app_state::AppState};
-use utoipa::ToSchema;
+use tokio::sync::mpsc;
+use utoipa::{IntoParams, ToSchema};
 use utoipa_axum::router::OpenApiRouter;
+use uuid::Uuid;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    api::products::get_product_unit_prices,
-    models::{CartItemEntity, OrderEntity},
-    schema::{cart_items, orders},
+    api::products::get_products,
+    billing, db,
+    events::OrderStatusChangedEvent,
+    extract::ValidatedPath,
+    middleware,
+    models::{OrderEntity, OrderItemEntity, PaymentEntity},
+    money, order_items,
+    pagination::{PaginatedResponse, PaginationParams},
+    routing_keys,
+    schema::{self, orders},
+    settings::Settings,
 };
 
+/// Number of orders priced and written per round trip while exporting.
+const EXPORT_BATCH_SIZE: usize = 500;
+/// Maximum number of orders that can be looked up in one status batch.
+const MAX_STATUS_BATCH_SIZE: usize = 500;
+/// Statuses of orders that will never be fulfilled, so they don't count as demand.
+const UNFULFILLED_STATUSES: &[&str] = &["CANCELLED", "CANCEL_PENDING", "REJECTED", "EXPIRED"];
+
 pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
     utoipa_axum::router::OpenApiRouter::new().nest(
         "/orders",
         OpenApiRouter::new()
             .routes(utoipa_axum::routes!(get_order))
-            .routes(utoipa_axum::routes!(get_orders)),
+            .routes(utoipa_axum::routes!(get_order_by_delivery))
+            .routes(utoipa_axum::routes!(get_orders))
+            .routes(utoipa_axum::routes!(get_order_statuses))
+            .merge(
+                OpenApiRouter::new()
+                    .routes(utoipa_axum::routes!(export_orders_csv))
+                    .routes(utoipa_axum::routes!(get_product_demand))
+                    .routes(utoipa_axum::routes!(get_order_timeline))
+                    .route_layer(axum::middleware::from_fn(middleware::admins_authorization)),
+            ),
     )
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OrderFilters {
+    /// Only include orders with this status
+    pub status: Option<String>,
+    /// Only include orders created at or after this instant
+    pub from: Option<DateTime<Utc>>,
+    /// Only include orders created at or before this instant
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Builds the order listing query shared by the JSON list and the CSV export.
+fn filtered_orders(filters: &OrderFilters) -> orders::BoxedQuery<'static, Pg> {
+    matching_orders(filters).order_by((orders::updated_at.desc(), orders::id.desc()))
+}
+
+/// Orders matching `filters`, unordered so they can be counted.
+fn matching_orders(filters: &OrderFilters) -> orders::BoxedQuery<'static, Pg> {
+    let mut query = orders::table.into_boxed();
+
+    if let Some(status) = &filters.status {
+        query = query.filter(orders::status.eq(status.clone()));
+    }
+    if let Some(from) = filters.from {
+        query = query.filter(orders::created_at.ge(from));
+    }
+    if let Some(to) = filters.to {
+        query = query.filter(orders::created_at.le(to));
+    }
+
+    query
+}
+
 #[derive(Serialize, ToSchema)]
 struct GetOrderRes {
     pub order: OrderEntity,
-    pub order_items: Vec<CartItemEntity>,
+    pub order_items: Vec<OrderItemEntity>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
     pub total_price: f32,
+    /// ISO 4217 currency of `total_price`
+    pub currency: String,
 }
 
 /// Fetch a specific order.
@@ -49,14 +117,10 @@ struct GetOrderRes {
     )
 )]
 async fn get_order(
-    Path(id): Path<i32>,
+    ValidatedPath(id): ValidatedPath<i32>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let order: QueryResult<OrderEntity> = orders::table.find(id).get_result(conn).await;
 
@@ -68,21 +132,59 @@ async fn get_order(
     }
 
     let order = order.unwrap();
-    let order_items: Vec<CartItemEntity> = cart_items::table
-        .filter(cart_items::cart_id.eq(order.cart_id))
-        .get_results(conn)
+    let order_items = order_items::load(conn, state.http_client, std::slice::from_ref(&order))
+        .await?
+        .remove(&order.id)
+        .unwrap_or_default();
+    let total_price = billing::items_total(&order_items);
+
+    Ok(StdResponse {
+        data: Some(GetOrderRes {
+            currency: order.currency.clone(),
+            order,
+            order_items,
+            total_price,
+        }),
+        message: Some("Get order successfully"),
+    })
+}
+
+/// Fetch the order a delivery was created for.
+#[utoipa::path(
+    get,
+    path = "/by-delivery/{delivery_id}",
+    tags = ["Orders"],
+    params(
+        ("delivery_id" = Uuid, Path, description = "Delivery ID assigned by DeliveryService")
+    ),
+    responses(
+        (status = 200, description = "Get order successfully", body = StdResponse<GetOrderRes, String>)
+    )
+)]
+async fn get_order_by_delivery(
+    ValidatedPath(delivery_id): ValidatedPath<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let order: OrderEntity = orders::table
+        .filter(orders::delivery_id.eq(delivery_id))
+        .get_result(conn)
         .await
-        .context("Failed to get order items")?;
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
 
-    let cart_item_ids = order_items.iter().map(|item| item.product_id).collect();
-    let unit_prices = get_product_unit_prices(state.http_client, cart_item_ids).await?;
-    let total_price: f32 = order_items
-        .iter()
-        .map(|item| unit_prices.get(&item.product_id).copied().unwrap_or(0.0))
-        .sum();
+    let order_items = order_items::load(conn, state.http_client, std::slice::from_ref(&order))
+        .await?
+        .remove(&order.id)
+        .unwrap_or_default();
+    let total_price = billing::items_total(&order_items);
 
     Ok(StdResponse {
         data: Some(GetOrderRes {
+            currency: order.currency.clone(),
             order,
             order_items,
             total_price,
@@ -91,52 +193,47 @@ async fn get_order(
     })
 }
 
-/// Fetch all orders belonging to the authenticated patient.
+/// Fetch all orders, optionally filtered by status and creation date.
 #[utoipa::path(
     get,
     path = "/",
     tags = ["Orders"],
+    params(OrderFilters, PaginationParams),
     responses(
-        (status = 200, description = "List my orders", body = StdResponse<Vec<GetOrderRes>, String>)
+        (status = 200, description = "List my orders", body = StdResponse<PaginatedResponse<GetOrderRes>, String>)
     )
 )]
-async fn get_orders(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+async fn get_orders(
+    Query(filters): Query<OrderFilters>,
+    Query(pagination): Query<PaginationParams>,
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+) -> Result<impl IntoResponse, AppError> {
+    let pagination = pagination.clamp(Settings::get_pagination_limits());
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
-    let orders: Vec<OrderEntity> = orders::table
-        .order_by(orders::updated_at.desc())
+    let orders: Vec<OrderEntity> = filtered_orders(&filters)
+        .limit(pagination.per_page())
+        .offset(pagination.offset())
         .get_results(conn)
         .await
         .context("Failed to get my orders")?;
 
-    let cart_ids: Vec<i32> = orders.iter().map(|order| order.cart_id).collect();
-    let order_items: Vec<CartItemEntity> = cart_items::table
-        .filter(cart_items::cart_id.eq_any(&cart_ids))
-        .get_results(conn)
+    let total_count: i64 = matching_orders(&filters)
+        .count()
+        .get_result(conn)
         .await
-        .context("Failed to get cart items")?;
+        .context("Failed to count orders")?;
 
-    let cart_item_ids = order_items.iter().map(|item| item.product_id).collect();
-    let unit_prices = get_product_unit_prices(state.http_client, cart_item_ids).await?;
-
-    let mut group: HashMap<i32, Vec<CartItemEntity>> = HashMap::new();
-    for item in order_items {
-        group.entry(item.cart_id).or_default().push(item);
-    }
+    let mut group = order_items::load(conn, state.http_client, &orders).await?;
 
     let order_with_items: Vec<GetOrderRes> = orders
         .into_iter()
         .map(|order| {
-            let order_items = group.remove(&order.cart_id).unwrap_or_default();
-            let total_price: f32 = order_items
-                .iter()
-                .map(|item| unit_prices.get(&item.product_id).copied().unwrap_or(0.0))
-                .sum();
+            let order_items = group.remove(&order.id).unwrap_or_default();
+            let total_price = billing::items_total(&order_items);
             GetOrderRes {
+                currency: order.currency.clone(),
                 order_items,
                 order,
                 total_price,
@@ -144,8 +241,443 @@ async fn get_orders(State(state): State<AppState>) -> Result<impl IntoResponse,
         })
         .collect();
 
+    Ok((
+        pagination.headers(&uri, total_count),
+        StdResponse {
+            data: Some(pagination.response(order_with_items, total_count)),
+            message: Some("Get my orders successfully"),
+        },
+    ))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct GetOrderStatusesReq {
+    pub order_ids: Vec<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OrderStatusRes {
+    pub status: String,
+    pub delivery_id: Option<Uuid>,
+}
+
+/// Look up the status of many orders at once. Unknown order IDs are left out of the result.
+#[utoipa::path(
+    post,
+    path = "/status-batch",
+    tags = ["Orders"],
+    request_body = GetOrderStatusesReq,
+    responses(
+        (status = 200, description = "Get order statuses successfully", body = StdResponse<HashMap<i32, OrderStatusRes>, String>)
+    )
+)]
+async fn get_order_statuses(
+    State(state): State<AppState>,
+    Json(body): Json<GetOrderStatusesReq>,
+) -> Result<impl IntoResponse, AppError> {
+    if body.order_ids.len() > MAX_STATUS_BATCH_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "At most {} orders can be looked up at once",
+            MAX_STATUS_BATCH_SIZE
+        )));
+    }
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let rows: Vec<(i32, String, Option<Uuid>)> = orders::table
+        .filter(orders::id.eq_any(&body.order_ids))
+        .select((orders::id, orders::status, orders::delivery_id))
+        .get_results(conn)
+        .await
+        .context("Failed to get order statuses")?;
+
+    let statuses: HashMap<i32, OrderStatusRes> = rows
+        .into_iter()
+        .map(|(id, status, delivery_id)| {
+            (
+                id,
+                OrderStatusRes {
+                    status,
+                    delivery_id,
+                },
+            )
+        })
+        .collect();
+
     Ok(StdResponse {
-        data: Some(order_with_items),
-        message: Some("Get my orders successfully"),
+        data: Some(statuses),
+        message: Some("Get order statuses successfully"),
     })
 }
+
+/// Export orders as CSV (admin). Accepts the same filters as the JSON list.
+///
+/// Rows are streamed from the database in batches, so the response is never buffered in full.
+#[utoipa::path(
+    get,
+    path = "/export.csv",
+    tags = ["Orders"],
+    params(OrderFilters),
+    responses(
+        (status = 200, description = "CSV export of orders", body = String, content_type = "text/csv")
+    )
+)]
+async fn export_orders_csv(
+    Query(filters): Query<OrderFilters>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = db::acquire_owned(&state.db_pool).await?;
+
+    let (tx, rx) = mpsc::channel::<Result<String, std::io::Error>>(4);
+    tokio::spawn(async move {
+        if let Err(err) = write_orders_csv(&mut conn, &state, &filters, &tx).await {
+            tracing::error!("Failed to export orders: {:?}", err);
+            let _ = tx.send(Err(std::io::Error::other(err.to_string()))).await;
+        }
+    });
+
+    let body = Body::from_stream(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }));
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"orders.csv\"",
+            ),
+        ],
+        body,
+    ))
+}
+
+/// Streams filtered orders out of `conn` and sends them to `tx` as CSV chunks, one chunk per batch.
+/// Stops quietly if the client hangs up.
+async fn write_orders_csv(
+    conn: &mut AsyncPgConnection,
+    state: &AppState,
+    filters: &OrderFilters,
+    tx: &mpsc::Sender<Result<String, std::io::Error>>,
+) -> Result<()> {
+    let header = "order_id,patient_id,status,order_type,total,created_at\n".to_string();
+    if tx.send(Ok(header)).await.is_err() {
+        return Ok(());
+    }
+
+    let rows = filtered_orders(filters)
+        .load_stream::<OrderEntity>(conn)
+        .await
+        .context("Failed to stream orders")?;
+    let mut batches = pin!(rows.chunks(EXPORT_BATCH_SIZE));
+
+    while let Some(batch) = batches.next().await {
+        let orders = batch
+            .into_iter()
+            .collect::<QueryResult<Vec<OrderEntity>>>()
+            .context("Failed to read order row")?;
+
+        // The streaming connection is busy, so item lookups go through a separate one.
+        let items_conn = &mut db::acquire(&state.db_pool).await?;
+
+        let order_items = order_items::load(items_conn, state.http_client.clone(), &orders).await?;
+
+        let mut totals: HashMap<i32, f32> = HashMap::new();
+        for item in order_items.values().flatten() {
+            *totals.entry(item.order_id).or_default() += item.line_total();
+        }
+
+        let chunk: String = orders
+            .iter()
+            .map(|order| {
+                csv_row(&[
+                    order.id.to_string(),
+                    order.patient_id.to_string(),
+                    order.status.clone(),
+                    order.order_type.clone(),
+                    money::amount_string(
+                        totals.get(&order.id).copied().unwrap_or(0.0),
+                        &order.currency,
+                    ),
+                    order.created_at.to_rfc3339(),
+                ])
+            })
+            .collect();
+
+        if tx.send(Ok(chunk)).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+/// One CSV record made of `fields`, terminated by a newline.
+fn csv_row(fields: &[String]) -> String {
+    let mut row = fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",");
+    row.push('\n');
+    row
+}
+
+/// Escapes `value` for a CSV field. Fields holding separators, quotes or line breaks are quoted,
+/// and text a spreadsheet would evaluate as a formula is prefixed with `'`.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ProductDemandParams {
+    /// Only count orders created at or after this instant. Defaults to all orders.
+    pub from: Option<DateTime<Utc>>,
+    /// Only count orders created at or before this instant. Defaults to now.
+    pub to: Option<DateTime<Utc>>,
+    /// Look up product names in InventoryService
+    #[serde(default)]
+    pub include_names: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ProductDemand {
+    pub product_id: i32,
+    pub total_quantity: i64,
+    /// Only set when `include_names` was requested
+    pub product_name: Option<String>,
+}
+
+/// Total quantity ordered per product over a window (admin), for inventory planning.
+///
+/// Cancelled, rejected and expired orders are left out. Orders whose items have not been
+/// backfilled are not counted, see the `backfill-order-items` command.
+#[utoipa::path(
+    get,
+    path = "/product-demand",
+    tags = ["Orders"],
+    params(ProductDemandParams),
+    responses(
+        (status = 200, description = "Get product demand successfully", body = StdResponse<Vec<ProductDemand>, String>)
+    )
+)]
+async fn get_product_demand(
+    Query(params): Query<ProductDemandParams>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    use schema::order_items;
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let totals: Vec<(i32, Option<i64>)> = order_items::table
+        .inner_join(orders::table)
+        .filter(orders::status.ne_all(UNFULFILLED_STATUSES))
+        .filter(orders::created_at.ge(params.from.unwrap_or(DateTime::UNIX_EPOCH)))
+        .filter(orders::created_at.le(params.to.unwrap_or_else(Utc::now)))
+        .group_by(order_items::product_id)
+        .select((order_items::product_id, sum(order_items::quantity)))
+        .order_by(order_items::product_id.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get product demand")?;
+
+    let products = if params.include_names {
+        get_products(
+            state.http_client,
+            totals.iter().map(|(product_id, _)| *product_id).collect(),
+        )
+        .await?
+    } else {
+        HashMap::new()
+    };
+
+    let demand: Vec<ProductDemand> = totals
+        .into_iter()
+        .map(|(product_id, total_quantity)| ProductDemand {
+            product_id,
+            total_quantity: total_quantity.unwrap_or(0),
+            product_name: products
+                .get(&product_id)
+                .map(|product| product.name.clone()),
+        })
+        .collect();
+
+    Ok(StdResponse {
+        data: Some(demand),
+        message: Some("Get product demand successfully"),
+    })
+}
+
+/// An outbox event published about an order.
+#[derive(Queryable)]
+struct OrderOutboxEvent {
+    event_type: String,
+    payload: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TimelineEntry {
+    /// What happened, e.g. CREATED, RESERVE_REQUESTED, STATUS_CHANGED or PAYMENT_CREATED
+    pub kind: String,
+    pub timestamp: DateTime<Utc>,
+    pub details: Option<String>,
+}
+
+impl TimelineEntry {
+    fn new(kind: &str, timestamp: DateTime<Utc>, details: Option<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            timestamp,
+            details,
+        }
+    }
+
+    /// Describes an outbox event. Events without a dedicated description keep their routing key.
+    fn from_event(event: OrderOutboxEvent) -> Self {
+        let kind = match event.event_type.as_str() {
+            routing_keys::INVENTORY_RESERVE_ORDER => "RESERVE_REQUESTED",
+            routing_keys::INVENTORY_CANCEL_ORDER => "CANCEL_REQUESTED",
+            routing_keys::DELIVERY_ORDER_REQUEST => "DELIVERY_REQUESTED",
+            routing_keys::ORDER_STATUS_CHANGED => {
+                let details = serde_json::from_str::<OrderStatusChangedEvent>(&event.payload)
+                    .ok()
+                    .map(|change| match change.old_status {
+                        Some(old_status) => format!("{} -> {}", old_status, change.new_status),
+                        None => change.new_status,
+                    });
+                return Self::new("STATUS_CHANGED", event.created_at, details);
+            }
+            other => other,
+        };
+        Self::new(kind, event.created_at, None)
+    }
+}
+
+/// Chronological history of an order (admin), for support.
+///
+/// Interleaves the order's creation, every outbox event published about it (status changes,
+/// reservation, cancellation and delivery requests, ...) and its payments. Events are only listed
+/// as long as the outbox keeps them.
+#[utoipa::path(
+    get,
+    path = "/{id}/timeline",
+    tags = ["Orders"],
+    params(
+        ("id" = i32, Path, description = "Order ID whose timeline to fetch")
+    ),
+    responses(
+        (status = 200, description = "Get order timeline successfully", body = StdResponse<Vec<TimelineEntry>, String>),
+        (status = 404, description = "Order not found")
+    )
+)]
+async fn get_order_timeline(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let order: OrderEntity =
+        orders::table
+            .find(id)
+            .get_result(conn)
+            .await
+            .map_err(|err| match err {
+                DieselError::NotFound => AppError::NotFound,
+                _ => AppError::Other(err.into()),
+            })?;
+
+    let events: Vec<OrderOutboxEvent> = schema::outbox::table
+        .filter(schema::outbox::order_id.eq(id))
+        .order_by((schema::outbox::created_at.asc(), schema::outbox::id.asc()))
+        .select((
+            schema::outbox::event_type,
+            schema::outbox::payload,
+            schema::outbox::created_at,
+        ))
+        .get_results(conn)
+        .await
+        .context("Failed to get order events")?;
+
+    let payments: Vec<PaymentEntity> = schema::payments::table
+        .filter(schema::payments::order_id.eq(id))
+        .get_results(conn)
+        .await
+        .context("Failed to get order payments")?;
+
+    let mut timeline = vec![TimelineEntry::new(
+        "CREATED",
+        order.created_at,
+        Some(format!("{} order", order.order_type)),
+    )];
+    timeline.extend(events.into_iter().map(TimelineEntry::from_event));
+    for payment in payments {
+        timeline.push(TimelineEntry::new(
+            "PAYMENT_CREATED",
+            payment.created_at,
+            Some(format!(
+                "{:.2} {} via {}",
+                payment.amount, payment.currency, payment.provider
+            )),
+        ));
+        if payment.status != "PENDING" {
+            // Payments only record when they last changed, which is when they left PENDING
+            // unless they were refunded afterwards.
+            timeline.push(TimelineEntry::new(
+                &format!("PAYMENT_{}", payment.status),
+                payment.updated_at,
+                payment.failure_reason,
+            ));
+        }
+    }
+    // Stable, so entries sharing a timestamp keep the order they were added in.
+    timeline.sort_by_key(|entry| entry.timestamp);
+
+    Ok(StdResponse {
+        data: Some(timeline),
+        message: Some("Get order timeline successfully"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_csv_fields_are_left_as_is() {
+        assert_eq!(csv_field("PAID"), "PAID");
+        assert_eq!(csv_field("12.50"), "12.50");
+    }
+
+    #[test]
+    fn csv_fields_with_separators_are_quoted() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn csv_fields_starting_a_formula_are_neutralized() {
+        assert_eq!(csv_field("=SUM(A1:A2)"), "'=SUM(A1:A2)");
+        assert_eq!(csv_field("@cmd"), "'@cmd");
+        assert_eq!(csv_field("+1,2"), "\"'+1,2\"");
+    }
+
+    #[test]
+    fn csv_rows_join_fields_and_end_with_a_newline() {
+        let row = csv_row(&["1".into(), "a,b".into(), "-3".into()]);
+
+        assert_eq!(row, "1,\"a,b\",'-3\n");
+    }
+}
@@ -1,52 +1,48 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use anyhow::{Context, Result};
 use axum::{
-    Extension, Json, Router,
-    extract::{Path, State},
-    response::IntoResponse,
-    routing,
+    Extension,
+    extract::{OriginalUri, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
 };
-use diesel::{ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
-use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, QueryResult,
+    SelectableHelper,
+    dsl::{exists, not},
+    sql_types::Integer,
+};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use medbook_core::{
     aliases::DieselError,
     app_error::{AppError, StdResponse},
     app_state::AppState,
     middleware::{self},
 };
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use utoipa_axum::router::OpenApiRouter;
+use uuid::Uuid;
 
 use crate::{
-    api::products::get_product_unit_prices,
+    api::products::{ProductDetails, get_product_unit_prices, get_products},
+    billing, db, etag,
+    extract::{FieldError, Validate, ValidatedJson, ValidatedPath},
+    middleware::json_body_guard,
     models::{CartEntity, CartItemEntity, CreateCartEntity, CreateCartItemEntity},
+    money::FormatParams,
+    pagination::{PaginatedResponse, PaginationParams},
     schema::{
+        caregivers,
         cart_items::{self},
-        carts,
+        carts, orders,
     },
+    settings::Settings,
 };
 
-/// Defines all patient-facing carts routes (CRUD operations + authorization).
-#[deprecated]
-pub fn routes() -> Router<AppState> {
-    Router::new().nest(
-        "/patients/carts",
-        Router::new()
-            .route("/", routing::get(get_carts))
-            .route("/", routing::post(create_cart))
-            .route("/{id}", routing::patch(update_cart))
-            .route("/{id}", routing::get(get_cart))
-            .route("/{id}", routing::delete(delete_cart))
-            .route("/my-carts", routing::get(get_my_carts))
-            .route_layer(axum::middleware::from_fn(
-                middleware::patients_authorization,
-            )),
-    )
-}
-
-/// Defines routes with OpenAPI specs. Should be used over `routes()` where possible.
+/// Defines all patient-facing cart routes (CRUD operations + authorization).
 pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
     utoipa_axum::router::OpenApiRouter::new().nest(
         "/patients/carts",
@@ -54,48 +50,132 @@ pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
             .routes(utoipa_axum::routes!(get_carts))
             .routes(utoipa_axum::routes!(get_cart))
             .routes(utoipa_axum::routes!(get_my_carts))
+            .routes(utoipa_axum::routes!(get_current_cart))
+            .routes(utoipa_axum::routes!(claim_guest_cart))
             .routes(utoipa_axum::routes!(delete_cart))
             .routes(utoipa_axum::routes!(create_cart))
             .routes(utoipa_axum::routes!(update_cart))
+            .routes(utoipa_axum::routes!(validate_cart))
+            .routes(utoipa_axum::routes!(increment_cart_item))
+            .routes(utoipa_axum::routes!(reprice_cart))
+            .routes(utoipa_axum::routes!(transfer_cart))
             .route_layer(axum::middleware::from_fn(
                 middleware::patients_authorization,
-            )),
+            ))
+            .route_layer(axum::middleware::from_fn(json_body_guard)),
     )
 }
 
-/// Get all carts in the system (admin or debugging use).
+/// Get all carts in the system (admin or debugging use), oldest first.
 #[utoipa::path(
     get,
     path = "/",
     tags = ["Carts"],
     security(("bearerAuth" = [])),
+    params(PaginationParams),
     responses(
-        (status = 200, description = "List all carts", body = StdResponse<Vec<CartEntity>, String>)
+        (status = 200, description = "List all carts", body = StdResponse<PaginatedResponse<CartEntity>, String>)
     )
 )]
-async fn get_carts(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+async fn get_carts(
+    Query(pagination): Query<PaginationParams>,
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+) -> Result<impl IntoResponse, AppError> {
+    let pagination = pagination.clamp(Settings::get_pagination_limits());
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let carts: Vec<CartEntity> = carts::table
+        .order_by(carts::id.asc())
+        .limit(pagination.per_page())
+        .offset(pagination.offset())
         .get_results(conn)
         .await
         .context("Failed to get carts")?;
 
-    Ok(StdResponse {
-        data: Some(carts),
-        message: Some("Get carts successfully"),
-    })
+    let total_count: i64 = carts::table
+        .count()
+        .get_result(conn)
+        .await
+        .context("Failed to count carts")?;
+
+    Ok((
+        pagination.headers(&uri, total_count),
+        StdResponse {
+            data: Some(pagination.response(carts, total_count)),
+            message: Some("Get carts successfully"),
+        },
+    ))
 }
 
 #[derive(Serialize, ToSchema)]
-struct GetCartRes {
+pub struct GetCartRes {
     pub cart: CartEntity,
-    pub cart_items: Vec<CartItemEntity>,
+    pub cart_items: Vec<CartItemRes>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
     pub total_price: f32,
+    /// `total_price` formatted for display in the default currency, only sent when
+    /// `formatted=true` is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted_total: Option<String>,
+}
+
+/// A cart item annotated with its current stock level so the UI can warn before checkout.
+#[derive(Serialize, ToSchema)]
+pub struct CartItemRes {
+    #[serde(flatten)]
+    pub item: CartItemEntity,
+    pub product_name: Option<String>,
+    pub available_quantity: Option<i32>,
+    pub is_available: bool,
+}
+
+impl GetCartRes {
+    pub fn new(
+        cart: CartEntity,
+        cart_items: Vec<CartItemEntity>,
+        products: &HashMap<i32, ProductDetails>,
+    ) -> Self {
+        let total_price = cart_items
+            .iter()
+            .map(|item| {
+                let unit_price = products
+                    .get(&item.product_id)
+                    .and_then(|product| product.unit_price)
+                    .unwrap_or(0.0);
+                item.quantity as f32 * unit_price
+            })
+            .sum();
+
+        let cart_items = cart_items
+            .into_iter()
+            .map(|item| {
+                let product = products.get(&item.product_id);
+                CartItemRes {
+                    product_name: product.map(|product| product.name.clone()),
+                    available_quantity: product.and_then(|product| product.available_quantity),
+                    is_available: product.is_some_and(|product| product.can_supply(item.quantity)),
+                    item,
+                }
+            })
+            .collect();
+
+        Self {
+            cart,
+            cart_items,
+            total_price,
+            formatted_total: None,
+        }
+    }
+
+    /// Adds `formatted_total` if `format` asks for it.
+    pub fn with_format(self, format: FormatParams) -> Self {
+        Self {
+            formatted_total: format.format(self.total_price, &Settings::get_default_currency()),
+            ..self
+        }
+    }
 }
 
 /// Get a specific cart belonging to the authenticated patient.
@@ -105,22 +185,22 @@ struct GetCartRes {
     tags = ["Carts"],
     security(("bearerAuth" = [])),
     params(
-        ("id" = i32, Path, description = "Cart ID to fetch")
+        ("id" = i32, Path, description = "Cart ID to fetch"),
+        FormatParams
     ),
     responses(
-        (status = 200, description = "Get cart successfully", body = StdResponse<GetCartRes, String>)
+        (status = 200, description = "Get cart successfully", body = StdResponse<GetCartRes, String>),
+        (status = 304, description = "Cart unchanged since the ETag in If-None-Match")
     )
 )]
 async fn get_cart(
-    Path(id): Path<i32>,
+    ValidatedPath(id): ValidatedPath<i32>,
+    Query(format): Query<FormatParams>,
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
-) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let cart: QueryResult<CartEntity> = carts::table
         .find(id)
@@ -144,52 +224,90 @@ async fn get_cart(
         .context("Failed to get cart items")?;
 
     let cart_item_ids = cart_items.iter().map(|item| item.product_id).collect();
-    let unit_prices = get_product_unit_prices(state.http_client, cart_item_ids).await?;
+    let products = get_products(state.http_client, cart_item_ids).await?;
 
-    let total_price: f32 = cart_items
-        .iter()
-        .map(|item| {
-            let unit_price: f32 = unit_prices.get(&item.product_id).copied().unwrap_or(0.0);
-            item.quantity as f32 * unit_price
-        })
-        .sum();
+    let etag = cart_etag(&cart, &cart_items, &products, format);
+    if etag::is_not_modified(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
 
-    Ok(StdResponse {
-        data: Some(GetCartRes {
-            cart,
-            cart_items,
-            total_price,
-        }),
-        message: Some("Get cart successfully"),
-    })
+    Ok((
+        [(header::ETAG, etag)],
+        StdResponse {
+            data: Some(GetCartRes::new(cart, cart_items, &products).with_format(format)),
+            message: Some("Get cart successfully"),
+        },
+    )
+        .into_response())
+}
+
+/// ETag covering the cart, its items, the current price and stock of their products and whether
+/// the total is formatted. Products are read from InventoryService either way, since a price
+/// change alters the response without touching the cart.
+fn cart_etag(
+    cart: &CartEntity,
+    cart_items: &[CartItemEntity],
+    products: &HashMap<i32, ProductDetails>,
+    format: FormatParams,
+) -> String {
+    let mut parts = vec![
+        cart.id.to_string(),
+        cart.updated_at.to_rfc3339(),
+        format.formatted.to_string(),
+    ];
+    parts.extend(cart_items.iter().map(|item| {
+        let product = products.get(&item.product_id);
+        format!(
+            "{}:{}:{}:{:?}:{:?}",
+            item.product_id,
+            item.quantity,
+            item.updated_at.to_rfc3339(),
+            product.map(|product| product.unit_price),
+            product.and_then(|product| product.available_quantity),
+        )
+    }));
+    etag::weak_etag(&parts)
 }
 
-/// Get all carts belonging to the current authenticated patient.
+/// Get all carts belonging to the current authenticated patient, most recently updated first.
+///
+/// The total count and links to the other pages are also sent in the `X-Total-Count` and `Link`
+/// headers.
 #[utoipa::path(
     get,
     path = "/my-carts",
     tags = ["Carts"],
     security(("bearerAuth" = [])),
+    params(PaginationParams),
     responses(
-        (status = 200, description = "List my carts", body = StdResponse<Vec<GetCartRes>, String>)
+        (status = 200, description = "List my carts", body = StdResponse<PaginatedResponse<GetCartRes>, String>)
     )
 )]
 async fn get_my_carts(
+    Query(pagination): Query<PaginationParams>,
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
+    OriginalUri(uri): OriginalUri,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    let pagination = pagination.clamp(Settings::get_pagination_limits());
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let carts: Vec<CartEntity> = carts::table
         .filter(carts::patient_id.eq(patient_id))
+        .order_by((carts::updated_at.desc(), carts::id.desc()))
+        .limit(pagination.per_page())
+        .offset(pagination.offset())
         .get_results(conn)
         .await
         .context("Failed to get my carts")?;
 
+    let total_count: i64 = carts::table
+        .filter(carts::patient_id.eq(patient_id))
+        .count()
+        .get_result(conn)
+        .await
+        .context("Failed to count my carts")?;
+
     let cart_ids: Vec<i32> = carts.iter().map(|cart| cart.id).collect();
 
     let cart_items: Vec<CartItemEntity> = cart_items::table
@@ -199,7 +317,7 @@ async fn get_my_carts(
         .context("Failed to get cart items")?;
 
     let cart_item_ids = cart_items.iter().map(|item| item.product_id).collect();
-    let unit_prices = get_product_unit_prices(state.http_client, cart_item_ids).await?;
+    let products = get_products(state.http_client, cart_item_ids).await?;
 
     let mut group: HashMap<i32, Vec<CartItemEntity>> = HashMap::new();
     for item in cart_items {
@@ -210,27 +328,242 @@ async fn get_my_carts(
         .into_iter()
         .map(|cart| {
             let cart_items = group.remove(&cart.id).unwrap_or_default();
-            let total_price = cart_items
-                .iter()
-                .map(|item| {
-                    let unit_price = unit_prices.get(&item.product_id).copied().unwrap_or(0.0);
-                    item.quantity as f32 * unit_price
-                })
-                .sum();
-            GetCartRes {
-                cart_items,
-                cart,
-                total_price,
-            }
+            GetCartRes::new(cart, cart_items, &products)
         })
         .collect();
 
+    Ok((
+        pagination.headers(&uri, total_count),
+        StdResponse {
+            data: Some(pagination.response(carts_with_items, total_count)),
+            message: Some("Get my carts successfully"),
+        },
+    ))
+}
+
+/// First key of the advisory lock serializing creation of a patient's current cart. The second key
+/// is the patient ID.
+const CURRENT_CART_LOCK_KEY: i32 = 1001;
+
+/// Get the current cart of the authenticated patient, creating an empty one if needed.
+///
+/// The current cart is the most recently updated cart that has not been turned into an order yet.
+/// When there is none, an empty cart is created and returned. Concurrent first calls are serialized
+/// per patient, so they all return the same newly created cart.
+#[utoipa::path(
+    get,
+    path = "/current",
+    tags = ["Carts"],
+    security(("bearerAuth" = [])),
+    params(FormatParams),
+    responses(
+        (status = 200, description = "Get current cart successfully", body = StdResponse<GetCartRes, String>)
+    )
+)]
+async fn get_current_cart(
+    Query(format): Query<FormatParams>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let (cart, cart_items) = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let cart = match lock_current_cart(conn, patient_id).await? {
+                    Some(cart) => cart,
+                    None => diesel::insert_into(carts::table)
+                        .values(CreateCartEntity { patient_id })
+                        .returning(CartEntity::as_returning())
+                        .get_result(conn)
+                        .await
+                        .context("Failed to create cart")?,
+                };
+
+                let cart_items: Vec<CartItemEntity> = cart_items::table
+                    .filter(cart_items::cart_id.eq(cart.id))
+                    .get_results(conn)
+                    .await
+                    .context("Failed to get cart items")?;
+
+                Ok::<(CartEntity, Vec<CartItemEntity>), anyhow::Error>((cart, cart_items))
+            })
+        })
+        .await?;
+
+    let cart_item_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(state.http_client, cart_item_ids).await?;
+
     Ok(StdResponse {
-        data: Some(carts_with_items),
-        message: Some("Get my carts successfully"),
+        data: Some(GetCartRes::new(cart, cart_items, &products).with_format(format)),
+        message: Some("Get current cart successfully"),
     })
 }
 
+/// Takes the per-patient current cart lock for the rest of the transaction and returns the current
+/// cart of `patient_id`, if they have one.
+async fn lock_current_cart(
+    conn: &mut AsyncPgConnection,
+    patient_id: i32,
+) -> Result<Option<CartEntity>> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1, $2)")
+        .bind::<Integer, _>(CURRENT_CART_LOCK_KEY)
+        .bind::<Integer, _>(patient_id)
+        .execute(conn)
+        .await
+        .context("Failed to lock current cart")?;
+
+    carts::table
+        .filter(carts::patient_id.eq(patient_id))
+        .filter(not(exists(
+            orders::table.filter(orders::cart_id.eq(carts::id)),
+        )))
+        .order_by((carts::updated_at.desc(), carts::id.desc()))
+        .select(CartEntity::as_select())
+        .first(conn)
+        .await
+        .optional()
+        .context("Failed to get current cart")
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ClaimGuestCartReq {
+    /// Session token the guest cart was created with
+    pub session_token: Uuid,
+}
+
+impl Validate for ClaimGuestCartReq {
+    fn validate(&self) -> Vec<FieldError> {
+        Vec::new()
+    }
+}
+
+/// Attach a guest cart to the authenticated patient, e.g. right after they log in.
+///
+/// The guest cart becomes the patient's current cart if they have none. Otherwise its items are
+/// merged into the current cart and the guest cart is deleted. Quantities of products in both carts
+/// are added up, capped at [`Settings::get_max_item_quantity`].
+#[utoipa::path(
+    post,
+    path = "/claim",
+    tags = ["Carts"],
+    security(("bearerAuth" = [])),
+    request_body = ClaimGuestCartReq,
+    responses(
+        (status = 200, description = "Claimed guest cart successfully", body = StdResponse<GetCartRes, String>),
+        (status = 404, description = "No unclaimed guest cart has this session token")
+    )
+)]
+async fn claim_guest_cart(
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<ClaimGuestCartReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let (cart, cart_items) = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let current_cart = lock_current_cart(conn, patient_id).await?;
+
+                let guest_cart: CartEntity = carts::table
+                    .filter(carts::session_token.eq(body.session_token))
+                    .filter(carts::patient_id.is_null())
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                let cart_id = match current_cart {
+                    None => guest_cart.id,
+                    Some(current_cart) => {
+                        merge_cart_items(conn, guest_cart.id, current_cart.id).await?;
+                        diesel::delete(carts::table.find(guest_cart.id))
+                            .execute(conn)
+                            .await
+                            .context("Failed to delete guest cart")?;
+                        current_cart.id
+                    }
+                };
+
+                let cart: CartEntity = diesel::update(carts::table.find(cart_id))
+                    .set((
+                        carts::patient_id.eq(patient_id),
+                        carts::session_token.eq(None::<Uuid>),
+                        carts::updated_at.eq(diesel::dsl::now),
+                    ))
+                    .returning(CartEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to claim cart")?;
+
+                let cart_items: Vec<CartItemEntity> = cart_items::table
+                    .filter(cart_items::cart_id.eq(cart.id))
+                    .get_results(conn)
+                    .await
+                    .context("Failed to get cart items")?;
+
+                Ok::<(CartEntity, Vec<CartItemEntity>), AppError>((cart, cart_items))
+            })
+        })
+        .await?;
+
+    let cart_item_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(state.http_client, cart_item_ids).await?;
+
+    Ok(StdResponse {
+        data: Some(GetCartRes::new(cart, cart_items, &products)),
+        message: Some("Claimed guest cart successfully"),
+    })
+}
+
+/// Adds the items of cart `from` to cart `into`, keeping the last seen price of products already in
+/// `into`.
+async fn merge_cart_items(
+    conn: &mut AsyncPgConnection,
+    from: i32,
+    into: i32,
+) -> Result<(), AppError> {
+    let max_quantity = Settings::get_max_item_quantity();
+    let items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq(from))
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+
+    let mut product_ids: HashSet<i32> = cart_items::table
+        .filter(cart_items::cart_id.eq(into))
+        .select(cart_items::product_id)
+        .get_results::<i32>(conn)
+        .await
+        .context("Failed to get cart items")?
+        .into_iter()
+        .collect();
+    product_ids.extend(items.iter().map(|item| item.product_id));
+    ensure_within_cart_limit(product_ids.len())?;
+
+    for item in items {
+        diesel::insert_into(cart_items::table)
+            .values(CreateCartItemEntity {
+                cart_id: into,
+                product_id: item.product_id,
+                quantity: item.quantity.min(max_quantity),
+                last_seen_unit_price: item.last_seen_unit_price,
+            })
+            .on_conflict((cart_items::cart_id, cart_items::product_id))
+            .do_update()
+            .set(cart_items::quantity.eq(least(cart_items::quantity + item.quantity, max_quantity)))
+            .execute(conn)
+            .await
+            .context("Failed to merge cart item")?;
+    }
+
+    Ok(())
+}
+
 /// Delete a cart belonging to the authenticated patient.
 #[utoipa::path(
     delete,
@@ -245,15 +578,11 @@ async fn get_my_carts(
     )
 )]
 async fn delete_cart(
-    Path(id): Path<i32>,
+    ValidatedPath(id): ValidatedPath<i32>,
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let cart: QueryResult<CartEntity> = diesel::delete(carts::table)
         .filter(carts::id.eq(id))
@@ -277,22 +606,111 @@ async fn delete_cart(
 /// Create a new cart for the patient.
 
 #[derive(Deserialize, ToSchema)]
-struct CreateCartReq {
+pub struct CreateCartReq {
     pub cart_items: Vec<CreateCartReqCartItem>,
 }
 
 #[derive(Deserialize, ToSchema)]
-struct CreateCartReqCartItem {
+pub struct CreateCartReqCartItem {
     pub product_id: i32,
     pub quantity: i32,
 }
 
+impl Validate for CreateCartReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        for (index, item) in self.cart_items.iter().enumerate() {
+            if item.product_id < 1 {
+                errors.push(FieldError::new(
+                    format!("cart_items[{}].product_id", index),
+                    format!("cart_items[{}].product_id must be >= 1", index),
+                ));
+            }
+            if item.quantity < 1 {
+                errors.push(FieldError::new(
+                    format!("cart_items[{}].quantity", index),
+                    format!("cart_items[{}].quantity must be >= 1", index),
+                ));
+            }
+        }
+        errors
+    }
+}
+
 #[derive(Serialize, ToSchema)]
 struct CreateCartRes {
     pub cart: CartEntity,
     pub cart_items: Vec<CartItemEntity>,
 }
 
+/// Collapses duplicate product IDs by summing their quantities, dropping non-positive quantities.
+/// Returns `(product_id, quantity)` pairs ordered by product ID.
+pub fn aggregate_cart_items(items: &[CreateCartReqCartItem]) -> Result<Vec<(i32, i32)>, AppError> {
+    let mut quantities: BTreeMap<i32, i32> = BTreeMap::new();
+    for item in items.iter().filter(|item| item.quantity > 0) {
+        let quantity = quantities.entry(item.product_id).or_default();
+        *quantity = quantity.checked_add(item.quantity).ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "Quantity of product {} is too large",
+                item.product_id
+            ))
+        })?;
+    }
+
+    Ok(quantities.into_iter().collect())
+}
+
+/// Rejects carts that would hold more distinct products than [`Settings::get_max_cart_items`].
+pub fn ensure_within_cart_limit(distinct_items: usize) -> Result<(), AppError> {
+    let max_items = Settings::get_max_cart_items();
+    if distinct_items > max_items {
+        return Err(AppError::BadRequest(format!(
+            "A cart can contain at most {} different products",
+            max_items
+        )));
+    }
+
+    Ok(())
+}
+
+/// Current unit prices of `product_ids`, recorded on cart items as they are added.
+///
+/// Carts stay editable while InventoryService is unavailable, the prices are left unknown then.
+pub async fn last_seen_prices(client: Client, product_ids: Vec<i32>) -> HashMap<i32, f32> {
+    get_product_unit_prices(client, product_ids)
+        .await
+        .unwrap_or_else(|err| {
+            tracing::warn!("Failed to get unit prices for cart items: {:?}", err);
+            HashMap::new()
+        })
+}
+
+/// Adds `(product_id, quantity)` items to a new cart, recording `unit_prices` as their last seen
+/// prices.
+pub async fn insert_cart_items(
+    conn: &mut AsyncPgConnection,
+    cart_id: i32,
+    items: Vec<(i32, i32)>,
+    unit_prices: &HashMap<i32, f32>,
+) -> Result<Vec<CartItemEntity>> {
+    let cart_items: Vec<CreateCartItemEntity> = items
+        .into_iter()
+        .map(|(product_id, quantity)| CreateCartItemEntity {
+            cart_id,
+            product_id,
+            quantity,
+            last_seen_unit_price: unit_prices.get(&product_id).copied(),
+        })
+        .collect();
+
+    diesel::insert_into(cart_items::table)
+        .values(cart_items)
+        .returning(CartItemEntity::as_returning())
+        .get_results(conn)
+        .await
+        .context("Failed to create cart items")
+}
+
 /// Create a new cart for the authenticated patient.
 #[utoipa::path(
     post,
@@ -307,13 +725,20 @@ struct CreateCartRes {
 async fn create_cart(
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
-    Json(body): Json<CreateCartReq>,
+    ValidatedJson(body): ValidatedJson<CreateCartReq>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    let items = aggregate_cart_items(&body.cart_items)?;
+    if items.is_empty() {
+        return Err(AppError::BadRequest(
+            "Cart must contain at least one item with a positive quantity".into(),
+        ));
+    }
+    ensure_within_cart_limit(items.len())?;
+
+    let product_ids = items.iter().map(|(product_id, _)| *product_id).collect();
+    let unit_prices = last_seen_prices(state.http_client, product_ids).await;
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let (cart, cart_items) = conn
         .transaction(move |tx| {
@@ -325,23 +750,7 @@ async fn create_cart(
                     .await
                     .context("Failed to create cart")?;
 
-                let cart_items: Vec<CreateCartItemEntity> = body
-                    .cart_items
-                    .into_iter()
-                    .filter(|item| item.quantity > 0)
-                    .map(|item| CreateCartItemEntity {
-                        cart_id: cart.id,
-                        product_id: item.product_id,
-                        quantity: item.quantity,
-                    })
-                    .collect();
-
-                let cart_items = diesel::insert_into(cart_items::table)
-                    .values(cart_items)
-                    .returning(CartItemEntity::as_returning())
-                    .get_results(tx)
-                    .await
-                    .context("Failed to create cart items")?;
+                let cart_items = insert_cart_items(tx, cart.id, items, &unit_prices).await?;
 
                 Ok::<(CartEntity, Vec<CartItemEntity>), anyhow::Error>((cart, cart_items))
             })
@@ -358,7 +767,7 @@ async fn create_cart(
 /// Update a cart
 
 #[derive(Serialize, ToSchema)]
-struct UpdateCartRes {
+pub struct UpdateCartRes {
     pub deleted_items: Vec<CartItemEntity>,
     pub updated_items: Vec<CartItemEntity>,
     pub updated_cart: CartEntity,
@@ -379,16 +788,20 @@ struct UpdateCartRes {
     )
 )]
 async fn update_cart(
-    Path(id): Path<i32>,
+    ValidatedPath(id): ValidatedPath<i32>,
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
-    Json(body): Json<CreateCartReq>,
+    ValidatedJson(body): ValidatedJson<CreateCartReq>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    // Items missing from the body are deleted and the rest are upserted,
+    // so the resulting cart holds exactly the distinct products in the body.
+    let distinct_items: HashSet<i32> = body.cart_items.iter().map(|item| item.product_id).collect();
+    ensure_within_cart_limit(distinct_items.len())?;
+
+    let unit_prices =
+        last_seen_prices(state.http_client, distinct_items.into_iter().collect()).await;
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let result = conn
         .transaction(move |conn| {
@@ -405,65 +818,604 @@ async fn update_cart(
                     return Err(AppError::NotFound);
                 }
 
-                let new_product_ids: Vec<i32> =
-                    body.cart_items.iter().map(|item| item.product_id).collect();
-
-                let deleted_items: Vec<CartItemEntity> = diesel::delete(
-                    cart_items::table
-                        .filter(cart_items::cart_id.eq(id))
-                        .filter(cart_items::product_id.ne_all(&new_product_ids)),
+                Ok::<UpdateCartRes, AppError>(
+                    replace_cart_items(conn, id, &body.cart_items, &unit_prices).await?,
                 )
-                .returning(CartItemEntity::as_returning())
-                .get_results(conn)
-                .await
-                .context("Failed to delete cart items")?;
-
-                for item in &body.cart_items {
-                    diesel::insert_into(cart_items::table)
-                        .values((
-                            cart_items::cart_id.eq(id),
-                            cart_items::product_id.eq(item.product_id),
-                            cart_items::quantity.eq(item.quantity),
-                        ))
-                        .on_conflict((cart_items::cart_id, cart_items::product_id))
-                        .do_update()
-                        .set(cart_items::quantity.eq(item.quantity))
-                        .execute(conn)
-                        .await
-                        .context("Failed to upsert cart item")?;
+            })
+        })
+        .await;
+
+    match result {
+        Ok(updated) => Ok(StdResponse {
+            data: Some(updated),
+            message: Some("Updated cart successfully"),
+        }),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Replaces the items of a cart with `items`, keeping the last seen price of items already in it.
+pub async fn replace_cart_items(
+    conn: &mut AsyncPgConnection,
+    cart_id: i32,
+    items: &[CreateCartReqCartItem],
+    unit_prices: &HashMap<i32, f32>,
+) -> Result<UpdateCartRes> {
+    let new_product_ids: Vec<i32> = items.iter().map(|item| item.product_id).collect();
+
+    let deleted_items: Vec<CartItemEntity> = diesel::delete(
+        cart_items::table
+            .filter(cart_items::cart_id.eq(cart_id))
+            .filter(cart_items::product_id.ne_all(&new_product_ids)),
+    )
+    .returning(CartItemEntity::as_returning())
+    .get_results(conn)
+    .await
+    .context("Failed to delete cart items")?;
+
+    for item in items {
+        diesel::insert_into(cart_items::table)
+            .values((
+                cart_items::cart_id.eq(cart_id),
+                cart_items::product_id.eq(item.product_id),
+                cart_items::quantity.eq(item.quantity),
+                cart_items::last_seen_unit_price.eq(unit_prices.get(&item.product_id).copied()),
+            ))
+            // Items already in the cart keep the price they were added at.
+            .on_conflict((cart_items::cart_id, cart_items::product_id))
+            .do_update()
+            .set(cart_items::quantity.eq(item.quantity))
+            .execute(conn)
+            .await
+            .context("Failed to upsert cart item")?;
+    }
+
+    let updated_cart = diesel::update(carts::table.find(cart_id))
+        .set(carts::updated_at.eq(diesel::dsl::now))
+        .returning(CartEntity::as_returning())
+        .get_result(conn)
+        .await
+        .context("Failed to update cart timestamp")?;
+
+    let updated_items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq(cart_id))
+        .get_results(conn)
+        .await
+        .context("Failed to get updated items")?;
+
+    Ok(UpdateCartRes {
+        deleted_items,
+        updated_items,
+        updated_cart,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum CartItemValidationStatus {
+    Ok,
+    ProductNotFound,
+    /// InventoryService has no usable price for the product
+    PriceUnavailable,
+    InsufficientStock,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ValidatedCartItem {
+    pub product_id: i32,
+    pub quantity: i32,
+    pub product_name: Option<String>,
+    #[serde(with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    pub unit_price: Option<f32>,
+    pub available_quantity: Option<i32>,
+    pub status: CartItemValidationStatus,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ValidateCartRes {
+    pub can_order: bool,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total_price: f32,
+    pub cart_items: Vec<ValidatedCartItem>,
+}
+
+/// Check whether a cart can be ordered right now, without creating an order.
+///
+/// Every product must exist and be in stock for the requested quantity. Prices are the current ones
+/// from InventoryService. Nothing is modified and no events are published.
+#[utoipa::path(
+    post,
+    path = "/{id}/validate",
+    tags = ["Carts"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Cart ID to validate")
+    ),
+    responses(
+        (status = 200, description = "Validated cart successfully", body = StdResponse<ValidateCartRes, String>)
+    )
+)]
+async fn validate_cart(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let cart: CartEntity = carts::table
+        .find(id)
+        .filter(carts::patient_id.eq(patient_id))
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+
+    let cart_items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq(cart.id))
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+
+    let cart_item_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(state.http_client, cart_item_ids).await?;
+
+    let validated_items: Vec<ValidatedCartItem> = cart_items
+        .iter()
+        .map(|item| {
+            let product = products.get(&item.product_id);
+            let status = match product {
+                None => CartItemValidationStatus::ProductNotFound,
+                Some(product) if product.unit_price.is_none() => {
+                    CartItemValidationStatus::PriceUnavailable
+                }
+                Some(product) if !product.can_supply(item.quantity) => {
+                    CartItemValidationStatus::InsufficientStock
                 }
+                Some(_) => CartItemValidationStatus::Ok,
+            };
 
-                let updated_cart = diesel::update(carts::table.find(id))
+            ValidatedCartItem {
+                product_id: item.product_id,
+                quantity: item.quantity,
+                product_name: product.map(|product| product.name.clone()),
+                unit_price: product.and_then(|product| product.unit_price),
+                available_quantity: product.and_then(|product| product.available_quantity),
+                status,
+            }
+        })
+        .collect();
+
+    let total_price = validated_items
+        .iter()
+        .map(|item| item.quantity as f32 * item.unit_price.unwrap_or(0.0))
+        .sum();
+    let can_order = !validated_items.is_empty()
+        && validated_items
+            .iter()
+            .all(|item| matches!(item.status, CartItemValidationStatus::Ok));
+
+    Ok(StdResponse {
+        data: Some(ValidateCartRes {
+            can_order,
+            total_price,
+            cart_items: validated_items,
+        }),
+        message: Some("Validated cart successfully"),
+    })
+}
+
+diesel::define_sql_function! {
+    fn least(a: diesel::sql_types::Integer, b: diesel::sql_types::Integer) -> diesel::sql_types::Integer;
+}
+
+diesel::define_sql_function! {
+    fn greatest(a: diesel::sql_types::Integer, b: diesel::sql_types::Integer) -> diesel::sql_types::Integer;
+}
+
+#[derive(Deserialize, ToSchema)]
+struct IncrementCartItemReq {
+    /// Amount to add to the current quantity, negative to remove units
+    pub delta: i32,
+}
+
+impl Validate for IncrementCartItemReq {
+    fn validate(&self) -> Vec<FieldError> {
+        if self.delta == 0 {
+            return vec![FieldError::new("delta", "delta must not be 0")];
+        }
+        Vec::new()
+    }
+}
+
+/// Atomically add `delta` units of a product to a cart belonging to the authenticated patient.
+///
+/// Unlike `PATCH /{id}`, concurrent increments compose instead of overwriting each other. Missing
+/// items are created at `delta`. The resulting quantity is clamped between 1 and
+/// [`Settings::get_max_item_quantity`].
+#[utoipa::path(
+    post,
+    path = "/{id}/items/{product_id}/increment",
+    tags = ["Carts"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Cart ID to update"),
+        ("product_id" = i32, Path, description = "Product whose quantity should change")
+    ),
+    request_body = IncrementCartItemReq,
+    responses(
+        (status = 200, description = "Incremented cart item successfully", body = StdResponse<CartItemEntity, String>)
+    )
+)]
+async fn increment_cart_item(
+    ValidatedPath((id, product_id)): ValidatedPath<(i32, i32)>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<IncrementCartItemReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let max_quantity = Settings::get_max_item_quantity();
+    // Quantities stay within 1..=max, so a larger delta clamps to the same result. Bounding it
+    // keeps `quantity + delta` from overflowing in the database.
+    let delta = body.delta.clamp(-max_quantity, max_quantity);
+    let last_seen_unit_price = last_seen_prices(state.http_client, vec![product_id])
+        .await
+        .get(&product_id)
+        .copied();
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let item = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                // Locking the cart serializes the item limit check with concurrent increments.
+                let cart: CartEntity = carts::table
+                    .find(id)
+                    .filter(carts::patient_id.eq(patient_id))
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                let product_ids: Vec<i32> = cart_items::table
+                    .filter(cart_items::cart_id.eq(cart.id))
+                    .select(cart_items::product_id)
+                    .get_results(conn)
+                    .await
+                    .context("Failed to get cart items")?;
+                if !product_ids.contains(&product_id) {
+                    ensure_within_cart_limit(product_ids.len() + 1)?;
+                }
+
+                let item: CartItemEntity = diesel::insert_into(cart_items::table)
+                    .values(CreateCartItemEntity {
+                        cart_id: cart.id,
+                        product_id,
+                        quantity: delta.clamp(1, max_quantity),
+                        last_seen_unit_price,
+                    })
+                    .on_conflict((cart_items::cart_id, cart_items::product_id))
+                    .do_update()
+                    .set(cart_items::quantity.eq(least(
+                        greatest(cart_items::quantity + delta, 1),
+                        max_quantity,
+                    )))
+                    .returning(CartItemEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to increment cart item")?;
+
+                diesel::update(carts::table.find(cart.id))
                     .set(carts::updated_at.eq(diesel::dsl::now))
+                    .execute(conn)
+                    .await
+                    .context("Failed to update cart timestamp")?;
+
+                Ok::<CartItemEntity, AppError>(item)
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(item),
+        message: Some("Incremented cart item successfully"),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct RepricedCartItem {
+    pub product_id: i32,
+    pub quantity: i32,
+    /// Unit price when the item was added, `null` if it was not known then
+    #[serde(with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    pub old_price: Option<f32>,
+    /// Current unit price, `null` if the product no longer exists
+    #[serde(with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    pub new_price: Option<f32>,
+    /// Whether the price differs from the one the item was added at
+    pub changed: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RepriceCartRes {
+    pub cart_items: Vec<RepricedCartItem>,
+    /// Total at the current unit prices
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total_price: f32,
+}
+
+/// Compare the prices cart items were added at with the current InventoryService prices.
+///
+/// Lets the UI point out prices that changed since an item was added. Nothing is modified, the
+/// stored prices stay those seen when adding.
+#[utoipa::path(
+    post,
+    path = "/{id}/reprice",
+    tags = ["Carts"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Cart ID to reprice")
+    ),
+    responses(
+        (status = 200, description = "Repriced cart successfully", body = StdResponse<RepriceCartRes, String>)
+    )
+)]
+async fn reprice_cart(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let cart: CartEntity = carts::table
+        .find(id)
+        .filter(carts::patient_id.eq(patient_id))
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+
+    let cart_items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq(cart.id))
+        .order_by(cart_items::product_id.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+
+    let product_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let unit_prices = get_product_unit_prices(state.http_client, product_ids).await?;
+
+    let repriced_items: Vec<RepricedCartItem> = cart_items
+        .iter()
+        .map(|item| {
+            let old_price = item.last_seen_unit_price;
+            let new_price = unit_prices.get(&item.product_id).copied();
+            let changed = match (old_price, new_price) {
+                (Some(old_price), Some(new_price)) => {
+                    (old_price - new_price).abs() > billing::AMOUNT_EPSILON
+                }
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+
+            RepricedCartItem {
+                product_id: item.product_id,
+                quantity: item.quantity,
+                old_price,
+                new_price,
+                changed,
+            }
+        })
+        .collect();
+
+    let total_price = repriced_items
+        .iter()
+        .map(|item| item.quantity as f32 * item.new_price.unwrap_or(0.0))
+        .sum();
+
+    Ok(StdResponse {
+        data: Some(RepriceCartRes {
+            cart_items: repriced_items,
+            total_price,
+        }),
+        message: Some("Repriced cart successfully"),
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+struct TransferCartReq {
+    /// Patient the cart is handed over to
+    pub patient_id: i32,
+}
+
+impl Validate for TransferCartReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.patient_id < 1 {
+            errors.push(FieldError::new("patient_id", "patient_id must be >= 1"));
+        }
+        errors
+    }
+}
+
+/// Hand a cart of the authenticated patient over to another patient, e.g. a caregiver passing on
+/// a cart filled in for a family member.
+///
+/// Only allowed between a caregiver and a patient they care for, in either direction. Carts an
+/// order was placed from stay with their owner.
+#[utoipa::path(
+    post,
+    path = "/{id}/transfer",
+    tags = ["Carts"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Cart ID to transfer")
+    ),
+    request_body = TransferCartReq,
+    responses(
+        (status = 200, description = "Transferred cart successfully", body = StdResponse<GetCartRes, String>),
+        (status = 403, description = "No caregiver relationship with the target patient"),
+        (status = 404, description = "Cart not found"),
+        (status = 409, description = "An order was placed from the cart")
+    )
+)]
+async fn transfer_cart(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<TransferCartReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let target_id = body.patient_id;
+    if target_id == patient_id {
+        return Err(AppError::BadRequest(
+            "Cart already belongs to this patient".into(),
+        ));
+    }
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let (cart, cart_items) = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                carts::table
+                    .find(id)
+                    .filter(carts::patient_id.eq(patient_id))
+                    .select(carts::id)
+                    .for_update()
+                    .get_result::<i32>(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                let related: bool = diesel::select(exists(
+                    caregivers::table.filter(
+                        caregivers::caregiver_id
+                            .eq(patient_id)
+                            .and(caregivers::patient_id.eq(target_id))
+                            .or(caregivers::caregiver_id
+                                .eq(target_id)
+                                .and(caregivers::patient_id.eq(patient_id))),
+                    ),
+                ))
+                .get_result(conn)
+                .await
+                .context("Failed to check caregiver relationship")?;
+                if !related {
+                    return Err(AppError::ForbiddenResource);
+                }
+
+                let order_id: Option<i32> = orders::table
+                    .filter(orders::cart_id.eq(id))
+                    .select(orders::id)
+                    .first(conn)
+                    .await
+                    .optional()
+                    .context("Failed to get cart orders")?;
+                if let Some(order_id) = order_id {
+                    return Err(AppError::Conflict(format!(
+                        "Order #{} was placed from this cart",
+                        order_id
+                    )));
+                }
+
+                // The cart may become the target's current cart, so keep a concurrent request from
+                // creating another one for them meanwhile.
+                lock_current_cart(conn, target_id).await?;
+
+                let cart: CartEntity = diesel::update(carts::table.find(id))
+                    .set(carts::patient_id.eq(target_id))
                     .returning(CartEntity::as_returning())
                     .get_result(conn)
                     .await
-                    .context("Failed to update cart timestamp")?;
+                    .context("Failed to transfer cart")?;
 
-                let updated_items: Vec<CartItemEntity> = cart_items::table
+                let cart_items: Vec<CartItemEntity> = cart_items::table
                     .filter(cart_items::cart_id.eq(id))
+                    .order_by(cart_items::product_id.asc())
                     .get_results(conn)
                     .await
-                    .context("Failed to get updated items")?;
+                    .context("Failed to get cart items")?;
 
-                Ok::<(Vec<CartItemEntity>, Vec<CartItemEntity>, CartEntity), AppError>((
-                    deleted_items,
-                    updated_items,
-                    updated_cart,
-                ))
+                Ok::<(CartEntity, Vec<CartItemEntity>), AppError>((cart, cart_items))
             })
         })
-        .await;
+        .await?;
 
-    match result {
-        Ok((deleted_items, updated_items, updated_cart)) => Ok(StdResponse {
-            data: Some(UpdateCartRes {
-                deleted_items,
-                updated_items,
-                updated_cart,
-            }),
-            message: Some("Updated cart successfully"),
-        }),
-        Err(err) => Err(err.into()),
+    tracing::info!(
+        cart_id = cart.id,
+        from_patient_id = patient_id,
+        to_patient_id = target_id,
+        "Transferred cart"
+    );
+
+    let product_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(state.http_client, product_ids).await?;
+
+    Ok(StdResponse {
+        data: Some(GetCartRes::new(cart, cart_items, &products)),
+        message: Some("Transferred cart successfully"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(product_id: i32, quantity: i32) -> CreateCartReqCartItem {
+        CreateCartReqCartItem {
+            product_id,
+            quantity,
+        }
+    }
+
+    #[test]
+    fn aggregate_cart_items_sums_duplicate_products() {
+        let items = [item(2, 1), item(1, 3), item(2, 4)];
+
+        assert_eq!(
+            aggregate_cart_items(&items).ok(),
+            Some(vec![(1, 3), (2, 5)])
+        );
+    }
+
+    #[test]
+    fn aggregate_cart_items_drops_non_positive_quantities() {
+        let items = [item(1, 0), item(2, -3), item(3, 2)];
+
+        assert_eq!(aggregate_cart_items(&items).ok(), Some(vec![(3, 2)]));
+    }
+
+    #[test]
+    fn aggregate_cart_items_rejects_overflowing_quantities() {
+        let items = [item(1, i32::MAX), item(1, 1)];
+
+        assert!(matches!(
+            aggregate_cart_items(&items),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn cart_limit_allows_exactly_the_maximum() {
+        assert!(ensure_within_cart_limit(Settings::get_max_cart_items()).is_ok());
+    }
+
+    #[test]
+    fn cart_limit_rejects_one_over_the_maximum() {
+        assert!(matches!(
+            ensure_within_cart_limit(Settings::get_max_cart_items() + 1),
+            Err(AppError::BadRequest(_))
+        ));
     }
 }
@@ -1,2 +1,3 @@
 pub mod carts;
 pub mod orders;
+pub mod price_quote;
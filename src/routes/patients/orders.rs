@@ -1,12 +1,18 @@
 use anyhow::{Context, Result};
 use axum::{
-    Extension, Json, Router,
-    extract::{Path, State},
-    response::IntoResponse,
-    routing,
+    Extension,
+    extract::{OriginalUri, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
 };
-use diesel::{ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
-use diesel_async::{AsyncConnection, RunQueryDsl};
+use chrono::{DateTime, Utc};
+use diesel::{
+    ExpressionMethods, OptionalExtension, QueryDsl, QueryResult, SelectableHelper,
+    dsl::{count_star, max, min},
+    pg::Pg,
+    sql_types::Integer,
+};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use medbook_core::app_error::StdResponse;
 use medbook_core::{
     aliases::DieselError,
@@ -16,45 +22,42 @@ use medbook_core::{
     outbox,
 };
 use medbook_events::OrderCancelledEvent;
+use reqwest::Client;
 use serde_json::Value;
-use std::collections::HashMap;
-use utoipa::ToSchema;
+use std::collections::{HashMap, HashSet};
+use utoipa::{IntoParams, ToSchema};
 use utoipa_axum::router::OpenApiRouter;
+use uuid::Uuid;
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     api::{
-        deliveries::get_delivery_address_as_value_with_ownership_check,
-        products::get_product_unit_prices,
+        deliveries::get_delivery_address_with_ownership_check,
+        products::{ProductDetails, get_products},
+    },
+    billing, db, etag,
+    extract::{FieldError, Validate, ValidatedJson, ValidatedPath},
+    middleware::{error_response, json_body_guard},
+    models::{
+        CartEntity, CartItemEntity, CreateCartEntity, CreateOrderEntity, CreatePaymentEntity,
+        OrderEntity, OrderItemEntity, OrderType, PaymentEntity,
     },
-    models::{CartItemEntity, CreateOrderEntity, CreatePaymentEntity, OrderEntity, PaymentEntity},
+    money::FormatParams,
+    order_items, order_status,
+    pagination::{PaginatedResponse, PaginationParams},
+    routes::patients::carts::{insert_cart_items, last_seen_prices},
+    routing_keys,
     schema::{
         cart_items::{self},
+        carts,
         orders::{self},
         payments::{self},
     },
+    settings::Settings,
 };
 
 /// Defines all patient-facing order routes (CRUD operations + authorization).
-#[deprecated]
-pub fn routes() -> Router<AppState> {
-    Router::new().nest(
-        "/patients/orders",
-        Router::new()
-            .route("/", routing::get(get_orders))
-            .route("/", routing::post(create_order))
-            .route("/my-orders", routing::get(get_my_orders))
-            .route("/{id}", routing::get(get_order))
-            .route("/{id}", routing::delete(cancel_order))
-            .route("/{id}/payment", routing::post(create_payment_for_order))
-            .route_layer(axum::middleware::from_fn(
-                middleware::patients_authorization,
-            )),
-    )
-}
-
-/// Defines routes with OpenAPI specs. Should be used over `routes()` where possible.
 pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
     utoipa_axum::router::OpenApiRouter::new().nest(
         "/patients/orders",
@@ -62,50 +65,149 @@ pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
             .routes(utoipa_axum::routes!(get_orders))
             .routes(utoipa_axum::routes!(get_order))
             .routes(utoipa_axum::routes!(get_my_orders))
+            .routes(utoipa_axum::routes!(get_order_details_batch))
+            .routes(utoipa_axum::routes!(get_my_order_stats))
+            .routes(utoipa_axum::routes!(get_action_needed_orders))
             .routes(utoipa_axum::routes!(create_order))
+            .routes(utoipa_axum::routes!(create_orders_batch))
+            .routes(utoipa_axum::routes!(repeat_last_order))
+            .routes(utoipa_axum::routes!(get_last_delivery_address))
+            .routes(utoipa_axum::routes!(preview_order))
             .routes(utoipa_axum::routes!(cancel_order))
+            .routes(utoipa_axum::routes!(accept_partial_reservation))
+            .routes(utoipa_axum::routes!(update_order_delivery))
+            .routes(utoipa_axum::routes!(retry_order))
+            .routes(utoipa_axum::routes!(remove_order_item))
+            .routes(utoipa_axum::routes!(update_order_items))
             .routes(utoipa_axum::routes!(create_payment_for_order))
+            .routes(utoipa_axum::routes!(abandon_order_payment))
             .routes(utoipa_axum::routes!(get_order_payments))
+            .routes(utoipa_axum::routes!(get_order_receipt))
             .route_layer(axum::middleware::from_fn(
                 middleware::patients_authorization,
-            )),
+            ))
+            .route_layer(axum::middleware::from_fn(json_body_guard)),
     )
 }
 
-/// Fetch all orders in the system.
+/// Fetch all orders in the system, oldest first.
 #[utoipa::path(
     get,
     path = "/",
     tags = ["Orders"],
     security(("bearerAuth" = [])),
+    params(PaginationParams),
     responses(
-        (status = 200, description = "List all orders", body = StdResponse<Vec<OrderEntity>, String>)
+        (status = 200, description = "List all orders", body = StdResponse<PaginatedResponse<OrderEntity>, String>)
     )
 )]
-async fn get_orders(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+async fn get_orders(
+    Query(pagination): Query<PaginationParams>,
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+) -> Result<impl IntoResponse, AppError> {
+    let pagination = pagination.clamp(Settings::get_pagination_limits());
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let orders: Vec<OrderEntity> = orders::table
         // .filter(orders::deleted_at.is_null())
+        .order_by(orders::id.asc())
+        .limit(pagination.per_page())
+        .offset(pagination.offset())
         .get_results(conn)
         .await
         .context("Failed to get orders")?;
 
-    Ok(StdResponse {
-        data: Some(orders),
-        message: Some("Get orders succesfully"),
-    })
+    let total_count: i64 = orders::table
+        .count()
+        .get_result(conn)
+        .await
+        .context("Failed to count orders")?;
+
+    Ok((
+        pagination.headers(&uri, total_count),
+        StdResponse {
+            data: Some(pagination.response(orders, total_count)),
+            message: Some("Get orders succesfully"),
+        },
+    ))
 }
 
 #[derive(Serialize, ToSchema)]
 struct GetOrderRes {
     pub order: OrderEntity,
-    pub order_items: Vec<CartItemEntity>,
+    pub order_items: Vec<OrderItemEntity>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
     pub total_price: f32,
+    /// ISO 4217 currency of `total_price`
+    pub currency: String,
+    /// Most recent payment attempt, or `null` if the order has not been paid for yet
+    pub payment: Option<PaymentSummary>,
+    /// `total_price` formatted for display, only sent when `formatted=true` is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted_total: Option<String>,
+}
+
+impl GetOrderRes {
+    fn new(
+        order: OrderEntity,
+        order_items: Vec<OrderItemEntity>,
+        payment: Option<PaymentEntity>,
+        format: FormatParams,
+    ) -> Self {
+        let total_price = billing::items_total(&order_items);
+        Self {
+            formatted_total: format.format(total_price, &order.currency),
+            payment: payment.map(|payment| PaymentSummary::new(payment, format)),
+            currency: order.currency.clone(),
+            order,
+            order_items,
+            total_price,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct PaymentSummary {
+    pub payment_status: String,
+    pub provider: String,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub amount: f32,
+    /// `amount` formatted for display, only sent when `formatted=true` is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted_amount: Option<String>,
+}
+
+impl PaymentSummary {
+    fn new(payment: PaymentEntity, format: FormatParams) -> Self {
+        Self {
+            formatted_amount: format.format(payment.amount, &payment.currency),
+            payment_status: payment.status,
+            provider: payment.provider,
+            amount: payment.amount,
+        }
+    }
+}
+
+/// Loads the most recent payment of each order, keyed by order ID.
+async fn get_latest_payments(
+    conn: &mut AsyncPgConnection,
+    order_ids: &[i32],
+) -> Result<HashMap<i32, PaymentEntity>> {
+    let payments: Vec<PaymentEntity> = payments::table
+        .filter(payments::order_id.eq_any(order_ids))
+        .distinct_on(payments::order_id)
+        .order_by((payments::order_id, payments::created_at.desc()))
+        .get_results(conn)
+        .await
+        .context("Failed to get latest payments")?;
+
+    Ok(payments
+        .into_iter()
+        .map(|payment| (payment.order_id, payment))
+        .collect())
 }
 
 /// Fetch a specific order belonging to the authenticated patient.
@@ -115,22 +217,22 @@ struct GetOrderRes {
     tags = ["Orders"],
     security(("bearerAuth" = [])),
     params(
-        ("id" = i32, Path, description = "Order ID to fetch")
+        ("id" = i32, Path, description = "Order ID to fetch"),
+        FormatParams
     ),
     responses(
-        (status = 200, description = "Get order successfully", body = StdResponse<GetOrderRes, String>)
+        (status = 200, description = "Get order successfully", body = StdResponse<GetOrderRes, String>),
+        (status = 304, description = "Order unchanged since the ETag in If-None-Match")
     )
 )]
 async fn get_order(
-    Path(id): Path<i32>,
+    ValidatedPath(id): ValidatedPath<i32>,
+    Query(format): Query<FormatParams>,
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
-) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let order: QueryResult<OrderEntity> = orders::table
         .find(id)
@@ -147,378 +249,2289 @@ async fn get_order(
     }
 
     let order = order.unwrap();
-    let order_items: Vec<CartItemEntity> = cart_items::table
-        .filter(cart_items::cart_id.eq(order.cart_id))
-        .get_results(conn)
+    let order_items = order_items::load(conn, state.http_client, std::slice::from_ref(&order))
+        .await?
+        .remove(&order.id)
+        .unwrap_or_default();
+    let payment = get_latest_payments(conn, &[order.id])
+        .await?
+        .remove(&order.id);
+
+    let etag = order_etag(&order, &order_items, payment.as_ref(), format);
+    if etag::is_not_modified(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    Ok((
+        [(header::ETAG, etag)],
+        StdResponse {
+            data: Some(GetOrderRes::new(order, order_items, payment, format)),
+            message: Some("Get order successfully"),
+        },
+    )
+        .into_response())
+}
+
+/// Maximum number of orders fetched by a single details batch.
+const MAX_DETAILS_BATCH_SIZE: usize = 50;
+
+#[derive(Deserialize, ToSchema)]
+struct GetOrderDetailsBatchReq {
+    /// Orders to fetch, all belonging to the authenticated patient
+    order_ids: Vec<i32>,
+}
+
+impl Validate for GetOrderDetailsBatchReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.order_ids.is_empty() || self.order_ids.len() > MAX_DETAILS_BATCH_SIZE {
+            errors.push(FieldError::new(
+                "order_ids",
+                format!(
+                    "order_ids must have between 1 and {} entries",
+                    MAX_DETAILS_BATCH_SIZE
+                ),
+            ));
+        }
+        for (index, id) in self.order_ids.iter().enumerate() {
+            if *id < 1 {
+                errors.push(FieldError::new(
+                    format!("order_ids[{}]", index),
+                    format!("order_ids[{}] must be >= 1", index),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Fetch the full details of several orders of the authenticated patient at once, e.g. to compare
+/// them side by side.
+///
+/// Orders are returned in the requested order, duplicates once. Items and payments of all orders
+/// are loaded together. Fails as a whole if any order is missing or belongs to someone else.
+#[utoipa::path(
+    post,
+    path = "/details-batch",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(FormatParams),
+    request_body = GetOrderDetailsBatchReq,
+    responses(
+        (status = 200, description = "Get order details successfully", body = StdResponse<Vec<GetOrderRes>, String>),
+        (status = 403, description = "An order belongs to another patient"),
+        (status = 404, description = "An order was not found")
+    )
+)]
+async fn get_order_details_batch(
+    Query(format): Query<FormatParams>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<GetOrderDetailsBatchReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut order_ids = Vec::with_capacity(body.order_ids.len());
+    for id in body.order_ids {
+        if !order_ids.contains(&id) {
+            order_ids.push(id);
+        }
+    }
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let mut orders: HashMap<i32, OrderEntity> = orders::table
+        .filter(orders::id.eq_any(&order_ids))
+        .get_results::<OrderEntity>(conn)
         .await
-        .context("Failed to get order items")?;
+        .context("Failed to get orders")?
+        .into_iter()
+        .map(|order| (order.id, order))
+        .collect();
+    if orders.values().any(|order| order.patient_id != patient_id) {
+        return Err(AppError::ForbiddenResource);
+    }
+    if orders.len() < order_ids.len() {
+        return Err(AppError::NotFound);
+    }
 
-    let cart_item_ids = order_items.iter().map(|item| item.product_id).collect();
-    let unit_prices = get_product_unit_prices(state.http_client, cart_item_ids).await?;
-    let total_price: f32 = order_items
+    let orders: Vec<OrderEntity> = order_ids
         .iter()
-        .map(|item| unit_prices.get(&item.product_id).copied().unwrap_or(0.0))
-        .sum();
+        .filter_map(|id| orders.remove(id))
+        .collect();
+    let mut group = order_items::load(conn, state.http_client, &orders).await?;
+    let mut latest_payments = get_latest_payments(conn, &order_ids).await?;
+
+    let details: Vec<GetOrderRes> = orders
+        .into_iter()
+        .map(|order| {
+            let order_items = group.remove(&order.id).unwrap_or_default();
+            let payment = latest_payments.remove(&order.id);
+            GetOrderRes::new(order, order_items, payment, format)
+        })
+        .collect();
 
     Ok(StdResponse {
-        data: Some(GetOrderRes {
-            order,
-            order_items,
-            total_price,
-        }),
-        message: Some("Get order successfully"),
+        data: Some(details),
+        message: Some("Get order details successfully"),
     })
 }
 
+/// ETag covering the order itself, its items with their prices, its latest payment and whether
+/// amounts are formatted.
+fn order_etag(
+    order: &OrderEntity,
+    order_items: &[OrderItemEntity],
+    payment: Option<&PaymentEntity>,
+    format: FormatParams,
+) -> String {
+    let mut parts = vec![
+        order.id.to_string(),
+        order.status.clone(),
+        order.updated_at.to_rfc3339(),
+        format.formatted.to_string(),
+    ];
+    parts.extend(order_items.iter().map(|item| {
+        format!(
+            "{}:{}:{}:{}:{}",
+            item.product_id,
+            item.quantity,
+            item.unit_price_at_order,
+            item.backordered_quantity,
+            item.created_at.to_rfc3339()
+        )
+    }));
+    if let Some(payment) = payment {
+        parts.push(format!(
+            "{}:{}",
+            payment.id,
+            payment.updated_at.to_rfc3339()
+        ));
+    }
+    etag::weak_etag(&parts)
+}
+
+/// Statuses of orders that are still in progress, as shown in the patient's "active" tab.
+const ACTIVE_STATUSES: &[&str] = &[
+    "PENDING",
+    "RESERVED",
+    "PARTIALLY_RESERVED",
+    "RESERVE_TIMEOUT",
+    "RETRYABLE",
+    "PAYMENT_PENDING",
+    "DELIVERY_PENDING",
+    "CANCEL_PENDING",
+];
+
+#[derive(Deserialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum OrderBucket {
+    /// Orders still in progress
+    Active,
+    /// Delivered, cancelled, rejected or expired orders
+    Completed,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GetMyOrdersParams {
+    /// Only return active or completed orders. Returns every order when omitted.
+    bucket: Option<OrderBucket>,
+}
+
+/// Builds the query for a patient's orders in `bucket`, shared by the page and its total count.
+fn my_orders_query(
+    patient_id: i32,
+    bucket: Option<OrderBucket>,
+) -> orders::BoxedQuery<'static, Pg> {
+    let query = orders::table
+        // .filter(orders::deleted_at.is_null())
+        .filter(orders::patient_id.eq(patient_id))
+        .into_boxed();
+
+    match bucket {
+        Some(OrderBucket::Active) => query.filter(orders::status.eq_any(ACTIVE_STATUSES)),
+        Some(OrderBucket::Completed) => query.filter(orders::status.ne_all(ACTIVE_STATUSES)),
+        None => query,
+    }
+}
+
 /// Fetch all orders belonging to the authenticated patient.
+///
+/// The total count and links to the other pages are also sent in the `X-Total-Count` and `Link`
+/// headers.
 #[utoipa::path(
     get,
     path = "/my-orders",
     tags = ["Orders"],
     security(("bearerAuth" = [])),
+    params(GetMyOrdersParams, PaginationParams, FormatParams),
     responses(
-        (status = 200, description = "List my orders", body = StdResponse<Vec<GetOrderRes>, String>)
+        (status = 200, description = "List my orders", body = StdResponse<PaginatedResponse<GetOrderRes>, String>)
     )
 )]
 async fn get_my_orders(
+    Query(params): Query<GetMyOrdersParams>,
+    Query(pagination): Query<PaginationParams>,
+    Query(format): Query<FormatParams>,
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
+    OriginalUri(uri): OriginalUri,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    let pagination = pagination.clamp(Settings::get_pagination_limits());
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
-    let orders: Vec<OrderEntity> = orders::table
-        // .filter(orders::deleted_at.is_null())
-        .filter(orders::patient_id.eq(patient_id))
+    let orders: Vec<OrderEntity> = my_orders_query(patient_id, params.bucket)
         .order_by(orders::updated_at.desc())
+        .limit(pagination.per_page())
+        .offset(pagination.offset())
         .get_results(conn)
         .await
         .context("Failed to get my orders")?;
 
-    let cart_ids: Vec<i32> = orders.iter().map(|order| order.cart_id).collect();
-    let order_items: Vec<CartItemEntity> = cart_items::table
-        .filter(cart_items::cart_id.eq_any(&cart_ids))
-        .get_results(conn)
+    let total_count: i64 = my_orders_query(patient_id, params.bucket)
+        .count()
+        .get_result(conn)
         .await
-        .context("Failed to get cart items")?;
+        .context("Failed to count my orders")?;
 
-    let cart_item_ids = order_items.iter().map(|item| item.product_id).collect();
-    let unit_prices = get_product_unit_prices(state.http_client, cart_item_ids).await?;
+    let mut group = order_items::load(conn, state.http_client, &orders).await?;
 
-    let mut group: HashMap<i32, Vec<CartItemEntity>> = HashMap::new();
-    for item in order_items {
-        group.entry(item.cart_id).or_default().push(item);
-    }
+    let order_ids: Vec<i32> = orders.iter().map(|order| order.id).collect();
+    let mut latest_payments = get_latest_payments(conn, &order_ids).await?;
 
     let order_with_items: Vec<GetOrderRes> = orders
         .into_iter()
         .map(|order| {
-            let order_items = group.remove(&order.cart_id).unwrap_or_default();
-            let total_price: f32 = order_items
-                .iter()
-                .map(|item| unit_prices.get(&item.product_id).copied().unwrap_or(0.0))
-                .sum();
-            GetOrderRes {
-                order_items,
-                order,
-                total_price,
-            }
+            let order_items = group.remove(&order.id).unwrap_or_default();
+            let payment = latest_payments.remove(&order.id);
+            GetOrderRes::new(order, order_items, payment, format)
         })
         .collect();
 
-    Ok(StdResponse {
-        data: Some(order_with_items),
-        message: Some("Get my orders successfully"),
-    })
+    Ok((
+        pagination.headers(&uri, total_count),
+        StdResponse {
+            data: Some(pagination.response(order_with_items, total_count)),
+            message: Some("Get my orders successfully"),
+        },
+    ))
 }
 
-#[derive(Deserialize, ToSchema)]
-struct CreateOrderReq {
-    delivery_address_id: Option<i32>,
-    cart_id: i32,
+#[derive(Serialize, ToSchema)]
+struct OrderStatsRes {
+    pub total_orders: i64,
+    /// Sum of paid payments less refunds, keyed by ISO 4217 currency
+    #[serde(serialize_with = "crate::money::map_as_string")]
+    #[schema(value_type = HashMap<String, String>)]
+    pub total_spent: HashMap<String, f32>,
+    pub orders_by_status: HashMap<String, i64>,
+    pub first_order_at: Option<DateTime<Utc>>,
+    pub last_order_at: Option<DateTime<Utc>>,
 }
 
-/// Create a new order for the authenticated patient.
+/// Get aggregate order statistics of the authenticated patient.
 #[utoipa::path(
-    post,
-    path = "/",
+    get,
+    path = "/stats",
     tags = ["Orders"],
     security(("bearerAuth" = [])),
-    request_body = CreateOrderReq,
     responses(
-        (status = 200, description = "Created order successfully", body = StdResponse<OrderEntity, String>)
+        (status = 200, description = "Get order stats successfully", body = StdResponse<OrderStatsRes, String>)
     )
 )]
-async fn create_order(
+async fn get_my_order_stats(
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
-    Json(body): Json<CreateOrderReq>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let orders_by_status: HashMap<String, i64> = orders::table
+        .filter(orders::patient_id.eq(patient_id))
+        .group_by(orders::status)
+        .select((orders::status, count_star()))
+        .get_results::<(String, i64)>(conn)
         .await
-        .context("Failed to obtain a DB connection pool")?;
+        .context("Failed to count orders by status")?
+        .into_iter()
+        .collect();
 
-    let delivery_address: Option<Value> = match body.delivery_address_id {
-        Some(id) => {
-            let delivery_address = get_delivery_address_as_value_with_ownership_check(
-                state.http_client,
-                id,
-                patient_id,
-            )
-            .await;
+    let (first_order_at, last_order_at): (Option<DateTime<Utc>>, Option<DateTime<Utc>>) =
+        orders::table
+            .filter(orders::patient_id.eq(patient_id))
+            .select((min(orders::created_at), max(orders::created_at)))
+            .get_result(conn)
+            .await
+            .context("Failed to get order dates")?;
 
-            match delivery_address {
-                Ok(delivery_address) => Some(delivery_address),
-                Err(_) => None,
-            }
-        }
-        None => None,
-    };
+    let total_spent = billing::patient_total_spent(conn, patient_id).await?;
 
-    let order_type: String = match delivery_address {
-        Some(_) => "DELIVERY".into(),
-        None => "PICKUP".into(),
-    };
+    Ok(StdResponse {
+        data: Some(OrderStatsRes {
+            total_orders: orders_by_status.values().sum(),
+            total_spent,
+            orders_by_status,
+            first_order_at,
+            last_order_at,
+        }),
+        message: Some("Get order stats successfully"),
+    })
+}
 
-    let order = conn
-        .transaction(move |conn| {
-            Box::pin(async move {
-                let order = diesel::insert_into(orders::table)
-                    .values(CreateOrderEntity {
-                        patient_id,
-                        delivery_address,
-                        cart_id: body.cart_id,
-                        status: "PENDING".into(),
-                        order_type,
-                    })
-                    .returning(OrderEntity::as_returning())
-                    .get_result(conn)
-                    .await
-                    .context("Failed to create order")?;
+/// Statuses of orders the patient may still have to act on before they are paid for.
+const ACTIONABLE_STATUSES: &[&str] = &["PENDING", "RESERVED", "PARTIALLY_RESERVED"];
 
-                let order_items: Vec<CartItemEntity> = cart_items::table
-                    .filter(cart_items::cart_id.eq(order.cart_id))
-                    .get_results(conn)
-                    .await
-                    .context("Failed to get cart items")?;
+/// Why an order shows up in the patient's action-needed feed.
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum ActionReason {
+    /// The items are reserved and waiting to be paid for
+    PaymentRequired,
+    /// Only some items could be reserved, and the patient has to accept the rest or cancel
+    PartiallyReserved,
+    /// A product now costs something else than when the order was placed
+    PriceChanged,
+    /// A product no longer exists or cannot be supplied in the ordered quantity
+    OutOfStock,
+}
 
-                let order_items = order_items
-                    .iter()
-                    .map(|item| medbook_events::OrderItem {
-                        product_id: item.product_id,
-                        quantity: item.quantity,
-                    })
-                    .collect();
-
-                outbox::publish(
-                    conn,
-                    "inventory.reserve_order".into(),
-                    medbook_events::OrderRequestedEvent {
-                        order_id: order.id,
-                        order_items,
-                    },
-                )
-                .await?;
+#[derive(Serialize, ToSchema)]
+struct ActionNeededOrder {
+    pub order: OrderEntity,
+    pub order_items: Vec<OrderItemEntity>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total_price: f32,
+    /// Never empty, in the order of the `ActionReason` variants
+    pub reasons: Vec<ActionReason>,
+}
 
-                Ok::<OrderEntity, anyhow::Error>(order)
+/// Reasons the patient has to act on `order`, given the current InventoryService `products`.
+fn action_reasons(
+    order: &OrderEntity,
+    order_items: &[OrderItemEntity],
+    products: &HashMap<i32, ProductDetails>,
+) -> Vec<ActionReason> {
+    let mut reasons = Vec::new();
+    if order.status == "RESERVED" {
+        reasons.push(ActionReason::PaymentRequired);
+    }
+    if order.status == "PARTIALLY_RESERVED" {
+        reasons.push(ActionReason::PartiallyReserved);
+    }
+    if order_items.iter().any(|item| {
+        products
+            .get(&item.product_id)
+            .and_then(|product| product.unit_price)
+            .is_some_and(|unit_price| {
+                (unit_price - item.unit_price_at_order).abs() > billing::AMOUNT_EPSILON
             })
-        })
-        .await
-        .context("Transaction failed")?;
-
-    Ok(StdResponse {
-        data: Some(order),
-        message: Some("Create order succesfully"),
-    })
+    }) {
+        reasons.push(ActionReason::PriceChanged);
+    }
+    if order_items.iter().any(|item| {
+        products
+            .get(&item.product_id)
+            .is_none_or(|product| !product.can_supply(item.quantity))
+    }) {
+        reasons.push(ActionReason::OutOfStock);
+    }
+    reasons
 }
 
-/// Cancel a reserved order for the authenticated patient.
+/// List the orders of the authenticated patient that need their attention, most recently updated
+/// first.
+///
+/// Reserved orders need to be paid for, and partially reserved ones accepted or cancelled. Unpaid
+/// orders whose products changed price or ran out of stock since they were placed are listed too.
+/// Only these candidate orders are checked against InventoryService.
 #[utoipa::path(
-    delete,
-    path = "/{id}",
+    get,
+    path = "/action-needed",
     tags = ["Orders"],
     security(("bearerAuth" = [])),
-    params(
-        ("id" = i32, Path, description = "Order ID to cancel")
-    ),
     responses(
-        (status = 200, description = "Cancelled order successfully", body = StdResponse<OrderEntity, String>)
+        (status = 200, description = "List orders needing action", body = StdResponse<Vec<ActionNeededOrder>, String>)
     )
 )]
-async fn cancel_order(
-    Path(id): Path<i32>,
+async fn get_action_needed_orders(
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
-
-    let cancelled_order = conn
-        .transaction(move |conn| {
-            Box::pin(async move {
-                let cancelled_order: OrderEntity = diesel::update(orders::table.find(id))
-                    .filter(orders::deleted_at.is_null())
-                    .filter(orders::patient_id.eq(patient_id))
-                    .filter(orders::status.eq("RESERVED"))
-                    .set((
-                        orders::deleted_at.eq(diesel::dsl::now),
-                        orders::status.eq("CANCEL_PENDING"),
-                    ))
-                    .returning(OrderEntity::as_returning())
-                    .get_result(conn)
-                    .await
-                    .map_err(|_| AppError::NotFound)?;
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
-                let order_items: Vec<CartItemEntity> = cart_items::table
-                    .filter(cart_items::cart_id.eq(cancelled_order.cart_id))
-                    .get_results(conn)
-                    .await
-                    .context("Failed to get cart items")?;
+    let candidates: Vec<OrderEntity> = orders::table
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(orders::deleted_at.is_null())
+        .filter(orders::status.eq_any(ACTIONABLE_STATUSES))
+        .order_by(orders::updated_at.desc())
+        .get_results(conn)
+        .await
+        .context("Failed to get actionable orders")?;
 
-                let order_items = order_items
-                    .iter()
-                    .map(|item| medbook_events::OrderItem {
-                        product_id: item.product_id,
-                        quantity: item.quantity,
-                    })
-                    .collect();
-
-                outbox::publish(
-                    conn,
-                    "inventory.cancel_order".into(),
-                    OrderCancelledEvent {
-                        order_id: cancelled_order.id,
-                        order_items,
-                    },
-                )
-                .await?;
+    let mut group = order_items::load(conn, state.http_client.clone(), &candidates).await?;
+    let product_ids = group
+        .values()
+        .flatten()
+        .map(|item| item.product_id)
+        .collect();
+    let products = get_products(state.http_client, product_ids).await?;
 
-                Ok::<OrderEntity, AppError>(cancelled_order)
+    let orders: Vec<ActionNeededOrder> = candidates
+        .into_iter()
+        .filter_map(|order| {
+            let order_items = group.remove(&order.id).unwrap_or_default();
+            let reasons = action_reasons(&order, &order_items, &products);
+            if reasons.is_empty() {
+                return None;
+            }
+            Some(ActionNeededOrder {
+                total_price: billing::items_total(&order_items),
+                order,
+                order_items,
+                reasons,
             })
         })
-        .await?;
+        .collect();
 
     Ok(StdResponse {
-        data: Some(cancelled_order),
-        message: Some("Cancelled order successfully"),
+        data: Some(orders),
+        message: Some("Get orders needing action successfully"),
     })
 }
 
+/// Maximum length, in characters, of the delivery notes attached to an order.
+const MAX_NOTES_LENGTH: usize = 500;
+
+/// Trims the notes, treating blank notes as absent, and rejects notes over [`MAX_NOTES_LENGTH`].
+fn validate_notes(notes: Option<String>) -> Result<Option<String>, AppError> {
+    let notes = notes
+        .map(|notes| notes.trim().to_string())
+        .filter(|notes| !notes.is_empty());
+
+    if let Some(notes) = &notes
+        && notes.chars().count() > MAX_NOTES_LENGTH
+    {
+        return Err(AppError::BadRequest(format!(
+            "Notes must be at most {} characters long",
+            MAX_NOTES_LENGTH
+        )));
+    }
+
+    Ok(notes)
+}
+
+/// Rejects delivery times in the past or further ahead than
+/// [`Settings::get_max_schedule_ahead_days`].
+fn validate_scheduled_for(scheduled_for: DateTime<Utc>) -> Result<DateTime<Utc>, AppError> {
+    let now = Utc::now();
+    if scheduled_for <= now {
+        return Err(AppError::BadRequest(
+            "scheduled_for must be in the future".into(),
+        ));
+    }
+
+    let max_days = Settings::get_max_schedule_ahead_days();
+    if scheduled_for > now + chrono::Duration::days(max_days) {
+        return Err(AppError::BadRequest(format!(
+            "scheduled_for must be at most {} days ahead",
+            max_days
+        )));
+    }
+
+    Ok(scheduled_for)
+}
+
+/// Reports notes that would be rejected by [`validate_notes`].
+fn notes_error(notes: Option<&str>) -> Option<FieldError> {
+    notes
+        .filter(|notes| notes.trim().chars().count() > MAX_NOTES_LENGTH)
+        .map(|_| {
+            FieldError::new(
+                "notes",
+                format!("notes must be at most {} characters long", MAX_NOTES_LENGTH),
+            )
+        })
+}
+
 #[derive(Deserialize, ToSchema)]
-pub struct CreatePaymentForOrderReq {
-    pub provider: String,
+struct CreateOrderReq {
+    delivery_address_id: Option<i32>,
+    cart_id: i32,
+    /// Delivery instructions, e.g. "leave at door"
+    notes: Option<String>,
+    /// Defaults to DELIVERY when a delivery address is given, else to the configured default
+    order_type: Option<OrderType>,
+    /// When a DELIVERY order should be delivered, between now and `MAX_SCHEDULE_AHEAD_DAYS` ahead.
+    /// Delivered as soon as possible when left out.
+    scheduled_for: Option<DateTime<Utc>>,
 }
 
-#[derive(Serialize, ToSchema)]
-pub struct CreatePaymentForOrderRes {
-    pub payment: PaymentEntity,
-    pub updated_order: OrderEntity,
+impl Validate for CreateOrderReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.cart_id < 1 {
+            errors.push(FieldError::new("cart_id", "cart_id must be >= 1"));
+        }
+        if self.order_type == Some(OrderType::Delivery) && self.delivery_address_id.is_none() {
+            errors.push(FieldError::new(
+                "delivery_address_id",
+                "delivery_address_id is required for DELIVERY orders",
+            ));
+        }
+        errors.extend(notes_error(self.notes.as_deref()));
+        errors
+    }
 }
 
-/// Create a new payment for an existing order.
+/// Create a new order for the authenticated patient.
+///
+/// Unless `CHECK_STOCK_BEFORE_RESERVE` is off, products InventoryService reports as short of the
+/// carted quantity fail the request right away instead of getting the order rejected later.
+/// Patients with `MAX_ACTIVE_ORDERS` orders in progress cannot place another one until one is over.
 #[utoipa::path(
     post,
-    path = "/{id}/payment",
+    path = "/",
     tags = ["Orders"],
     security(("bearerAuth" = [])),
-    params(
-        ("id" = i32, Path, description = "Order ID to create payment for")
-    ),
-    request_body = CreatePaymentForOrderReq,
+    request_body = CreateOrderReq,
     responses(
-        (status = 200, description = "Created payment successfully", body = StdResponse<CreatePaymentForOrderRes, String>)
+        (status = 200, description = "Created order successfully", body = StdResponse<OrderEntity, String>),
+        (status = 400, description = "Products no longer exist or are out of stock"),
+        (status = 403, description = "Cart belongs to another patient"),
+        (status = 404, description = "Cart not found"),
+        (status = 409, description = "Patient already has the maximum of orders in progress")
     )
 )]
-async fn create_payment_for_order(
-    Path(id): Path<i32>,
+async fn create_order(
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
-    Json(body): Json<CreatePaymentForOrderReq>,
+    ValidatedJson(body): ValidatedJson<CreateOrderReq>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
-
-    match body.provider.as_str() {
-        "qr_payment" => {}
-        _ => {
-            return Err(AppError::BadRequest(format!(
-                "{} is not a valid payment provider",
-                body.provider
-            )));
-        }
-    }
+    let conn = &mut db::acquire(&state.db_pool).await?;
+    let draft = draft_order(conn, state.http_client, patient_id, body).await?;
 
-    let order: OrderEntity = orders::table
-        .find(id)
-        .filter(orders::patient_id.eq(patient_id))
-        .filter(orders::status.eq("RESERVED"))
-        .get_result(conn)
-        .await
-        .map_err(|_| AppError::NotFound)?;
+    let order = conn
+        .transaction(move |conn| {
+            Box::pin(async move { place_order(conn, patient_id, draft).await })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(order),
+        message: Some("Create order succesfully"),
+    })
+}
+
+/// Persists a PENDING order from `draft` and requests the reservation of its items. Must run in a
+/// transaction so the order is not left without its items or reserve request.
+async fn place_order(
+    conn: &mut AsyncPgConnection,
+    patient_id: i32,
+    draft: OrderDraft,
+) -> Result<OrderEntity, AppError> {
+    enforce_max_active_orders(conn, patient_id).await?;
+
+    let order = diesel::insert_into(orders::table)
+        .values(CreateOrderEntity {
+            patient_id,
+            delivery_address: draft.delivery_address,
+            cart_id: draft.cart_id,
+            status: "PENDING".into(),
+            order_type: draft.order_type.as_str().into(),
+            notes: draft.notes,
+            currency: Settings::get_default_currency(),
+            scheduled_for: draft.scheduled_for,
+        })
+        .returning(OrderEntity::as_returning())
+        .get_result(conn)
+        .await
+        .context("Failed to create order")?;
+
+    let order_items =
+        order_items::snapshot(conn, order.id, &draft.cart_items, &draft.products).await?;
+
+    order_status::status_changed(conn, None, &order).await?;
+
+    publish_reserve_request(conn, order.id, &order_items).await?;
+
+    Ok(order)
+}
+
+/// Statuses of orders that are over and done with, so they don't count towards
+/// [`Settings::get_max_active_orders`]. Orders whose reservation timed out can't be cancelled to
+/// make room, so they are over until retried. Rejected orders awaiting a retry still count.
+const TERMINAL_STATUSES: &[&str] = &[
+    "DELIVERED",
+    "CANCELLED",
+    "REJECTED",
+    "RESERVE_TIMEOUT",
+    "EXPIRED",
+];
+
+/// First key of the advisory lock serializing the placement of a patient's orders. The second key
+/// is the patient ID.
+const ACTIVE_ORDERS_LOCK_KEY: i32 = 1002;
+
+/// Rejects a new order of `patient_id` with Conflict once they have
+/// [`Settings::get_max_active_orders`] orders in progress. Deleted orders are not counted. Call it
+/// in the transaction placing the order, so orders placed earlier in the same batch are counted.
+///
+/// Takes the per-patient active orders lock for the rest of the transaction, so concurrent orders
+/// of the patient are counted one after the other and can't exceed the cap together.
+async fn enforce_max_active_orders(
+    conn: &mut AsyncPgConnection,
+    patient_id: i32,
+) -> Result<(), AppError> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock($1, $2)")
+        .bind::<Integer, _>(ACTIVE_ORDERS_LOCK_KEY)
+        .bind::<Integer, _>(patient_id)
+        .execute(conn)
+        .await
+        .context("Failed to lock active orders")?;
+
+    let max_active_orders = Settings::get_max_active_orders(patient_id);
+    let active_orders: i64 = orders::table
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(orders::deleted_at.is_null())
+        .filter(orders::status.ne_all(TERMINAL_STATUSES))
+        .count()
+        .get_result(conn)
+        .await
+        .context("Failed to count active orders")?;
+    if active_orders < max_active_orders {
+        return Ok(());
+    }
+
+    tracing::warn!(
+        patient_id,
+        active_orders,
+        max_active_orders,
+        "Rejected order above the maximum of active orders"
+    );
+    Err(AppError::Conflict(format!(
+        "Patients may have at most {} orders in progress at once",
+        max_active_orders
+    )))
+}
+
+/// Maximum number of orders placed by a single batch request.
+const MAX_BATCH_ORDERS: usize = 20;
+
+#[derive(Deserialize, ToSchema)]
+struct CreateOrdersBatchReq {
+    /// One entry per order to place, each from a different cart
+    orders: Vec<CreateOrderReq>,
+}
+
+impl Validate for CreateOrdersBatchReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.orders.is_empty() || self.orders.len() > MAX_BATCH_ORDERS {
+            errors.push(FieldError::new(
+                "orders",
+                format!(
+                    "orders must have between 1 and {} entries",
+                    MAX_BATCH_ORDERS
+                ),
+            ));
+        }
+
+        let mut cart_ids = HashSet::new();
+        for (index, order) in self.orders.iter().enumerate() {
+            errors.extend(order.validate().into_iter().map(|err| {
+                FieldError::new(
+                    format!("orders[{}].{}", index, err.field),
+                    format!("orders[{}]: {}", index, err.message),
+                )
+            }));
+            if !cart_ids.insert(order.cart_id) {
+                errors.push(FieldError::new(
+                    format!("orders[{}].cart_id", index),
+                    format!("orders[{}].cart_id is already used by another entry", index),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Create one order per cart for the authenticated patient, e.g. to check out several carts of a
+/// business account at once.
+///
+/// Every entry goes through the same checks as a single order. Either all orders are placed, each
+/// with its own reservation request, or none are.
+#[utoipa::path(
+    post,
+    path = "/batch",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    request_body = CreateOrdersBatchReq,
+    responses(
+        (status = 200, description = "Created orders successfully", body = StdResponse<Vec<OrderEntity>, String>),
+        (status = 403, description = "A cart belongs to another patient"),
+        (status = 404, description = "A cart was not found")
+    )
+)]
+async fn create_orders_batch(
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<CreateOrdersBatchReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let mut drafts = Vec::with_capacity(body.orders.len());
+    for order in body.orders {
+        drafts.push(draft_order(conn, state.http_client.clone(), patient_id, order).await?);
+    }
+
+    let orders = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let mut orders = Vec::with_capacity(drafts.len());
+                for draft in drafts {
+                    orders.push(place_order(conn, patient_id, draft).await?);
+                }
+                Ok::<Vec<OrderEntity>, AppError>(orders)
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(orders),
+        message: Some("Created orders successfully"),
+    })
+}
+
+/// Statuses of orders that were called off, which are not repeated.
+const CALLED_OFF_STATUSES: &[&str] = &["CANCELLED", "CANCEL_PENDING"];
+
+/// Place the authenticated patient's most recent order again.
+///
+/// The items of their latest order that was not cancelled are copied into a new cart, which is
+/// ordered right away with the same delivery address, order type and notes. Every product must
+/// still exist and be in stock, and the address must still exist in DeliveryService. Otherwise
+/// nothing is created.
+#[utoipa::path(
+    post,
+    path = "/repeat-last",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    responses(
+        (status = 200, description = "Repeated last order successfully", body = StdResponse<OrderEntity, String>),
+        (status = 400, description = "A product or the delivery address is no longer available"),
+        (status = 404, description = "Patient has no order to repeat")
+    )
+)]
+async fn repeat_last_order(
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let last_order: OrderEntity = orders::table
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(orders::status.ne_all(CALLED_OFF_STATUSES))
+        .order_by((orders::created_at.desc(), orders::id.desc()))
+        .select(OrderEntity::as_select())
+        .first(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+
+    let items: Vec<(i32, i32)> = order_items::reserved_items(conn, &last_order)
+        .await?
+        .into_iter()
+        .map(|item| (item.product_id, item.quantity))
+        .collect();
+    if items.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "Order #{} has no items to repeat",
+            last_order.id
+        )));
+    }
+
+    let delivery_address_id = last_order
+        .delivery_address
+        .as_ref()
+        .and_then(snapshot_address_id);
+    let order_type: OrderType = last_order.order_type.parse()?;
+
+    let product_ids = items.iter().map(|(product_id, _)| *product_id).collect();
+    let unit_prices = last_seen_prices(state.http_client.clone(), product_ids).await;
+
+    let cart_id = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let cart: CartEntity = diesel::insert_into(carts::table)
+                    .values(CreateCartEntity { patient_id })
+                    .returning(CartEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to create cart")?;
+                insert_cart_items(conn, cart.id, items, &unit_prices).await?;
+                Ok::<i32, AppError>(cart.id)
+            })
+        })
+        .await?;
+
+    // The order is drafted outside of a transaction, as create_order does, so no locks are held
+    // while InventoryService and DeliveryService are called.
+    let request = CreateOrderReq {
+        delivery_address_id,
+        cart_id,
+        notes: last_order.notes.clone(),
+        order_type: Some(order_type),
+        scheduled_for: None,
+    };
+    let placed = async {
+        let draft = match draft_order(conn, state.http_client, patient_id, request).await {
+            Err(AppError::NotFound) if delivery_address_id.is_some() => {
+                return Err(AppError::BadRequest(format!(
+                    "Delivery address of order #{} no longer exists",
+                    last_order.id
+                )));
+            }
+            draft => draft?,
+        };
+        // Repeating is a single tap, so stock is checked here whatever the preflight setting says.
+        order_items::ensure_in_stock(&draft.cart_items, &draft.products)?;
+
+        conn.transaction(move |conn| {
+            Box::pin(async move { place_order(conn, patient_id, draft).await })
+        })
+        .await
+    }
+    .await;
+
+    // A failed repeat leaves no stray cart behind to become the patient's current cart.
+    if placed.is_err()
+        && let Err(err) = diesel::delete(carts::table.find(cart_id))
+            .execute(conn)
+            .await
+    {
+        tracing::warn!(
+            "Failed to delete cart #{} of a failed repeat: {:?}",
+            cart_id,
+            err
+        );
+    }
+    let order = placed?;
+
+    Ok(StdResponse {
+        data: Some(order),
+        message: Some("Repeated last order successfully"),
+    })
+}
+
+/// ID the address snapshot of an order had in DeliveryService, if it is recorded in it.
+fn snapshot_address_id(address: &Value) -> Option<i32> {
+    address
+        .get("id")
+        .and_then(Value::as_i64)
+        .and_then(|id| i32::try_from(id).ok())
+}
+
+#[derive(Serialize, ToSchema)]
+struct LastDeliveryAddressRes {
+    /// ID of the address in DeliveryService, `null` if the snapshot does not record it
+    pub delivery_address_id: Option<i32>,
+    pub delivery_address: Value,
+    /// Order the address was last used for
+    pub order_id: i32,
+    pub used_at: DateTime<Utc>,
+}
+
+/// Get the delivery address of the authenticated patient's most recent order, to prefill checkout.
+///
+/// The address is read from the order's snapshot, so it is returned as it was when ordering even if
+/// it has been edited or deleted in DeliveryService since.
+#[utoipa::path(
+    get,
+    path = "/last-address",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    responses(
+        (status = 200, description = "Get last delivery address successfully", body = StdResponse<LastDeliveryAddressRes, String>),
+        (status = 404, description = "Patient has no order with a delivery address")
+    )
+)]
+async fn get_last_delivery_address(
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let (order_id, delivery_address, used_at): (i32, Option<Value>, DateTime<Utc>) = orders::table
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(orders::deleted_at.is_null())
+        .filter(orders::delivery_address.is_not_null())
+        .order_by((orders::created_at.desc(), orders::id.desc()))
+        .select((orders::id, orders::delivery_address, orders::created_at))
+        .first(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+    let delivery_address = delivery_address.ok_or(AppError::NotFound)?;
+
+    Ok(StdResponse {
+        data: Some(LastDeliveryAddressRes {
+            delivery_address_id: snapshot_address_id(&delivery_address),
+            delivery_address,
+            order_id,
+            used_at,
+        }),
+        message: Some("Get last delivery address successfully"),
+    })
+}
+
+/// Checks the result of looking up the owner of an order's cart: missing carts are `NotFound`, and
+/// guest carts and other patients' carts `ForbiddenResource`.
+fn ensure_cart_owner(
+    cart_owner: QueryResult<Option<i32>>,
+    patient_id: i32,
+) -> Result<(), AppError> {
+    match cart_owner {
+        Ok(owner) if owner == Some(patient_id) => Ok(()),
+        Ok(_) => Err(AppError::ForbiddenResource),
+        Err(DieselError::NotFound) => Err(AppError::NotFound),
+        Err(err) => Err(AppError::Other(err.into())),
+    }
+}
+
+/// An order as [`create_order`] would place it, validated but not persisted yet.
+struct OrderDraft {
+    cart_id: i32,
+    delivery_address: Option<Value>,
+    order_type: OrderType,
+    notes: Option<String>,
+    scheduled_for: Option<DateTime<Utc>>,
+    cart_items: Vec<CartItemEntity>,
+    products: HashMap<i32, ProductDetails>,
+    total_price: f32,
+}
+
+/// Runs the checks of [`create_order`] and gathers what the order would be made of. Orders above
+/// the patient's maximum total are rejected through [`billing::enforce_max_order_total`], which may
+/// flag the patient for review.
+async fn draft_order(
+    conn: &mut AsyncPgConnection,
+    client: Client,
+    patient_id: i32,
+    body: CreateOrderReq,
+) -> Result<OrderDraft, AppError> {
+    let draft = collect_order(conn, client, patient_id, body).await?;
+    billing::enforce_max_order_total(
+        conn,
+        patient_id,
+        None,
+        draft.total_price,
+        &Settings::get_default_currency(),
+    )
+    .await?;
+
+    Ok(draft)
+}
+
+/// [`draft_order`] without the maximum total check, writing nothing.
+async fn collect_order(
+    conn: &mut AsyncPgConnection,
+    client: Client,
+    patient_id: i32,
+    body: CreateOrderReq,
+) -> Result<OrderDraft, AppError> {
+    let notes = validate_notes(body.notes)?;
+
+    let delivery_address: Option<Value> = match body.delivery_address_id {
+        Some(id) => Some(
+            get_delivery_address_with_ownership_check(client.clone(), id, patient_id)
+                .await?
+                .to_value()?,
+        ),
+        None => None,
+    };
+
+    let order_type = match (body.order_type, &delivery_address) {
+        (Some(order_type), _) => order_type,
+        (None, Some(_)) => OrderType::Delivery,
+        (None, None) => Settings::get_default_order_type(),
+    };
+    if order_type == OrderType::Delivery && delivery_address.is_none() {
+        return Err(AppError::BadRequest(
+            "DELIVERY orders need a delivery address".into(),
+        ));
+    }
+    let scheduled_for = match body.scheduled_for {
+        Some(_) if order_type != OrderType::Delivery => {
+            return Err(AppError::BadRequest(
+                "Only DELIVERY orders can be scheduled".into(),
+            ));
+        }
+        Some(scheduled_for) => Some(validate_scheduled_for(scheduled_for)?),
+        None => None,
+    };
+
+    let cart_owner = carts::table
+        .find(body.cart_id)
+        .select(carts::patient_id)
+        .get_result(conn)
+        .await;
+    ensure_cart_owner(cart_owner, patient_id)?;
+
+    let cart_items: Vec<CartItemEntity> = cart_items::table
+        .filter(cart_items::cart_id.eq(body.cart_id))
+        .order_by(cart_items::product_id.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+
+    let product_ids = cart_items.iter().map(|item| item.product_id).collect();
+    let products = get_products(client, product_ids).await?;
+    order_items::ensure_products_exist(&cart_items, &products)?;
+    if Settings::get_check_stock_before_reserve() {
+        order_items::ensure_in_stock(&cart_items, &products)?;
+    }
+
+    let total_price: f32 = cart_items
+        .iter()
+        .map(|item| {
+            item.quantity as f32 * products[&item.product_id].unit_price.unwrap_or_default()
+        })
+        .sum();
+
+    Ok(OrderDraft {
+        cart_id: body.cart_id,
+        delivery_address,
+        order_type,
+        notes,
+        scheduled_for,
+        cart_items,
+        products,
+        total_price,
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PreviewOrderReq {
+    #[serde(flatten)]
+    order: CreateOrderReq,
+    /// Coupon to apply to the order
+    coupon_code: Option<String>,
+}
+
+/// Longest coupon code accepted. Codes are letters, digits and dashes.
+const MAX_COUPON_CODE_LENGTH: usize = 32;
+
+impl Validate for PreviewOrderReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = self.order.validate();
+        if let Some(code) = self.coupon_code.as_deref().map(str::trim)
+            && (code.len() > MAX_COUPON_CODE_LENGTH
+                || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        {
+            errors.push(FieldError::new(
+                "coupon_code",
+                format!(
+                    "coupon_code must be at most {} letters, digits or dashes",
+                    MAX_COUPON_CODE_LENGTH
+                ),
+            ));
+        }
+        errors
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct PreviewOrderLine {
+    pub product_id: i32,
+    pub product_name: String,
+    pub quantity: i32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub unit_price: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub line_total: f32,
+    pub available_quantity: Option<i32>,
+    pub is_available: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PreviewOrderRes {
+    pub order_type: OrderType,
+    /// Validated address the order would be delivered to
+    pub delivery_address: Option<Value>,
+    pub notes: Option<String>,
+    pub items: Vec<PreviewOrderLine>,
+    /// Whether InventoryService can currently supply every item
+    pub all_available: bool,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub subtotal: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub discount: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub tax: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub delivery_fee: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total: f32,
+    /// ISO 4217 currency of every amount in the preview
+    pub currency: String,
+}
+
+/// Preview the order `POST /` would create for the authenticated patient.
+///
+/// Runs the same checks and pricing as creating the order and fails with the same errors, but
+/// nothing is persisted or published. Prices and stock levels are the current ones from
+/// InventoryService.
+#[utoipa::path(
+    post,
+    path = "/preview",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    request_body = PreviewOrderReq,
+    responses(
+        (status = 200, description = "Previewed order successfully", body = StdResponse<PreviewOrderRes, String>),
+        (status = 400, description = "Order would be rejected")
+    )
+)]
+async fn preview_order(
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<PreviewOrderReq>,
+) -> Result<impl IntoResponse, AppError> {
+    // No coupons can be issued yet, so any code is unknown.
+    if let Some(code) = body
+        .coupon_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+    {
+        return Err(AppError::BadRequest(format!(
+            "Unknown coupon code {}",
+            code
+        )));
+    }
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+    let draft = collect_order(conn, state.http_client, patient_id, body.order).await?;
+    // A preview only reports the rejection, it never flags the patient.
+    billing::check_max_order_total(
+        patient_id,
+        draft.total_price,
+        &Settings::get_default_currency(),
+    )?;
+
+    let items: Vec<PreviewOrderLine> = draft
+        .cart_items
+        .iter()
+        .map(|item| {
+            let product = &draft.products[&item.product_id];
+            let unit_price = product.unit_price.unwrap_or_default();
+            PreviewOrderLine {
+                product_id: item.product_id,
+                product_name: product.name.clone(),
+                quantity: item.quantity,
+                unit_price,
+                line_total: item.quantity as f32 * unit_price,
+                available_quantity: product.available_quantity,
+                is_available: product.can_supply(item.quantity),
+            }
+        })
+        .collect();
+    let subtotal: f32 = items.iter().map(|item| item.line_total).sum();
+    let (discount, tax, delivery_fee) = (0.0, 0.0, 0.0);
+
+    Ok(StdResponse {
+        data: Some(PreviewOrderRes {
+            order_type: draft.order_type,
+            delivery_address: draft.delivery_address,
+            notes: draft.notes,
+            all_available: items.iter().all(|item| item.is_available),
+            items,
+            subtotal,
+            discount,
+            tax,
+            delivery_fee,
+            total: subtotal - discount + tax + delivery_fee,
+            currency: Settings::get_default_currency(),
+        }),
+        message: Some("Previewed order successfully"),
+    })
+}
+
+/// Asks InventoryService to reserve `order_items` for an order.
+///
+/// The sweeper times reservations out from `orders.updated_at`, so it is bumped here and a request
+/// replacing an earlier one gets the full time to be answered.
+async fn publish_reserve_request(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    order_items: &[OrderItemEntity],
+) -> Result<()> {
+    diesel::update(orders::table.find(order_id))
+        .set(orders::updated_at.eq(Utc::now()))
+        .execute(conn)
+        .await
+        .context("Failed to mark the reservation request")?;
+
+    let order_items = order_items
+        .iter()
+        .map(|item| medbook_events::OrderItem {
+            product_id: item.product_id,
+            quantity: item.quantity,
+        })
+        .collect();
+
+    outbox::publish(
+        conn,
+        routing_keys::INVENTORY_RESERVE_ORDER.into(),
+        medbook_events::OrderRequestedEvent {
+            order_id,
+            order_items,
+        },
+    )
+    .await
+}
+
+/// Cancel a pending or reserved order for the authenticated patient.
+///
+/// Pending orders are cancelled right away. Reserved and partially reserved ones wait in
+/// CANCEL_PENDING until InventoryService confirms their items were released.
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to cancel")
+    ),
+    responses(
+        (status = 200, description = "Cancelled order successfully", body = StdResponse<OrderEntity, String>),
+        (status = 404, description = "Order not found or no longer cancellable")
+    )
+)]
+async fn cancel_order(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let cancelled_order = conn
+        .transaction(move |conn| {
+            Box::pin(async move { cancel_patient_order(conn, id, patient_id).await })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(cancelled_order),
+        message: Some("Cancelled order successfully"),
+    })
+}
+
+/// Statuses in which the order has not been handed over to DeliveryService yet.
+const PRE_DISPATCH_STATUSES: &[&str] = &[
+    "PENDING",
+    "RESERVED",
+    "PARTIALLY_RESERVED",
+    "PAYMENT_PENDING",
+];
+
+#[derive(Deserialize, ToSchema)]
+struct UpdateOrderDeliveryReq {
+    /// New delivery address to ship the order to. Omit to keep the current one.
+    delivery_address_id: Option<i32>,
+    /// New delivery instructions. Omit to keep the current ones.
+    /// Send an empty string to clear them.
+    notes: Option<String>,
+}
+
+impl Validate for UpdateOrderDeliveryReq {
+    fn validate(&self) -> Vec<FieldError> {
+        notes_error(self.notes.as_deref()).into_iter().collect()
+    }
+}
+
+/// Update the delivery address and/or notes of an order that has not been dispatched yet.
+#[utoipa::path(
+    patch,
+    path = "/{id}/delivery-address",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to update")
+    ),
+    request_body = UpdateOrderDeliveryReq,
+    responses(
+        (status = 200, description = "Updated order delivery successfully", body = StdResponse<OrderEntity, String>)
+    )
+)]
+async fn update_order_delivery(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<UpdateOrderDeliveryReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let notes_provided = body.notes.is_some();
+    let notes = validate_notes(body.notes)?;
+
+    let delivery_address: Option<Value> = match body.delivery_address_id {
+        Some(address_id) => Some(
+            get_delivery_address_with_ownership_check(state.http_client, address_id, patient_id)
+                .await?
+                .to_value()?,
+        ),
+        None => None,
+    };
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let updated_order = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let owner: i32 = orders::table
+                    .find(id)
+                    .select(orders::patient_id)
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+                if owner != patient_id {
+                    return Err(AppError::NotFound);
+                }
+
+                // Checked under the lock, so the order can't be dispatched before it is updated.
+                let status = order_status::lock_status(conn, id).await?;
+                if !PRE_DISPATCH_STATUSES.contains(&status.as_str()) {
+                    return Err(AppError::NotFound);
+                }
+
+                let order: OrderEntity = orders::table
+                    .find(id)
+                    .get_result(conn)
+                    .await
+                    .context("Failed to get order")?;
+
+                let (delivery_address, order_type) = match delivery_address {
+                    Some(delivery_address) => (
+                        Some(delivery_address),
+                        OrderType::Delivery.as_str().to_string(),
+                    ),
+                    None => (order.delivery_address, order.order_type),
+                };
+                let notes = if notes_provided { notes } else { order.notes };
+
+                let updated_order = diesel::update(orders::table.find(id))
+                    .set((
+                        orders::delivery_address.eq(delivery_address),
+                        orders::order_type.eq(order_type),
+                        orders::notes.eq(notes),
+                    ))
+                    .returning(OrderEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to update order")?;
+
+                Ok::<OrderEntity, AppError>(updated_order)
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(updated_order),
+        message: Some("Updated order delivery successfully"),
+    })
+}
+
+/// Status a patient's order in `status` moves to when they cancel it, if it can be cancelled.
+fn cancelled_status(status: &str) -> Option<&'static str> {
+    match status {
+        "PENDING" => Some("CANCELLED"),
+        "RESERVED" | "PARTIALLY_RESERVED" => Some("CANCEL_PENDING"),
+        _ => None,
+    }
+}
+
+/// Cancels a patient's PENDING, RESERVED or PARTIALLY_RESERVED order and asks InventoryService to
+/// release its items. Must be called inside a transaction; returns `NotFound` if the order is not
+/// cancellable.
+///
+/// Orders with reserved items move to CANCEL_PENDING until the release is confirmed. PENDING orders
+/// move straight to CANCELLED, and the release is requested in case the reservation is already
+/// under way.
+pub(crate) async fn cancel_patient_order(
+    conn: &mut AsyncPgConnection,
+    id: i32,
+    patient_id: i32,
+) -> Result<OrderEntity, AppError> {
+    // The order stays locked until the transaction ends, so no consumer can move it on between the
+    // status check and the events published below.
+    let status: String = orders::table
+        .find(id)
+        .filter(orders::deleted_at.is_null())
+        .filter(orders::patient_id.eq(patient_id))
+        .select(orders::status)
+        .for_update()
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+    let new_status = cancelled_status(&status).ok_or(AppError::NotFound)?;
+
+    let cancelled_order: OrderEntity = diesel::update(orders::table.find(id))
+        .set((
+            orders::deleted_at.eq(diesel::dsl::now),
+            orders::status.eq(new_status),
+        ))
+        .returning(OrderEntity::as_returning())
+        .get_result(conn)
+        .await
+        .map_err(|_| AppError::NotFound)?;
+
+    order_status::status_changed(conn, Some(&status), &cancelled_order).await?;
+
+    let order_items = order_items::reserved_items(conn, &cancelled_order).await?;
+    outbox::publish(
+        conn,
+        routing_keys::INVENTORY_CANCEL_ORDER.into(),
+        OrderCancelledEvent {
+            order_id: cancelled_order.id,
+            order_items,
+        },
+    )
+    .await?;
+
+    Ok(cancelled_order)
+}
+
+#[derive(Serialize, ToSchema)]
+struct AcceptPartialReservationRes {
+    pub order: OrderEntity,
+    /// What is left of the order once the backordered units are dropped
+    pub order_items: Vec<OrderItemEntity>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total_price: f32,
+}
+
+/// Go ahead with the items InventoryService could reserve for a partially reserved order.
+///
+/// The backordered units are dropped from the order, which becomes RESERVED and can be paid for as
+/// usual. To give up on the order instead, cancel it.
+#[utoipa::path(
+    post,
+    path = "/{id}/accept-partial",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to go ahead with")
+    ),
+    responses(
+        (status = 200, description = "Accepted partial reservation successfully", body = StdResponse<AcceptPartialReservationRes, String>),
+        (status = 404, description = "Order not found"),
+        (status = 409, description = "Order is not partially reserved")
+    )
+)]
+async fn accept_partial_reservation(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let result = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let owner: i32 = orders::table
+                    .find(id)
+                    .filter(orders::deleted_at.is_null())
+                    .select(orders::patient_id)
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+                if owner != patient_id {
+                    return Err(AppError::NotFound);
+                }
+
+                let status = order_status::lock_status(conn, id).await?;
+                if status != "PARTIALLY_RESERVED" {
+                    return Err(AppError::Conflict(format!(
+                        "Order in {} status has no partial reservation to accept",
+                        status
+                    )));
+                }
+
+                let order_items = order_items::drop_backorders(conn, id).await?;
+                if order_items.is_empty() {
+                    return Err(AppError::Conflict(
+                        "No items of the order could be reserved, cancel it instead".into(),
+                    ));
+                }
+
+                let order = diesel::update(orders::table.find(id))
+                    .set(orders::status.eq("RESERVED"))
+                    .returning(OrderEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to update order")?;
+                order_status::status_changed(conn, Some(&status), &order).await?;
+
+                Ok::<AcceptPartialReservationRes, AppError>(AcceptPartialReservationRes {
+                    total_price: billing::items_total(&order_items),
+                    order,
+                    order_items,
+                })
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(result),
+        message: Some("Accepted partial reservation successfully"),
+    })
+}
+
+/// Statuses from which an order that never got reserved can be sent for reservation again.
+const RETRYABLE_STATUSES: &[&str] = &["REJECTED", "RETRYABLE", "RESERVE_TIMEOUT"];
+
+/// Re-attempt the inventory reservation of a rejected order.
+///
+/// The order keeps its ID and history and goes back to PENDING with the cart's current items.
+#[utoipa::path(
+    post,
+    path = "/{id}/retry",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to retry")
+    ),
+    responses(
+        (status = 200, description = "Retried order successfully", body = StdResponse<OrderEntity, String>)
+    )
+)]
+async fn retry_order(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    // Products are priced before the order is locked, so InventoryService is not called while the
+    // lock is held. The items are checked against them again under the lock.
+    let cart_id: i32 = orders::table
+        .find(id)
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(orders::deleted_at.is_null())
+        .select(orders::cart_id)
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+    let product_ids: Vec<i32> = cart_items::table
+        .filter(cart_items::cart_id.eq(cart_id))
+        .select(cart_items::product_id)
+        .get_results(conn)
+        .await
+        .context("Failed to get cart items")?;
+    let products = get_products(state.http_client, product_ids.clone()).await?;
+
+    let retried_order = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                let order: OrderEntity = orders::table
+                    .find(id)
+                    .filter(orders::patient_id.eq(patient_id))
+                    .filter(orders::deleted_at.is_null())
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                if !RETRYABLE_STATUSES.contains(&order.status.as_str()) {
+                    return Err(AppError::Conflict(format!(
+                        "Order in {} status cannot be retried",
+                        order.status
+                    )));
+                }
+
+                let order_items: Vec<CartItemEntity> = cart_items::table
+                    .filter(cart_items::cart_id.eq(order.cart_id))
+                    .get_results(conn)
+                    .await
+                    .context("Failed to get cart items")?;
+
+                if order_items.is_empty() {
+                    return Err(AppError::BadRequest("Order has no items to reserve".into()));
+                }
+                if order_items
+                    .iter()
+                    .any(|item| !product_ids.contains(&item.product_id))
+                {
+                    return Err(AppError::Conflict(
+                        "Cart items changed, please try again".into(),
+                    ));
+                }
+
+                let retried_order: OrderEntity = diesel::update(orders::table.find(order.id))
+                    .set(orders::status.eq("PENDING"))
+                    .returning(OrderEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to update order status")?;
+
+                order_status::status_changed(conn, Some(&order.status), &retried_order).await?;
+                // The retry reserves the cart's current items, so they are priced anew as well.
+                let order_items =
+                    order_items::snapshot(conn, retried_order.id, &order_items, &products).await?;
+                publish_reserve_request(conn, retried_order.id, &order_items).await?;
+
+                Ok::<OrderEntity, AppError>(retried_order)
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(retried_order),
+        message: Some("Retried order successfully"),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct ChangedOrderItemsRes {
+    pub order: OrderEntity,
+    /// Items of the order after the change
+    pub order_items: Vec<OrderItemEntity>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total_price: f32,
+}
+
+/// Fails with Conflict unless the items of `order` may still change, i.e. it is PENDING.
+fn ensure_items_editable(order: &OrderEntity) -> Result<(), AppError> {
+    if order.status != "PENDING" {
+        return Err(AppError::Conflict(format!(
+            "Items of an order in {} status can no longer be changed",
+            order.status
+        )));
+    }
+    Ok(())
+}
+
+/// Remove a single item from a pending order without touching the cart it was placed from.
+///
+/// InventoryService is asked to reserve the remaining items instead. Removing the last item is
+/// rejected; cancel the order instead.
+#[utoipa::path(
+    delete,
+    path = "/{id}/items/{product_id}",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to remove the item from"),
+        ("product_id" = i32, Path, description = "Product to remove from the order")
+    ),
+    responses(
+        (status = 200, description = "Removed order item successfully", body = StdResponse<ChangedOrderItemsRes, String>),
+        (status = 404, description = "Order or item not found"),
+        (status = 409, description = "Order is no longer PENDING or has no other items")
+    )
+)]
+async fn remove_order_item(
+    ValidatedPath((id, product_id)): ValidatedPath<(i32, i32)>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let (order, order_items) = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                // Locking the order keeps InventoryService's answer from moving it past PENDING
+                // while the reservation request is being replaced.
+                let order: OrderEntity = orders::table
+                    .find(id)
+                    .filter(orders::patient_id.eq(patient_id))
+                    .filter(orders::deleted_at.is_null())
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                ensure_items_editable(&order)?;
+
+                let order_items = order_items::remove(conn, order.id, product_id)
+                    .await?
+                    .ok_or(AppError::NotFound)?;
+                if order_items.is_empty() {
+                    return Err(AppError::Conflict(
+                        "Order has no other items, cancel it instead".into(),
+                    ));
+                }
+
+                publish_reserve_request(conn, order.id, &order_items).await?;
+
+                Ok::<(OrderEntity, Vec<OrderItemEntity>), AppError>((order, order_items))
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(ChangedOrderItemsRes {
+            order,
+            total_price: billing::items_total(&order_items),
+            order_items,
+        }),
+        message: Some("Removed order item successfully"),
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+struct UpdateOrderItemsReq {
+    /// New quantities of products already on the order. Products left out keep their quantity.
+    order_items: Vec<UpdateOrderItemsReqItem>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct UpdateOrderItemsReqItem {
+    product_id: i32,
+    quantity: i32,
+}
+
+impl Validate for UpdateOrderItemsReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self.order_items.is_empty() {
+            errors.push(FieldError::new(
+                "order_items",
+                "order_items must not be empty",
+            ));
+        }
+
+        let max_quantity = Settings::get_max_item_quantity();
+        let mut product_ids = HashSet::new();
+        for (index, item) in self.order_items.iter().enumerate() {
+            if !product_ids.insert(item.product_id) {
+                errors.push(FieldError::new(
+                    format!("order_items[{}].product_id", index),
+                    format!("order_items[{}].product_id is listed more than once", index),
+                ));
+            }
+            if item.quantity < 1 || item.quantity > max_quantity {
+                errors.push(FieldError::new(
+                    format!("order_items[{}].quantity", index),
+                    format!(
+                        "order_items[{}].quantity must be between 1 and {}",
+                        index, max_quantity
+                    ),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Change the quantities of items of a pending order without touching the cart it was placed from.
+///
+/// Items keep the price recorded when the order was placed. InventoryService is asked to reserve
+/// the new quantities, superseding the earlier reservation request. To drop an item, use
+/// `DELETE /{id}/items/{product_id}` instead.
+#[utoipa::path(
+    patch,
+    path = "/{id}/items",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID whose items should change")
+    ),
+    request_body = UpdateOrderItemsReq,
+    responses(
+        (status = 200, description = "Updated order items successfully", body = StdResponse<ChangedOrderItemsRes, String>),
+        (status = 404, description = "Order not found or a product is not on it"),
+        (status = 409, description = "Order is no longer PENDING")
+    )
+)]
+async fn update_order_items(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    ValidatedJson(body): ValidatedJson<UpdateOrderItemsReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let quantities: HashMap<i32, i32> = body
+        .order_items
+        .iter()
+        .map(|item| (item.product_id, item.quantity))
+        .collect();
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    // The cap is checked up front since flagging the patient must not be rolled back with the
+    // rejection. The transaction below checks the order again under its lock.
+    let order: OrderEntity = orders::table
+        .find(id)
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(orders::deleted_at.is_null())
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+    ensure_items_editable(&order)?;
+    let current_items: Vec<OrderItemEntity> = order_items::table
+        .filter(order_items::order_id.eq(order.id))
+        .get_results(conn)
+        .await
+        .context("Failed to get order items")?;
+    let new_total: f32 = current_items
+        .iter()
+        .map(|item| {
+            let quantity = quantities.get(&item.product_id).copied();
+            quantity.unwrap_or(item.quantity) as f32 * item.unit_price_at_order
+        })
+        .sum();
+    billing::enforce_max_order_total(conn, patient_id, Some(order.id), new_total, &order.currency)
+        .await?;
+
+    let (order, order_items) = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                // Locking the order keeps InventoryService's answer from moving it past PENDING
+                // while the reservation request is being replaced.
+                let order: OrderEntity = orders::table
+                    .find(id)
+                    .filter(orders::patient_id.eq(patient_id))
+                    .filter(orders::deleted_at.is_null())
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                ensure_items_editable(&order)?;
+
+                let quantities: Vec<(i32, i32)> = quantities.into_iter().collect();
+                let order_items = order_items::set_quantities(conn, order.id, &quantities)
+                    .await?
+                    .ok_or(AppError::NotFound)?;
+
+                publish_reserve_request(conn, order.id, &order_items).await?;
+
+                Ok::<(OrderEntity, Vec<OrderItemEntity>), AppError>((order, order_items))
+            })
+        })
+        .await?;
+
+    Ok(StdResponse {
+        data: Some(ChangedOrderItemsRes {
+            order,
+            total_price: billing::items_total(&order_items),
+            order_items,
+        }),
+        message: Some("Updated order items successfully"),
+    })
+}
 
-    let order_items: Vec<CartItemEntity> = cart_items::table
-        .filter(cart_items::cart_id.eq(order.cart_id))
-        .get_results(conn)
+#[derive(Deserialize, ToSchema)]
+pub struct CreatePaymentForOrderReq {
+    pub provider: String,
+    /// Amount to pay now. Defaults to the whole outstanding balance.
+    #[serde(default, with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    pub amount: Option<f32>,
+}
+
+/// Longest payment provider name accepted, well above any configured one.
+const MAX_PROVIDER_LENGTH: usize = 64;
+
+impl Validate for CreatePaymentForOrderReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let providers = Settings::get_payment_providers();
+        if self.provider.trim().is_empty() {
+            errors.push(FieldError::new("provider", "provider must not be empty"));
+        } else if self.provider.len() > MAX_PROVIDER_LENGTH
+            || !self
+                .provider
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            // Don't echo the value, which may be huge or hold control characters.
+            errors.push(FieldError::new(
+                "provider",
+                format!(
+                    "provider must be at most {} letters, digits, dashes or underscores",
+                    MAX_PROVIDER_LENGTH
+                ),
+            ));
+        } else if !providers.contains(&self.provider) {
+            errors.push(FieldError::new(
+                "provider",
+                format!(
+                    "{} is not a valid payment provider, expected one of: {}",
+                    self.provider,
+                    providers.join(", ")
+                ),
+            ));
+        }
+        if self
+            .amount
+            .is_some_and(|amount| !amount.is_finite() || amount <= 0.0)
+        {
+            errors.push(FieldError::new("amount", "amount must be > 0"));
+        }
+        errors
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatePaymentForOrderRes {
+    pub payment: PaymentEntity,
+    pub updated_order: OrderEntity,
+}
+
+/// Statuses of orders that can still take payments.
+const PAYABLE_STATUSES: &[&str] = &["RESERVED", "PAYMENT_PENDING"];
+/// Statuses of payments that count against an order's outstanding balance.
+const COMMITTED_PAYMENT_STATUSES: &[&str] = &["PENDING", "PAID", "PARTIALLY_REFUNDED"];
+
+/// Header clients send to make payment creation safe to retry.
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Maximum length of an idempotency key, matching `payments.idempotency_key`.
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+
+/// Reads the optional [`IDEMPOTENCY_KEY_HEADER`], rejecting blank or overlong keys.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    let key = value.to_str().map(str::trim).unwrap_or_default();
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Idempotency-Key must be between 1 and {} ASCII characters",
+            MAX_IDEMPOTENCY_KEY_LENGTH
+        )));
+    }
+
+    Ok(Some(key.to_string()))
+}
+
+/// Finds the payment an earlier request with the same idempotency key created for a patient's
+/// order, together with the order.
+async fn find_idempotent_payment(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    patient_id: i32,
+    key: &str,
+) -> Result<Option<(OrderEntity, PaymentEntity)>> {
+    payments::table
+        .inner_join(orders::table)
+        .filter(payments::order_id.eq(order_id))
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(payments::idempotency_key.eq(key))
+        .select((OrderEntity::as_select(), PaymentEntity::as_select()))
+        .first(conn)
         .await
-        .context("Failed to get order items")?;
+        .optional()
+        .context("Failed to get payment by idempotency key")
+}
 
-    let cart_item_ids = order_items.iter().map(|item| item.product_id).collect();
-    let unit_prices = get_product_unit_prices(state.http_client, cart_item_ids).await?;
-    let total_price: f32 = order_items
-        .iter()
-        .map(|item| unit_prices.get(&item.product_id).copied().unwrap_or(0.0))
-        .sum();
+/// Create a new payment for an existing order.
+///
+/// Orders can be paid in several parts. A payment defaults to the whole outstanding balance and may
+/// not exceed it, counting payments that are still pending.
+///
+/// Requests carrying an `Idempotency-Key` that was already used for this order return the payment
+/// created by the first request instead of creating another one.
+#[utoipa::path(
+    post,
+    path = "/{id}/payment",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to create payment for"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Client-chosen key deduplicating retried requests")
+    ),
+    request_body = CreatePaymentForOrderReq,
+    responses(
+        (status = 200, description = "Created payment successfully", body = StdResponse<CreatePaymentForOrderRes, String>)
+    )
+)]
+async fn create_payment_for_order(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    headers: HeaderMap,
+    ValidatedJson(body): ValidatedJson<CreatePaymentForOrderReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let idempotency_key = idempotency_key(&headers)?;
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    if let Some(key) = &idempotency_key
+        && let Some((updated_order, payment)) =
+            find_idempotent_payment(conn, id, patient_id, key).await?
+    {
+        return Ok(StdResponse {
+            data: Some(CreatePaymentForOrderRes {
+                payment,
+                updated_order,
+            }),
+            message: Some("Created payment successfully"),
+        });
+    }
+
+    let order: OrderEntity = orders::table
+        .find(id)
+        .filter(orders::patient_id.eq(patient_id))
+        .filter(orders::status.eq_any(PAYABLE_STATUSES))
+        .get_result(conn)
+        .await
+        .map_err(|_| AppError::NotFound)?;
+
+    let total_price = billing::order_total(conn, state.http_client, &order).await?;
+    billing::enforce_max_order_total(
+        conn,
+        patient_id,
+        Some(order.id),
+        total_price,
+        &order.currency,
+    )
+    .await?;
+
+    let (updated_order, payment) =
+        create_payment(conn, id, patient_id, total_price, body, idempotency_key).await?;
 
-    let (updated_order, payment) = conn
+    Ok(StdResponse {
+        data: Some(CreatePaymentForOrderRes {
+            payment,
+            updated_order,
+        }),
+        message: Some("Created payment successfully"),
+    })
+}
+
+/// Creates a payment for a patient's payable order priced at `total_price`, or returns the one an
+/// earlier request with the same `idempotency_key` created.
+async fn create_payment(
+    conn: &mut AsyncPgConnection,
+    id: i32,
+    patient_id: i32,
+    total_price: f32,
+    body: CreatePaymentForOrderReq,
+    idempotency_key: Option<String>,
+) -> Result<(OrderEntity, PaymentEntity), AppError> {
+    // Serializable so a retried attempt sees payments committed by a concurrent one.
+    db::serializable(conn, move |conn| {
+        let idempotency_key = idempotency_key.clone();
+        let provider = body.provider.clone();
+        let amount = body.amount;
+        Box::pin(async move {
+            // Locking the order keeps concurrent payments from overshooting the total together.
+            let order: OrderEntity = orders::table
+                .find(id)
+                .filter(orders::patient_id.eq(patient_id))
+                .filter(orders::status.eq_any(PAYABLE_STATUSES))
+                .for_update()
+                .get_result(conn)
+                .await
+                // Other errors, serialization failures included, must reach the retry loop.
+                .map_err(|err| match err {
+                    DieselError::NotFound => AppError::NotFound,
+                    _ => AppError::Other(err.into()),
+                })?;
+
+            // A concurrent request with the same key may have committed while we awaited the lock.
+            if let Some(key) = &idempotency_key
+                && let Some(existing) =
+                    find_idempotent_payment(conn, order.id, patient_id, key).await?
+            {
+                return Ok(existing);
+            }
+
+            let committed =
+                billing::payments_sum(conn, order.id, COMMITTED_PAYMENT_STATUSES).await?;
+            let outstanding = total_price - committed;
+            if outstanding <= billing::AMOUNT_EPSILON {
+                return Err(AppError::BadRequest(
+                    "Order is already covered by its payments".into(),
+                ));
+            }
+
+            let amount = amount.unwrap_or(outstanding);
+            if amount > outstanding + billing::AMOUNT_EPSILON {
+                return Err(AppError::BadRequest(format!(
+                    "Payment of {:.2} exceeds the outstanding balance of {:.2}",
+                    amount, outstanding
+                )));
+            }
+
+            let updated_order = if order.status == "RESERVED" {
+                let updated_order = diesel::update(orders::table.find(order.id))
+                    .set(orders::status.eq("PAYMENT_PENDING"))
+                    .returning(OrderEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to update order")?;
+
+                order_status::status_changed(conn, Some("RESERVED"), &updated_order).await?;
+                updated_order
+            } else {
+                order
+            };
+
+            let payment = diesel::insert_into(payments::table)
+                .values(CreatePaymentEntity {
+                    order_id: updated_order.id,
+                    amount,
+                    provider,
+                    status: "PENDING".into(),
+                    currency: updated_order.currency.clone(),
+                    idempotency_key,
+                })
+                .returning(PaymentEntity::as_returning())
+                .get_result(conn)
+                .await
+                .context("Failed to create payment")?;
+
+            Ok::<(OrderEntity, PaymentEntity), AppError>((updated_order, payment))
+        })
+    })
+    .await
+}
+
+/// Rejects abandoning the payment of an order that is not waiting for payment, or that already has
+/// `paid_payments` and must be refunded instead.
+fn ensure_payment_abandonable(order_status: &str, paid_payments: i64) -> Result<(), AppError> {
+    if order_status != "PAYMENT_PENDING" {
+        return Err(AppError::Conflict(format!(
+            "Order in {} status has no payment to abandon",
+            order_status
+        )));
+    }
+    if paid_payments > 0 {
+        return Err(AppError::Conflict(
+            "Order has already been paid for and must be refunded instead".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize, ToSchema)]
+struct AbandonOrderPaymentRes {
+    pub cancelled_payments: Vec<PaymentEntity>,
+    pub updated_order: OrderEntity,
+}
+
+/// Abandon the pending payment of an order so the patient can pay with another provider.
+///
+/// Every PENDING payment is cancelled and the order goes back to RESERVED. Orders that already
+/// received a PAID payment cannot be reset this way and must be refunded instead.
+#[utoipa::path(
+    delete,
+    path = "/{id}/payment",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to abandon the payment of")
+    ),
+    responses(
+        (status = 200, description = "Abandoned payment successfully", body = StdResponse<AbandonOrderPaymentRes, String>),
+        (status = 409, description = "Order has no pending payment or is already partly paid")
+    )
+)]
+async fn abandon_order_payment(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let (updated_order, cancelled_payments) = conn
         .transaction(move |conn| {
             Box::pin(async move {
-                let updated_order = diesel::update(
-                    orders::table
-                        .find(id)
-                        .filter(orders::patient_id.eq(patient_id))
-                        .filter(orders::status.eq("RESERVED")),
+                let order: OrderEntity = orders::table
+                    .find(id)
+                    .filter(orders::patient_id.eq(patient_id))
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .map_err(|err| match err {
+                        DieselError::NotFound => AppError::NotFound,
+                        _ => AppError::Other(err.into()),
+                    })?;
+
+                let paid: i64 = payments::table
+                    .filter(payments::order_id.eq(order.id))
+                    .filter(payments::status.eq_any(billing::PAID_PAYMENT_STATUSES))
+                    .count()
+                    .get_result(conn)
+                    .await
+                    .context("Failed to count paid payments")?;
+                ensure_payment_abandonable(&order.status, paid)?;
+
+                let cancelled_payments: Vec<PaymentEntity> = diesel::update(
+                    payments::table
+                        .filter(payments::order_id.eq(order.id))
+                        .filter(payments::status.eq("PENDING")),
                 )
-                .set(orders::status.eq("PAYMENT_PENDING"))
-                .returning(OrderEntity::as_returning())
-                .get_result(conn)
+                .set(payments::status.eq("CANCELLED"))
+                .returning(PaymentEntity::as_returning())
+                .get_results(conn)
                 .await
-                .context("Failed to update order")?;
-
-                let payment = diesel::insert_into(payments::table)
-                    .values(CreatePaymentEntity {
-                        order_id: updated_order.id,
-                        amount: total_price,
-                        provider: body.provider,
-                        status: "PENDING".into(),
-                    })
-                    .returning(PaymentEntity::as_returning())
+                .context("Failed to cancel payments")?;
+                if cancelled_payments.is_empty() {
+                    return Err(AppError::Conflict(
+                        "Order has no pending payment to abandon".into(),
+                    ));
+                }
+
+                let updated_order: OrderEntity = diesel::update(orders::table.find(order.id))
+                    .set(orders::status.eq("RESERVED"))
+                    .returning(OrderEntity::as_returning())
                     .get_result(conn)
                     .await
-                    .context("Failed to create payment")?;
+                    .context("Failed to update order")?;
+
+                order_status::status_changed(conn, Some(&order.status), &updated_order).await?;
 
-                Ok::<(OrderEntity, PaymentEntity), AppError>((updated_order, payment))
+                Ok::<(OrderEntity, Vec<PaymentEntity>), AppError>((
+                    updated_order,
+                    cancelled_payments,
+                ))
             })
         })
-        .await
-        .context("Transaction failed")?;
+        .await?;
 
     Ok(StdResponse {
-        data: Some(CreatePaymentForOrderRes {
-            payment,
+        data: Some(AbandonOrderPaymentRes {
+            cancelled_payments,
             updated_order,
         }),
-        message: Some("Created payment successfully"),
+        message: Some("Abandoned payment successfully"),
     })
 }
 
@@ -537,15 +2550,11 @@ async fn create_payment_for_order(
     )
 )]
 async fn get_order_payments(
-    Path(id): Path<i32>,
+    ValidatedPath(id): ValidatedPath<i32>,
     State(state): State<AppState>,
     Extension(patient_id): Extension<i32>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
-        .await
-        .context("Failed to obtain a DB connection pool")?;
+    let conn = &mut db::acquire(&state.db_pool).await?;
 
     let _: OrderEntity = orders::table
         .find(id)
@@ -566,3 +2575,472 @@ async fn get_order_payments(
         message: Some("Get payments successfully"),
     })
 }
+
+/// Representations a receipt can be rendered in, chosen from the `Accept` header.
+enum ReceiptFormat {
+    Json,
+}
+
+impl ReceiptFormat {
+    /// Picks the first supported media type the client accepts. Missing `Accept` means JSON.
+    fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Some(ReceiptFormat::Json);
+        };
+
+        accept
+            .split(',')
+            .filter_map(|media_type| media_type.split(';').next())
+            .find_map(|media_type| match media_type.trim() {
+                "application/json" | "application/*" | "*/*" => Some(ReceiptFormat::Json),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReceiptLine {
+    pub product_id: i32,
+    pub product_name: String,
+    pub quantity: i32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub unit_price: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub line_total: f32,
+}
+
+/// One paid part of an order's payment.
+#[derive(Serialize, ToSchema)]
+struct ReceiptPayment {
+    pub payment_id: Uuid,
+    pub provider: String,
+    pub provider_ref: Option<String>,
+    /// Amount charged by the payment provider
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub amount: f32,
+    /// Part of `amount` refunded so far
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub refunded_amount: f32,
+    pub paid_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReceiptRes {
+    pub order_id: i32,
+    pub items: Vec<ReceiptLine>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub subtotal: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub discount: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub tax: f32,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total: f32,
+    /// Sum of every paid part, less refunds
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub amount_paid: f32,
+    /// ISO 4217 currency of every amount on the receipt
+    pub currency: String,
+    /// Paid parts of the payment, in the order they were paid
+    pub payments: Vec<ReceiptPayment>,
+    pub ordered_at: DateTime<Utc>,
+    /// When the last part was paid
+    pub paid_at: DateTime<Utc>,
+}
+
+/// Get the receipt of a paid order belonging to the authenticated patient, listing every paid part
+/// of split payments.
+///
+/// Only JSON is rendered for now. Other representations requested through `Accept` are answered
+/// with 406.
+#[utoipa::path(
+    get,
+    path = "/{id}/receipt",
+    tags = ["Orders"],
+    security(("bearerAuth" = [])),
+    params(
+        ("id" = i32, Path, description = "Order ID to get the receipt of")
+    ),
+    responses(
+        (status = 200, description = "Get receipt successfully", body = StdResponse<ReceiptRes, String>),
+        (status = 406, description = "Requested receipt format is not supported"),
+        (status = 409, description = "Order has not been paid yet")
+    )
+)]
+async fn get_order_receipt(
+    ValidatedPath(id): ValidatedPath<i32>,
+    State(state): State<AppState>,
+    Extension(patient_id): Extension<i32>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let Some(format) = ReceiptFormat::negotiate(&headers) else {
+        return Ok(error_response(
+            StatusCode::NOT_ACCEPTABLE,
+            "Receipts are only available as application/json".into(),
+        ));
+    };
+
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let order: OrderEntity = orders::table
+        .find(id)
+        .filter(orders::patient_id.eq(patient_id))
+        .get_result(conn)
+        .await
+        .map_err(|_| AppError::NotFound)?;
+
+    let order_items = order_items::load(conn, state.http_client, std::slice::from_ref(&order))
+        .await?
+        .remove(&order.id)
+        .unwrap_or_default();
+
+    let items: Vec<ReceiptLine> = order_items
+        .into_iter()
+        .map(|item| ReceiptLine {
+            product_id: item.product_id,
+            line_total: item.line_total(),
+            product_name: item.product_name,
+            quantity: item.quantity,
+            unit_price: item.unit_price_at_order,
+        })
+        .collect();
+    let subtotal: f32 = items.iter().map(|item| item.line_total).sum();
+    let (discount, tax) = (0.0, 0.0);
+    let total = subtotal - discount + tax;
+
+    // Split payments only make a receipt once their paid parts cover the whole order.
+    let amount_paid = billing::payments_sum(conn, order.id, billing::PAID_PAYMENT_STATUSES).await?;
+    let paid_payments: Vec<PaymentEntity> = payments::table
+        .filter(payments::order_id.eq(order.id))
+        .filter(payments::status.eq_any(billing::PAID_PAYMENT_STATUSES))
+        .order_by(payments::updated_at.asc())
+        .get_results(conn)
+        .await
+        .context("Failed to get order payments")?;
+    let Some(last_payment) = paid_payments.last() else {
+        return Err(AppError::Conflict("Order has not been paid yet".into()));
+    };
+    if amount_paid + billing::AMOUNT_EPSILON < total {
+        return Err(AppError::Conflict("Order has not been paid yet".into()));
+    }
+
+    let receipt = ReceiptRes {
+        order_id: order.id,
+        items,
+        subtotal,
+        discount,
+        tax,
+        total,
+        amount_paid,
+        currency: last_payment.currency.clone(),
+        paid_at: last_payment.updated_at,
+        ordered_at: order.created_at,
+        payments: paid_payments
+            .into_iter()
+            .map(|payment| ReceiptPayment {
+                payment_id: payment.id,
+                provider: payment.provider,
+                provider_ref: payment.provider_ref,
+                amount: payment.amount,
+                refunded_amount: payment.refunded_amount,
+                paid_at: payment.updated_at,
+            })
+            .collect(),
+    };
+
+    match format {
+        ReceiptFormat::Json => Ok(StdResponse {
+            data: Some(receipt),
+            message: Some("Get receipt successfully"),
+        }
+        .into_response()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpaid_orders_awaiting_payment_can_abandon_it() {
+        assert!(ensure_payment_abandonable("PAYMENT_PENDING", 0).is_ok());
+    }
+
+    #[test]
+    fn paid_orders_cannot_abandon_their_payment() {
+        assert!(matches!(
+            ensure_payment_abandonable("PAYMENT_PENDING", 1),
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn orders_not_awaiting_payment_have_none_to_abandon() {
+        assert!(matches!(
+            ensure_payment_abandonable("RESERVED", 0),
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    fn headers_with_idempotency_key(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            IDEMPOTENCY_KEY_HEADER,
+            axum::http::HeaderValue::from_str(key).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn idempotency_key_is_optional() {
+        assert_eq!(idempotency_key(&HeaderMap::new()).ok(), Some(None));
+    }
+
+    #[test]
+    fn idempotency_key_is_trimmed() {
+        let headers = headers_with_idempotency_key(" retry-1 ");
+
+        assert_eq!(
+            idempotency_key(&headers).ok(),
+            Some(Some("retry-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn idempotency_key_accepts_the_maximum_length() {
+        let key = "k".repeat(MAX_IDEMPOTENCY_KEY_LENGTH);
+
+        assert_eq!(
+            idempotency_key(&headers_with_idempotency_key(&key)).ok(),
+            Some(Some(key))
+        );
+    }
+
+    #[test]
+    fn idempotency_key_rejects_blank_and_overlong_keys() {
+        let overlong = "k".repeat(MAX_IDEMPOTENCY_KEY_LENGTH + 1);
+        for key in ["  ", overlong.as_str()] {
+            assert!(matches!(
+                idempotency_key(&headers_with_idempotency_key(key)),
+                Err(AppError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn orders_can_be_placed_from_own_carts() {
+        assert!(ensure_cart_owner(Ok(Some(7)), 7).is_ok());
+    }
+
+    #[test]
+    fn orders_cannot_be_placed_from_missing_carts() {
+        assert!(matches!(
+            ensure_cart_owner(Err(DieselError::NotFound), 7),
+            Err(AppError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn orders_cannot_be_placed_from_other_patients_carts() {
+        assert!(matches!(
+            ensure_cart_owner(Ok(Some(8)), 7),
+            Err(AppError::ForbiddenResource)
+        ));
+    }
+
+    #[test]
+    fn orders_cannot_be_placed_from_guest_carts() {
+        assert!(matches!(
+            ensure_cart_owner(Ok(None), 7),
+            Err(AppError::ForbiddenResource)
+        ));
+    }
+
+    #[test]
+    fn pending_orders_are_cancelled_right_away() {
+        assert_eq!(cancelled_status("PENDING"), Some("CANCELLED"));
+    }
+
+    #[test]
+    fn reserved_orders_wait_for_the_release() {
+        assert_eq!(cancelled_status("RESERVED"), Some("CANCEL_PENDING"));
+        assert_eq!(
+            cancelled_status("PARTIALLY_RESERVED"),
+            Some("CANCEL_PENDING")
+        );
+    }
+
+    #[test]
+    fn orders_past_reservation_cannot_be_cancelled() {
+        for status in [
+            "PAYMENT_PENDING",
+            "DELIVERY_PENDING",
+            "CANCELLED",
+            "REJECTED",
+        ] {
+            assert_eq!(cancelled_status(status), None);
+        }
+    }
+
+    fn preview(coupon_code: &str) -> PreviewOrderReq {
+        serde_json::from_value(serde_json::json!({ "cart_id": 1, "coupon_code": coupon_code }))
+            .unwrap()
+    }
+
+    fn payment(provider: &str) -> CreatePaymentForOrderReq {
+        serde_json::from_value(serde_json::json!({ "provider": provider })).unwrap()
+    }
+
+    fn error_fields(errors: Vec<FieldError>) -> Vec<String> {
+        errors.into_iter().map(|error| error.field).collect()
+    }
+
+    #[test]
+    fn coupon_codes_are_letters_digits_and_dashes() {
+        assert!(preview("SPRING-25").validate().is_empty());
+        assert!(
+            preview(&"A".repeat(MAX_COUPON_CODE_LENGTH))
+                .validate()
+                .is_empty()
+        );
+        for code in ["SPRING 25", "<script>", "ส่วนลด"] {
+            assert_eq!(error_fields(preview(code).validate()), ["coupon_code"]);
+        }
+    }
+
+    #[test]
+    fn overlong_coupon_codes_are_rejected() {
+        let code = "A".repeat(MAX_COUPON_CODE_LENGTH + 1);
+        assert_eq!(error_fields(preview(&code).validate()), ["coupon_code"]);
+    }
+
+    #[test]
+    fn configured_payment_providers_are_accepted() {
+        assert!(Settings::get_payment_providers().contains(&"qr_payment".to_string()));
+        assert!(payment("qr_payment").validate().is_empty());
+    }
+
+    #[test]
+    fn unknown_payment_providers_list_the_accepted_ones() {
+        let errors = payment("cash_on_delivery").validate();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("expected one of: qr_payment"));
+    }
+
+    #[test]
+    fn malformed_payment_providers_are_not_echoed() {
+        let provider = format!("{}\n", "x".repeat(MAX_PROVIDER_LENGTH));
+        let errors = payment(&provider).validate();
+        assert_eq!(errors.len(), 1);
+        assert!(!errors[0].message.contains(&provider));
+        assert_eq!(error_fields(payment("qr payment").validate()), ["provider"]);
+    }
+
+    #[test]
+    fn blank_payment_providers_are_rejected() {
+        assert_eq!(error_fields(payment("  ").validate()), ["provider"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn orders_whose_reservation_timed_out_leave_room_under_the_cap() {
+        let conn = &mut crate::db::tests::connect_rolled_back().await;
+        let patient_id = -1156;
+        let cart_id: i32 = diesel::insert_into(carts::table)
+            .values(carts::patient_id.eq(patient_id))
+            .returning(carts::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        let max_active_orders = Settings::get_max_active_orders(patient_id) as usize;
+
+        // The timed out order doesn't count, so it makes room for one more in progress.
+        let statuses = std::iter::repeat_n("PENDING", max_active_orders - 1)
+            .chain(["RESERVE_TIMEOUT", "PENDING"]);
+        for status in statuses {
+            assert!(enforce_max_active_orders(conn, patient_id).await.is_ok());
+            diesel::insert_into(orders::table)
+                .values((
+                    orders::cart_id.eq(cart_id),
+                    orders::patient_id.eq(patient_id),
+                    orders::status.eq(status),
+                ))
+                .execute(conn)
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            enforce_max_active_orders(conn, patient_id).await,
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn concurrent_payments_with_one_idempotency_key_create_one_payment() {
+        use crate::db::tests::connect;
+
+        // The requests only race across committed connections, so the fixtures are committed too
+        // and deleted at the end.
+        let setup = &mut connect().await;
+        let patient_id = -1105;
+        let cart_id: i32 = diesel::insert_into(carts::table)
+            .values(carts::patient_id.eq(patient_id))
+            .returning(carts::id)
+            .get_result(setup)
+            .await
+            .unwrap();
+        // Already awaiting payment, so creating one changes no status and publishes nothing.
+        let order_id: i32 = diesel::insert_into(orders::table)
+            .values((
+                orders::cart_id.eq(cart_id),
+                orders::patient_id.eq(patient_id),
+                orders::status.eq("PAYMENT_PENDING"),
+            ))
+            .returning(orders::id)
+            .get_result(setup)
+            .await
+            .unwrap();
+        let (first, second) = (&mut connect().await, &mut connect().await);
+        let request = || CreatePaymentForOrderReq {
+            provider: "qr_payment".into(),
+            amount: None,
+        };
+        let key = || Some("retry-1".to_string());
+
+        let (first, second) = tokio::join!(
+            create_payment(first, order_id, patient_id, 10.0, request(), key()),
+            create_payment(second, order_id, patient_id, 10.0, request(), key()),
+        );
+
+        let payment_count: i64 = payments::table
+            .filter(payments::order_id.eq(order_id))
+            .count()
+            .get_result(setup)
+            .await
+            .unwrap();
+        // Deleting the cart deletes its order and payments too.
+        diesel::delete(carts::table.find(cart_id))
+            .execute(setup)
+            .await
+            .unwrap();
+        let (Ok((_, first)), Ok((_, second))) = (first, second) else {
+            panic!("Both requests should have returned a payment");
+        };
+        assert_eq!(first.id, second.id);
+        assert_eq!(payment_count, 1);
+    }
+}
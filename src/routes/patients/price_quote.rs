@@ -0,0 +1,143 @@
+use axum::{extract::State, response::IntoResponse};
+use medbook_core::{
+    app_error::{AppError, StdResponse},
+    app_state::AppState,
+    middleware::{self},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::{
+    api::products::get_products,
+    extract::{FieldError, Validate, ValidatedJson},
+    middleware::json_body_guard,
+    settings::Settings,
+};
+
+/// Defines the patient-facing price quote route.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
+        "/patients/price-quote",
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(create_price_quote))
+            .route_layer(axum::middleware::from_fn(
+                middleware::patients_authorization,
+            ))
+            .route_layer(axum::middleware::from_fn(json_body_guard)),
+    )
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PriceQuoteReq {
+    pub items: Vec<PriceQuoteReqItem>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PriceQuoteReqItem {
+    pub product_id: i32,
+    pub quantity: i32,
+}
+
+impl Validate for PriceQuoteReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let max_items = Settings::get_max_cart_items();
+        if self.items.len() > max_items {
+            errors.push(FieldError::new(
+                "items",
+                format!("At most {} items can be quoted at once", max_items),
+            ));
+        }
+        for (index, item) in self.items.iter().enumerate() {
+            if item.product_id < 1 {
+                errors.push(FieldError::new(
+                    format!("items[{}].product_id", index),
+                    format!("items[{}].product_id must be >= 1", index),
+                ));
+            }
+            if item.quantity < 1 {
+                errors.push(FieldError::new(
+                    format!("items[{}].quantity", index),
+                    format!("items[{}].quantity must be >= 1", index),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct PriceQuoteLine {
+    pub product_id: i32,
+    pub quantity: i32,
+    pub product_name: Option<String>,
+    /// Current unit price, or `null` if the product does not exist
+    #[serde(with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    pub unit_price: Option<f32>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub line_total: f32,
+    pub available_quantity: Option<i32>,
+    pub is_available: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PriceQuoteRes {
+    pub items: Vec<PriceQuoteLine>,
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total_price: f32,
+    /// ISO 4217 currency of the prices
+    pub currency: String,
+}
+
+/// Price a hypothetical list of items, e.g. for an "add to cart" preview.
+///
+/// Prices and stock levels are the current ones from InventoryService. No cart or order is created.
+#[utoipa::path(
+    post,
+    path = "/",
+    tags = ["Carts"],
+    security(("bearerAuth" = [])),
+    request_body = PriceQuoteReq,
+    responses(
+        (status = 200, description = "Quoted prices successfully", body = StdResponse<PriceQuoteRes, String>)
+    )
+)]
+async fn create_price_quote(
+    State(state): State<AppState>,
+    ValidatedJson(body): ValidatedJson<PriceQuoteReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let product_ids = body.items.iter().map(|item| item.product_id).collect();
+    let products = get_products(state.http_client, product_ids).await?;
+
+    let items: Vec<PriceQuoteLine> = body
+        .items
+        .into_iter()
+        .map(|item| {
+            let product = products.get(&item.product_id);
+            let unit_price = product.and_then(|product| product.unit_price);
+            PriceQuoteLine {
+                product_id: item.product_id,
+                quantity: item.quantity,
+                product_name: product.map(|product| product.name.clone()),
+                unit_price,
+                line_total: item.quantity as f32 * unit_price.unwrap_or(0.0),
+                available_quantity: product.and_then(|product| product.available_quantity),
+                is_available: product.is_some_and(|product| product.can_supply(item.quantity)),
+            }
+        })
+        .collect();
+    let total_price = items.iter().map(|item| item.line_total).sum();
+
+    Ok(StdResponse {
+        data: Some(PriceQuoteRes {
+            items,
+            total_price,
+            currency: Settings::get_default_currency(),
+        }),
+        message: Some("Quoted prices successfully"),
+    })
+}
@@ -1,124 +1,628 @@
+use std::collections::HashMap;
+
 use anyhow::Context;
 use axum::{
-    Router,
-    extract::{Path, State},
+    extract::{OriginalUri, Query, State},
     response::IntoResponse,
-    routing,
 };
-use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
-use diesel_async::{AsyncConnection, RunQueryDsl};
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper, dsl::sum, pg::Pg};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use medbook_core::{
+    aliases::DieselError,
     app_error::{AppError, StdResponse},
     app_state::AppState,
     outbox,
 };
-use medbook_events::DeliveryOrderRequestEvent;
-use serde::Serialize;
-use utoipa::ToSchema;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use utoipa_axum::router::OpenApiRouter;
 use uuid::Uuid;
 
 use crate::{
+    billing, db,
+    events::PaymentRefundRequestedEvent,
+    extract::{FieldError, Validate, ValidatedJson, ValidatedPath},
+    middleware::{self, json_body_guard},
     models::{OrderEntity, PaymentEntity},
+    order_status,
+    pagination::{PaginatedResponse, PaginationParams},
+    routing_keys,
     schema::{
         orders::{self},
         payments,
     },
+    settings::Settings,
 };
 
-/// Defines all patient-facing order routes (CRUD operations + authorization).
-#[deprecated]
-pub fn routes() -> Router<AppState> {
-    Router::new().nest(
+/// Defines all payment routes.
+pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
+    utoipa_axum::router::OpenApiRouter::new().nest(
         "/payments",
-        Router::new().route("/{id}/mock-pay", routing::patch(mock_pay)),
+        OpenApiRouter::new()
+            .routes(utoipa_axum::routes!(mock_pay))
+            .routes(utoipa_axum::routes!(get_payment_providers))
+            .route_layer(axum::middleware::from_fn(json_body_guard))
+            .merge(
+                OpenApiRouter::new()
+                    .routes(utoipa_axum::routes!(get_payments))
+                    .routes(utoipa_axum::routes!(refund_payment))
+                    .route_layer(axum::middleware::from_fn(json_body_guard))
+                    .route_layer(axum::middleware::from_fn(middleware::admins_authorization)),
+            ),
     )
 }
 
-/// Defines routes with OpenAPI specs. Should be used over `routes()` where possible.
-pub fn routes_with_openapi() -> OpenApiRouter<AppState> {
-    utoipa_axum::router::OpenApiRouter::new().nest(
-        "/payments",
-        OpenApiRouter::new().routes(utoipa_axum::routes!(mock_pay)),
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PaymentFilters {
+    /// Only include payments with this status
+    pub status: Option<String>,
+    /// Only include payments made through this provider
+    pub provider: Option<String>,
+    /// Only include payments created at or after this instant
+    pub from: Option<DateTime<Utc>>,
+    /// Only include payments created at or before this instant
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Builds the payment query shared by the listing and its totals.
+fn filtered_payments(filters: &PaymentFilters) -> payments::BoxedQuery<'static, Pg> {
+    let mut query = payments::table.into_boxed();
+
+    if let Some(status) = &filters.status {
+        query = query.filter(payments::status.eq(status.clone()));
+    }
+    if let Some(provider) = &filters.provider {
+        query = query.filter(payments::provider.eq(provider.clone()));
+    }
+    if let Some(from) = filters.from {
+        query = query.filter(payments::created_at.ge(from));
+    }
+    if let Some(to) = filters.to {
+        query = query.filter(payments::created_at.le(to));
+    }
+
+    query
+}
+
+/// List the payment providers patients can currently pay with.
+#[utoipa::path(
+    get,
+    path = "/providers",
+    tags = ["Payments"],
+    responses(
+        (status = 200, description = "Get payment providers successfully", body = StdResponse<Vec<String>, String>)
+    )
+)]
+async fn get_payment_providers() -> Result<impl IntoResponse, AppError> {
+    Ok(StdResponse {
+        data: Some(Settings::get_payment_providers()),
+        message: Some("Get payment providers successfully"),
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+struct PaymentWithPatient {
+    #[serde(flatten)]
+    pub payment: PaymentEntity,
+    pub patient_id: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct GetPaymentsRes {
+    /// Payments matching the filters
+    #[serde(flatten)]
+    pub page: PaginatedResponse<PaymentWithPatient>,
+    /// Sum of the amounts of every payment matching the filters, across all pages
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    pub total_amount: f32,
+}
+
+/// List payments for reconciliation, optionally filtered by status, provider and creation date.
+#[utoipa::path(
+    get,
+    path = "/",
+    tags = ["Payments"],
+    params(PaymentFilters, PaginationParams),
+    responses(
+        (status = 200, description = "Get payments successfully", body = StdResponse<GetPaymentsRes, String>)
     )
+)]
+async fn get_payments(
+    Query(filters): Query<PaymentFilters>,
+    Query(pagination): Query<PaginationParams>,
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+) -> Result<impl IntoResponse, AppError> {
+    let pagination = pagination.clamp(Settings::get_pagination_limits());
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let payments: Vec<PaymentEntity> = filtered_payments(&filters)
+        .order_by((payments::created_at.desc(), payments::id.asc()))
+        .limit(pagination.per_page())
+        .offset(pagination.offset())
+        .get_results(conn)
+        .await
+        .context("Failed to get payments")?;
+
+    let (total_count, total_amount): (i64, Option<f32>) = filtered_payments(&filters)
+        .select((diesel::dsl::count_star(), sum(payments::amount)))
+        .get_result(conn)
+        .await
+        .context("Failed to sum payments")?;
+
+    let order_ids: Vec<i32> = payments.iter().map(|payment| payment.order_id).collect();
+    let patient_ids: HashMap<i32, i32> = orders::table
+        .filter(orders::id.eq_any(&order_ids))
+        .select((orders::id, orders::patient_id))
+        .get_results(conn)
+        .await
+        .context("Failed to get payment orders")?
+        .into_iter()
+        .collect();
+
+    let payments = payments
+        .into_iter()
+        .map(|payment| PaymentWithPatient {
+            patient_id: patient_ids.get(&payment.order_id).copied(),
+            payment,
+        })
+        .collect();
+
+    Ok((
+        pagination.headers(&uri, total_count),
+        StdResponse {
+            data: Some(GetPaymentsRes {
+                page: pagination.response(payments, total_count),
+                total_amount: total_amount.unwrap_or(0.0),
+            }),
+            message: Some("Get payments successfully"),
+        },
+    ))
 }
 
 #[derive(Serialize, ToSchema)]
 pub struct MockPayRes {
     updated_payment: PaymentEntity,
     updated_order: OrderEntity,
+    /// What is left to pay on the order after this payment
+    #[serde(with = "crate::money::as_string")]
+    #[schema(value_type = String)]
+    outstanding_balance: f32,
+}
+
+/// Statuses an order can be in once one of its payments has been paid.
+const PAID_ORDER_STATUSES: &[&str] = &["PAYMENT_PENDING", "DELIVERY_PENDING", "DELIVERED"];
+
+/// Rejects paying a payment unless it is pending on an order waiting for payment, or already paid.
+fn ensure_payable(payment_status: &str, order_status: &str) -> Result<(), AppError> {
+    match payment_status {
+        "PENDING" if order_status == "PAYMENT_PENDING" => Ok(()),
+        "PAID" if PAID_ORDER_STATUSES.contains(&order_status) => Ok(()),
+        _ => Err(AppError::Conflict(format!(
+            "Payment is {} and its order is {}",
+            payment_status, order_status
+        ))),
+    }
 }
 
 /// Mock payment operation for demonstration purposes.
+///
+/// The order only moves on to delivery once its paid payments cover the order total. Paying an
+/// already paid payment again returns the current state without side effects, so retries are safe.
 #[utoipa::path(
     post,
-    path = "/{id}/mock-pay",
+    path = "/{payment_id}/mock-pay",
     tags = ["Payments"],
     params(
-        ("id" = Uuid, Path, description = "Payment ID to mark as paid")
+        ("payment_id" = Uuid, Path, description = "Payment ID to mark as paid")
     ),
     responses(
-        (status = 200, description = "Payment successfully marked as paid", body = StdResponse<MockPayRes, String>)
+        (status = 200, description = "Payment successfully marked as paid", body = StdResponse<MockPayRes, String>),
+        (status = 404, description = "Payment not found"),
+        (status = 409, description = "Payment or its order can no longer be paid")
     )
 )]
 pub async fn mock_pay(
-    Path(id): Path<Uuid>,
+    ValidatedPath(id): ValidatedPath<Uuid>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let conn = &mut state
-        .db_pool
-        .get()
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let (payment, order): (PaymentEntity, OrderEntity) = payments::table
+        .find(id)
+        .inner_join(orders::table)
+        .select((PaymentEntity::as_select(), OrderEntity::as_select()))
+        .get_result(conn)
         .await
-        .context("Failed to obtain a DB connection pool")?;
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
 
-    let (updated_payment, updated_order) = conn
-        .transaction(move |conn| {
-            Box::pin(async move {
-                let updated_payment = diesel::update(
-                    payments::table
-                        .find(id)
-                        .filter(payments::status.eq("PENDING")),
-                )
+    ensure_payable(&payment.status, &order.status)?;
+
+    let total_price = billing::order_total(conn, state.http_client, &order).await?;
+
+    let (updated_payment, updated_order, outstanding_balance) =
+        pay(conn, id, order.id, total_price).await?;
+
+    Ok(StdResponse {
+        data: Some(MockPayRes {
+            updated_order,
+            updated_payment,
+            outstanding_balance,
+        }),
+        message: Some("Payment paid successfully"),
+    })
+}
+
+/// Marks a payment of order `order_id`, priced at `total_price`, as paid and hands the order over
+/// to DeliveryService once it is paid in full. Returns the payment, the order and what is left to
+/// pay on it. Payments that are already paid are returned as they are.
+async fn pay(
+    conn: &mut AsyncPgConnection,
+    id: Uuid,
+    order_id: i32,
+    total_price: f32,
+) -> Result<(PaymentEntity, OrderEntity, f32), AppError> {
+    db::serializable(conn, move |conn| {
+        Box::pin(async move {
+            // Locking the order and then the payment keeps consumers and concurrent calls from
+            // changing either between the status check and the events below.
+            let current_status = order_status::lock_status(conn, order_id).await?;
+            let payment: PaymentEntity = payments::table
+                .find(id)
+                .for_update()
+                .get_result(conn)
+                .await
+                .context("Failed to get payment")?;
+            ensure_payable(&payment.status, &current_status)?;
+
+            // Paying again returns the current state, so only the call that actually flips the
+            // payment to PAID runs the side effects.
+            if payment.status == "PAID" {
+                let paid =
+                    billing::payments_sum(conn, order_id, billing::PAID_PAYMENT_STATUSES).await?;
+                let current_order = orders::table
+                    .find(order_id)
+                    .get_result(conn)
+                    .await
+                    .context("Failed to get order")?;
+                return Ok::<(PaymentEntity, OrderEntity, f32), AppError>((
+                    payment,
+                    current_order,
+                    (total_price - paid).max(0.0),
+                ));
+            }
+
+            let updated_payment = diesel::update(payments::table.find(id))
                 .set(payments::status.eq("PAID"))
                 .returning(PaymentEntity::as_returning())
                 .get_result(conn)
                 .await
                 .context("Failed to update payment status")?;
 
-                let updated_order = diesel::update(
-                    orders::table
-                        .find(updated_payment.order_id)
-                        .filter(orders::status.eq("PAYMENT_PENDING")),
-                )
-                .set(orders::status.eq("DELIVERY_PENDING"))
-                .returning(OrderEntity::as_returning())
-                .get_result(conn)
-                .await
-                .context("Failed to update order status")?;
+            let (updated_order, outstanding_balance) =
+                billing::dispatch_if_paid(conn, order_id, total_price).await?;
+
+            Ok::<(PaymentEntity, OrderEntity, f32), AppError>((
+                updated_payment,
+                updated_order,
+                outstanding_balance,
+            ))
+        })
+    })
+    .await
+}
+
+/// Payment statuses that still have money left to refund.
+const REFUNDABLE_STATUSES: &[&str] = &["PAID", "PARTIALLY_REFUNDED"];
+
+#[derive(Deserialize, ToSchema)]
+struct RefundPaymentReq {
+    /// Amount to refund. Defaults to everything not refunded yet.
+    #[serde(default, with = "crate::money::option_as_string")]
+    #[schema(value_type = Option<String>)]
+    amount: Option<f32>,
+}
+
+impl Validate for RefundPaymentReq {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if self
+            .amount
+            .is_some_and(|amount| !amount.is_finite() || amount <= 0.0)
+        {
+            errors.push(FieldError::new("amount", "amount must be > 0"));
+        }
+        errors
+    }
+}
+
+/// A refund of a payment, as it is about to be recorded.
+#[derive(Debug, PartialEq)]
+struct Refund {
+    amount: f32,
+    status: &'static str,
+    refunded_amount: f32,
+}
+
+/// Works out a refund of `requested`, or of everything left when `None`, from a payment of `paid`
+/// of which `refunded` was already refunded. Refunds larger than what is left are rejected.
+fn plan_refund(
+    paid: f32,
+    refunded: f32,
+    requested: Option<f32>,
+    currency: &str,
+) -> Result<Refund, AppError> {
+    let refundable = paid - refunded;
+    let amount = requested.unwrap_or(refundable);
+    if amount > refundable + billing::AMOUNT_EPSILON {
+        return Err(AppError::BadRequest(format!(
+            "Refund of {:.2} exceeds the {:.2} {} left to refund",
+            amount, refundable, currency
+        )));
+    }
+
+    let fully_refunded = refundable - amount <= billing::AMOUNT_EPSILON;
+    let (status, refunded_amount) = if fully_refunded {
+        ("REFUNDED", paid)
+    } else {
+        ("PARTIALLY_REFUNDED", refunded + amount)
+    };
+
+    Ok(Refund {
+        amount,
+        status,
+        refunded_amount,
+    })
+}
+
+/// Refund part or all of a paid payment, e.g. when a single item is returned.
+///
+/// The refund may not exceed what was paid minus what was already refunded. The payment becomes
+/// REFUNDED once nothing is left to refund and PARTIALLY_REFUNDED before that. The provider is
+/// asked to move the money through a `payments.refund_requested` event.
+#[utoipa::path(
+    post,
+    path = "/{payment_id}/refund",
+    tags = ["Payments"],
+    params(
+        ("payment_id" = Uuid, Path, description = "Payment ID to refund")
+    ),
+    request_body = RefundPaymentReq,
+    responses(
+        (status = 200, description = "Refunded payment successfully", body = StdResponse<PaymentEntity, String>),
+        (status = 400, description = "Amount exceeds what is left to refund"),
+        (status = 404, description = "Payment not found"),
+        (status = 409, description = "Payment has not been paid or is already fully refunded")
+    )
+)]
+async fn refund_payment(
+    ValidatedPath(id): ValidatedPath<Uuid>,
+    State(state): State<AppState>,
+    ValidatedJson(body): ValidatedJson<RefundPaymentReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = &mut db::acquire(&state.db_pool).await?;
+
+    let order_id: i32 = payments::table
+        .find(id)
+        .select(payments::order_id)
+        .get_result(conn)
+        .await
+        .map_err(|err| match err {
+            DieselError::NotFound => AppError::NotFound,
+            _ => AppError::Other(err.into()),
+        })?;
+
+    let payment = conn
+        .transaction(move |conn| {
+            Box::pin(async move {
+                // Orders are locked before their payments, as everywhere else.
+                order_status::lock_status(conn, order_id).await?;
+                let payment: PaymentEntity = payments::table
+                    .find(id)
+                    .for_update()
+                    .get_result(conn)
+                    .await
+                    .context("Failed to get payment")?;
+
+                if !REFUNDABLE_STATUSES.contains(&payment.status.as_str()) {
+                    return Err(AppError::Conflict(format!(
+                        "Payment in {} status cannot be refunded",
+                        payment.status
+                    )));
+                }
+
+                let refund = plan_refund(
+                    payment.amount,
+                    payment.refunded_amount,
+                    body.amount,
+                    &payment.currency,
+                )?;
+
+                let payment = diesel::update(payments::table.find(id))
+                    .set((
+                        payments::status.eq(refund.status),
+                        payments::refunded_amount.eq(refund.refunded_amount),
+                    ))
+                    .returning(PaymentEntity::as_returning())
+                    .get_result(conn)
+                    .await
+                    .context("Failed to update payment refund")?;
 
                 outbox::publish(
                     conn,
-                    "delivery.order_request".into(),
-                    DeliveryOrderRequestEvent {
-                        delivery_address: updated_order.delivery_address.clone(),
-                        order_id: updated_order.id.clone(),
-                        order_type: updated_order.order_type.clone(),
+                    routing_keys::PAYMENT_REFUND_REQUESTED.into(),
+                    PaymentRefundRequestedEvent {
+                        payment_id: payment.id,
+                        order_id: payment.order_id,
+                        provider: payment.provider.clone(),
+                        provider_ref: payment.provider_ref.clone(),
+                        amount: refund.amount,
+                        refunded_amount: payment.refunded_amount,
+                        currency: payment.currency.clone(),
+                        timestamp: Utc::now(),
                     },
                 )
-                .await
-                .context("Failed to send outbox")?;
+                .await?;
 
-                Ok::<(PaymentEntity, OrderEntity), AppError>((updated_payment, updated_order))
+                Ok::<PaymentEntity, AppError>(payment)
             })
         })
-        .await
-        .context("Transaction failed")?;
+        .await?;
 
     Ok(StdResponse {
-        data: Some(MockPayRes {
-            updated_order,
-            updated_payment,
-        }),
-        message: Some("Payment paid successfully"),
+        data: Some(payment),
+        message: Some("Refunded payment successfully"),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_payments_of_orders_awaiting_payment_are_payable() {
+        assert!(ensure_payable("PENDING", "PAYMENT_PENDING").is_ok());
+    }
+
+    #[test]
+    fn paying_an_already_paid_payment_again_is_allowed() {
+        for order_status in PAID_ORDER_STATUSES {
+            assert!(ensure_payable("PAID", order_status).is_ok());
+        }
+    }
+
+    #[test]
+    fn payments_of_orders_in_the_wrong_state_conflict() {
+        assert!(matches!(
+            ensure_payable("PENDING", "CANCELLED"),
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            ensure_payable("PAID", "CANCELLED"),
+            Err(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            ensure_payable("FAILED", "PAYMENT_PENDING"),
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn partial_refunds_leave_the_payment_partially_refunded() {
+        assert_eq!(
+            plan_refund(100.0, 0.0, Some(30.0), "THB").ok(),
+            Some(Refund {
+                amount: 30.0,
+                status: "PARTIALLY_REFUNDED",
+                refunded_amount: 30.0,
+            })
+        );
+    }
+
+    #[test]
+    fn refunds_default_to_everything_left() {
+        assert_eq!(
+            plan_refund(100.0, 30.0, None, "THB").ok(),
+            Some(Refund {
+                amount: 70.0,
+                status: "REFUNDED",
+                refunded_amount: 100.0,
+            })
+        );
+    }
+
+    #[test]
+    fn refunding_the_rest_fully_refunds_the_payment() {
+        let refund = plan_refund(10.0, 3.3, Some(6.7), "THB").ok();
+
+        assert_eq!(refund.map(|refund| refund.status), Some("REFUNDED"));
+    }
+
+    #[test]
+    fn refunds_cannot_exceed_what_is_left() {
+        assert!(matches!(
+            plan_refund(100.0, 30.0, Some(80.0), "THB"),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn refund_amounts_must_be_positive() {
+        for amount in [0.0, -5.0, f32::NAN] {
+            let req = RefundPaymentReq {
+                amount: Some(amount),
+            };
+            assert_eq!(req.validate().len(), 1);
+        }
+        assert!(RefundPaymentReq { amount: None }.validate().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at DATABASE_URL"]
+    async fn paying_twice_dispatches_the_order_once() {
+        use crate::{
+            db::tests::connect,
+            schema::{carts, outbox},
+        };
+
+        // Payments are paid in serializable transactions of their own, which can't be nested in a
+        // rolled back test transaction. The fixtures are committed and deleted at the end instead.
+        let conn = &mut connect().await;
+        let patient_id = -1094;
+        let cart_id: i32 = diesel::insert_into(carts::table)
+            .values(carts::patient_id.eq(patient_id))
+            .returning(carts::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        let order_id: i32 = diesel::insert_into(orders::table)
+            .values((
+                orders::cart_id.eq(cart_id),
+                orders::patient_id.eq(patient_id),
+                orders::status.eq("PAYMENT_PENDING"),
+            ))
+            .returning(orders::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        let payment_id: Uuid = diesel::insert_into(payments::table)
+            .values((
+                payments::order_id.eq(order_id),
+                payments::amount.eq(10.0_f32),
+            ))
+            .returning(payments::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+
+        let first = pay(conn, payment_id, order_id, 10.0).await;
+        let second = pay(conn, payment_id, order_id, 10.0).await;
+
+        let events: Vec<String> = outbox::table
+            .filter(outbox::order_id.eq(order_id))
+            .select(outbox::event_type)
+            .get_results(conn)
+            .await
+            .unwrap();
+        diesel::delete(outbox::table.filter(outbox::order_id.eq(order_id)))
+            .execute(conn)
+            .await
+            .unwrap();
+        // Deleting the cart deletes its order and payments too.
+        diesel::delete(carts::table.find(cart_id))
+            .execute(conn)
+            .await
+            .unwrap();
+        assert!(first.is_ok() && second.is_ok());
+        let published =
+            |routing_key: &str| events.iter().filter(|event| *event == routing_key).count();
+        assert_eq!(published(routing_keys::DELIVERY_ORDER_REQUEST), 1);
+        assert_eq!(published(routing_keys::ORDER_STATUS_CHANGED), 1);
+    }
+}
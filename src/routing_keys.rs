@@ -0,0 +1,25 @@
+//! Routing keys of every event this service publishes or consumes. Always go through these
+//! constants instead of string literals so a typo can't route an event nowhere.
+
+// Published through the outbox
+
+pub const INVENTORY_RESERVE_ORDER: &str = "inventory.reserve_order";
+pub const INVENTORY_CANCEL_ORDER: &str = "inventory.cancel_order";
+pub const DELIVERY_ORDER_REQUEST: &str = "delivery.order_request";
+pub const ORDER_RETRYABLE: &str = "order.retryable";
+pub const ORDER_RESERVE_TIMEOUT: &str = "order.reserve_timeout";
+pub const ORDER_STATUS_CHANGED: &str = "order.status_changed";
+pub const PATIENT_FLAGGED_FOR_REVIEW: &str = "patient.flagged_for_review";
+pub const PAYMENT_REFUND_REQUESTED: &str = "payments.refund_requested";
+pub const NOTIFICATIONS_EMAIL: &str = "notifications.email";
+
+// Consumed by this service
+
+pub const ORDER_REJECTED: &str = "orders.order_rejected";
+pub const ORDER_RESERVED: &str = "orders.order_reserved";
+pub const ORDER_PARTIALLY_RESERVED: &str = "orders.order_partially_reserved";
+pub const DELIVERY_CREATED: &str = "orders.delivery_created";
+pub const DELIVERY_SUCCESS: &str = "orders.delivery_success";
+pub const ORDER_CANCELLED: &str = "orders.order_cancelled";
+pub const PRODUCT_RESTOCKED: &str = "orders.product_restocked";
+pub const PAYMENT_RECONCILE: &str = "payments.reconcile";
@@ -1,5 +1,13 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    caregivers (caregiver_id, patient_id) {
+        caregiver_id -> Int4,
+        patient_id -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     cart_items (cart_id, product_id) {
         cart_id -> Int4,
@@ -7,13 +15,39 @@ diesel::table! {
         quantity -> Int4,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        last_seen_unit_price -> Nullable<Float4>,
     }
 }
 
 diesel::table! {
     carts (id) {
         id -> Int4,
-        patient_id -> Int4,
+        patient_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        session_token -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    order_items (order_id, product_id) {
+        order_id -> Int4,
+        product_id -> Int4,
+        quantity -> Int4,
+        product_name -> Text,
+        unit_price_at_order -> Float4,
+        created_at -> Timestamptz,
+        backordered_quantity -> Int4,
+    }
+}
+
+diesel::table! {
+    order_webhooks (id) {
+        id -> Int4,
+        url -> Text,
+        event_mask -> Text,
+        secret -> Text,
+        failure_count -> Int4,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
     }
@@ -31,6 +65,11 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         deleted_at -> Nullable<Timestamptz>,
+        notes -> Nullable<Text>,
+        #[max_length = 3]
+        currency -> Varchar,
+        reserved_until -> Nullable<Timestamptz>,
+        scheduled_for -> Nullable<Timestamptz>,
     }
 }
 
@@ -42,6 +81,7 @@ diesel::table! {
         status -> Text,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        order_id -> Nullable<Int4>,
     }
 }
 
@@ -59,11 +99,62 @@ diesel::table! {
         failure_reason -> Nullable<Text>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        #[max_length = 3]
+        currency -> Varchar,
+        #[max_length = 128]
+        idempotency_key -> Nullable<Varchar>,
+        refunded_amount -> Float4,
+    }
+}
+
+diesel::table! {
+    reconciliation_issues (id) {
+        id -> Int4,
+        payment_id -> Nullable<Uuid>,
+        #[max_length = 128]
+        provider_ref -> Varchar,
+        #[max_length = 32]
+        issue -> Varchar,
+        #[max_length = 32]
+        recorded_status -> Nullable<Varchar>,
+        #[max_length = 32]
+        reported_status -> Varchar,
+        recorded_amount -> Nullable<Float4>,
+        reported_amount -> Float4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        payload -> Text,
+        status -> Text,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
 diesel::joinable!(cart_items -> carts (cart_id));
+diesel::joinable!(order_items -> orders (order_id));
 diesel::joinable!(orders -> carts (cart_id));
 diesel::joinable!(payments -> orders (order_id));
+diesel::joinable!(reconciliation_issues -> payments (payment_id));
+diesel::joinable!(webhook_deliveries -> order_webhooks (webhook_id));
 
-diesel::allow_tables_to_appear_in_same_query!(cart_items, carts, orders, outbox, payments,);
+diesel::allow_tables_to_appear_in_same_query!(
+    caregivers,
+    cart_items,
+    carts,
+    order_items,
+    order_webhooks,
+    orders,
+    outbox,
+    payments,
+    reconciliation_issues,
+    webhook_deliveries,
+);
@@ -0,0 +1,169 @@
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use medbook_core::app_error::AppError;
+use serde::de::DeserializeOwned;
+use sha2::Sha256;
+
+use crate::{
+    extract::{Validate, parse_validated},
+    settings::Settings,
+};
+
+/// Header carrying the hex-encoded HMAC-SHA256 of the request body, prefixed with `sha256=`.
+/// Used both for the webhooks we send and the callbacks we receive.
+pub const SIGNATURE_HEADER: &str = "x-medbook-signature";
+
+fn mac(secret: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac
+}
+
+/// Computes the value of [`SIGNATURE_HEADER`] for `body`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        hex::encode(mac(secret, body).finalize().into_bytes())
+    )
+}
+
+/// Whether `signature` is the [`SIGNATURE_HEADER`] value of `body` under `secret`. The digests are
+/// compared in constant time.
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(digest) = signature
+        .strip_prefix("sha256=")
+        .and_then(|digest| hex::decode(digest.trim()).ok())
+    else {
+        return false;
+    };
+    mac(secret, body).verify_slice(&digest).is_ok()
+}
+
+/// Raw body of a callback whose [`SIGNATURE_HEADER`] matches
+/// [`Settings::get_inbound_webhook_secret`].
+///
+/// The body is buffered before anything parses it, since the signature covers the exact bytes
+/// sent. Missing or wrong signatures are rejected with 403, and so is every callback while no
+/// secret is configured.
+pub struct SignedBody(pub Bytes);
+
+impl<S> FromRequest<S> for SignedBody
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let signature = req
+            .headers()
+            .get(SIGNATURE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        match (Settings::get_inbound_webhook_secret(), signature) {
+            (Some(secret), Some(signature)) if verify(&secret, &bytes, &signature) => {
+                Ok(Self(bytes))
+            }
+            _ => Err(AppError::ForbiddenResource.into_response()),
+        }
+    }
+}
+
+/// [`SignedBody`] deserialized and validated like [`crate::extract::ValidatedJson`].
+pub struct SignedJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for SignedJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let SignedBody(bytes) = SignedBody::from_request(req, state).await?;
+        Ok(Self(parse_validated(&bytes)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode};
+
+    use super::*;
+
+    const SECRET: &str = "webhook-secret";
+    const BODY: &[u8] = br#"{"order_id":1}"#;
+
+    #[test]
+    fn signatures_are_prefixed_hex_digests() {
+        let signature = sign(SECRET, BODY);
+        let digest = signature.strip_prefix("sha256=").unwrap();
+
+        assert_eq!(digest.len(), 64);
+        assert!(digest.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn valid_signatures_verify() {
+        assert!(verify(SECRET, BODY, &sign(SECRET, BODY)));
+    }
+
+    #[test]
+    fn signatures_of_another_secret_or_body_do_not_verify() {
+        assert!(!verify(SECRET, BODY, &sign("other-secret", BODY)));
+        assert!(!verify(SECRET, BODY, &sign(SECRET, br#"{"order_id":2}"#)));
+    }
+
+    #[test]
+    fn missing_or_malformed_signatures_do_not_verify() {
+        let digest = sign(SECRET, BODY).replace("sha256=", "");
+        for signature in ["", "sha256=", "sha256=not-hex", digest.as_str()] {
+            assert!(!verify(SECRET, BODY, signature));
+        }
+    }
+
+    async fn signed_body(signature: Option<&str>) -> Result<Bytes, StatusCode> {
+        // SAFETY: only these tests read the variable, and they all set it to the same value.
+        unsafe { std::env::set_var("INBOUND_WEBHOOK_SECRET", SECRET) };
+        let mut req = axum::http::Request::builder().method("POST").uri("/");
+        if let Some(signature) = signature {
+            req = req.header(SIGNATURE_HEADER, signature);
+        }
+        let req = req.body(Body::from(BODY)).unwrap();
+
+        SignedBody::from_request(req, &())
+            .await
+            .map(|SignedBody(bytes)| bytes)
+            .map_err(|response| response.status())
+    }
+
+    #[tokio::test]
+    async fn signed_body_accepts_signed_callbacks() {
+        let signature = sign(SECRET, BODY);
+
+        assert_eq!(signed_body(Some(&signature)).await, Ok(Bytes::from(BODY)));
+    }
+
+    #[tokio::test]
+    async fn signed_body_rejects_missing_signatures() {
+        assert_eq!(signed_body(None).await, Err(StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn signed_body_rejects_wrong_signatures() {
+        let signature = sign("other-secret", BODY);
+
+        assert_eq!(
+            signed_body(Some(&signature)).await,
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use diesel::{ExpressionMethods, QueryDsl, SelectableHelper};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl, pooled_connection::bb8::Pool};
+use medbook_core::outbox;
+use medbook_events::OrderCancelledEvent;
+
+use crate::{
+    db, events::OrderReserveTimeoutEvent, models::OrderEntity, order_items, order_status,
+    routing_keys, schema::orders, settings::Settings,
+};
+
+/// How often the sweeper looks for stuck orders.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// Maximum number of orders moved by a single sweep. Anything left is picked up by the next one.
+const SWEEP_BATCH_SIZE: i64 = 100;
+/// Statuses in which InventoryService holds items for an order until its `reserved_until`.
+const HELD_STATUSES: &[&str] = &["RESERVED", "PARTIALLY_RESERVED"];
+
+/// Periodically times out orders InventoryService never answered and expires reserved orders that
+/// were not paid in time. Never returns.
+pub async fn run_sweeper(pool: Pool<AsyncPgConnection>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match expire_unanswered_reservations(&pool).await {
+            Ok(0) => {}
+            Ok(count) => tracing::warn!(
+                "{} orders got no reservation answer from InventoryService in time",
+                count
+            ),
+            Err(err) => tracing::error!("Reservation timeout sweep failed: {:?}", err),
+        }
+        match expire_unpaid_reservations(&pool).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("{} unpaid reserved orders expired", count),
+            Err(err) => tracing::error!("Unpaid reservation sweep failed: {:?}", err),
+        }
+    }
+}
+
+/// Times out up to [`SWEEP_BATCH_SIZE`] PENDING orders older than
+/// [`Settings::get_reserve_timeout_secs`], moving them to RESERVE_TIMEOUT, and notifies the
+/// patients. Returns how many orders timed out.
+///
+/// Orders are picked through `orders_status_updated_at_idx`, oldest first. Rows locked by a handler
+/// or another replica are skipped rather than waited for.
+async fn expire_unanswered_reservations(pool: &Pool<AsyncPgConnection>) -> Result<usize> {
+    let conn = &mut db::acquire(pool).await?;
+    let pending_since =
+        Utc::now() - chrono::Duration::seconds(Settings::get_reserve_timeout_secs());
+
+    conn.transaction(move |conn| {
+        Box::pin(async move {
+            // `updated_at` marks when the reservation was requested, the status is unchanged since.
+            let order_ids: Vec<i32> = orders::table
+                .filter(orders::status.eq("PENDING"))
+                .filter(orders::deleted_at.is_null())
+                .filter(orders::updated_at.lt(pending_since))
+                .order_by(orders::updated_at.asc())
+                .limit(SWEEP_BATCH_SIZE)
+                .select(orders::id)
+                .for_update()
+                .skip_locked()
+                .get_results(conn)
+                .await
+                .context("Failed to get timed out pending orders")?;
+
+            let timed_out_orders: Vec<OrderEntity> =
+                diesel::update(orders::table.filter(orders::id.eq_any(&order_ids)))
+                    .set(orders::status.eq("RESERVE_TIMEOUT"))
+                    .returning(OrderEntity::as_returning())
+                    .get_results(conn)
+                    .await
+                    .context("Failed to time out pending orders")?;
+
+            for order in &timed_out_orders {
+                order_status::status_changed(conn, Some("PENDING"), order).await?;
+                outbox::publish(
+                    conn,
+                    routing_keys::ORDER_RESERVE_TIMEOUT.into(),
+                    OrderReserveTimeoutEvent {
+                        order_id: order.id,
+                        patient_id: order.patient_id,
+                    },
+                )
+                .await?;
+            }
+
+            Ok::<usize, anyhow::Error>(timed_out_orders.len())
+        })
+    })
+    .await
+}
+
+/// Moves up to [`SWEEP_BATCH_SIZE`] RESERVED or PARTIALLY_RESERVED orders past their
+/// `reserved_until` to EXPIRED and asks InventoryService to release their items. Returns how many
+/// orders expired.
+///
+/// Orders are picked through `orders_status_reserved_until_idx`, earliest expiry first. Rows locked
+/// by a payment or another replica are skipped rather than waited for.
+async fn expire_unpaid_reservations(pool: &Pool<AsyncPgConnection>) -> Result<usize> {
+    let conn = &mut db::acquire(pool).await?;
+
+    conn.transaction(move |conn| {
+        Box::pin(async move {
+            let old_statuses: HashMap<i32, String> = orders::table
+                .filter(orders::status.eq_any(HELD_STATUSES))
+                .filter(orders::reserved_until.lt(diesel::dsl::now))
+                .order_by(orders::reserved_until.asc())
+                .limit(SWEEP_BATCH_SIZE)
+                .select((orders::id, orders::status))
+                .for_update()
+                .skip_locked()
+                .get_results::<(i32, String)>(conn)
+                .await
+                .context("Failed to get expired reserved orders")?
+                .into_iter()
+                .collect();
+            let order_ids: Vec<i32> = old_statuses.keys().copied().collect();
+
+            let expired_orders: Vec<OrderEntity> =
+                diesel::update(orders::table.filter(orders::id.eq_any(&order_ids)))
+                    .set(orders::status.eq("EXPIRED"))
+                    .returning(OrderEntity::as_returning())
+                    .get_results(conn)
+                    .await
+                    .context("Failed to expire reserved orders")?;
+
+            for order in &expired_orders {
+                order_status::status_changed(conn, Some(&old_statuses[&order.id]), order).await?;
+
+                let order_items = order_items::reserved_items(conn, order).await?;
+                outbox::publish(
+                    conn,
+                    routing_keys::INVENTORY_CANCEL_ORDER.into(),
+                    OrderCancelledEvent {
+                        order_id: order.id,
+                        order_items,
+                    },
+                )
+                .await?;
+            }
+
+            Ok::<usize, anyhow::Error>(expired_orders.len())
+        })
+    })
+    .await
+}
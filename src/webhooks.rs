@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl, pooled_connection::bb8::Pool};
+use reqwest::Client;
+use serde::Serialize;
+
+use crate::{
+    models::{CreateWebhookDeliveryEntity, OrderEntity, OrderWebhookEntity, WebhookDeliveryEntity},
+    schema::{order_webhooks, webhook_deliveries},
+    security::{SIGNATURE_HEADER, sign},
+};
+
+/// How often the relay looks for due deliveries.
+const RELAY_INTERVAL: Duration = Duration::from_secs(5);
+/// Maximum number of deliveries attempted in a single relay pass.
+const RELAY_BATCH_SIZE: i64 = 50;
+/// Deliveries are marked as FAILED after this many unsuccessful attempts.
+const MAX_ATTEMPTS: i32 = 8;
+/// Timeout for a single outgoing webhook request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long claimed deliveries stay hidden from other relays, enough to send a whole batch.
+const CLAIM_LEASE: Duration =
+    Duration::from_secs(REQUEST_TIMEOUT.as_secs() * RELAY_BATCH_SIZE as u64);
+
+/// Body POSTed to subscribers when an order changes status.
+#[derive(Serialize, Debug)]
+pub struct OrderStatusChangedPayload {
+    pub order_id: i32,
+    pub patient_id: i32,
+    pub status: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Returns whether a webhook's event mask subscribes to `status`.
+pub fn mask_matches(event_mask: &str, status: &str) -> bool {
+    event_mask
+        .split(',')
+        .map(str::trim)
+        .any(|event| event == "*" || event.eq_ignore_ascii_case(status))
+}
+
+/// Queues a delivery for every webhook subscribed to the order's current status.
+/// Runs on the caller's connection so the deliveries commit together with the status change.
+pub async fn enqueue_status_change(
+    conn: &mut AsyncPgConnection,
+    order: &OrderEntity,
+) -> Result<()> {
+    let webhooks: Vec<OrderWebhookEntity> = order_webhooks::table
+        .get_results(conn)
+        .await
+        .context("Failed to get order webhooks")?;
+
+    let payload = serde_json::to_string(&OrderStatusChangedPayload {
+        order_id: order.id,
+        patient_id: order.patient_id,
+        status: order.status.clone(),
+        changed_at: order.updated_at,
+    })
+    .context("Failed to serialize webhook payload")?;
+
+    let deliveries: Vec<CreateWebhookDeliveryEntity> = webhooks
+        .iter()
+        .filter(|webhook| mask_matches(&webhook.event_mask, &order.status))
+        .map(|webhook| CreateWebhookDeliveryEntity {
+            webhook_id: webhook.id,
+            payload: payload.clone(),
+        })
+        .collect();
+
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+
+    diesel::insert_into(webhook_deliveries::table)
+        .values(deliveries)
+        .execute(conn)
+        .await
+        .context("Failed to enqueue webhook deliveries")?;
+
+    Ok(())
+}
+
+/// Periodically sends due webhook deliveries. Never returns.
+pub async fn run_relay(pool: Pool<AsyncPgConnection>, client: Client) {
+    let mut interval = tokio::time::interval(RELAY_INTERVAL);
+    loop {
+        interval.tick().await;
+        match relay_once(&pool, &client).await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Relayed {} webhook deliveries", count),
+            Err(err) => tracing::error!("Webhook relay failed: {:?}", err),
+        }
+    }
+}
+
+/// Attempts one batch of due deliveries and returns how many were sent successfully.
+///
+/// Due rows are claimed in a short transaction by pushing their next attempt [`CLAIM_LEASE`] ahead,
+/// with `SKIP LOCKED` so several replicas can relay concurrently. The requests are sent after it
+/// commits, so slow subscribers never hold row locks or a transaction open. Deliveries claimed
+/// by a replica that dies before recording the outcome are retried once the lease runs out.
+async fn relay_once(pool: &Pool<AsyncPgConnection>, client: &Client) -> Result<usize> {
+    let conn = &mut pool
+        .get()
+        .await
+        .context("Failed to obtain a DB connection pool")?;
+
+    let (deliveries, webhooks) = claim_due_deliveries(conn).await?;
+
+    let mut sent = 0;
+    for delivery in deliveries {
+        let Some(webhook) = webhooks.iter().find(|w| w.id == delivery.webhook_id) else {
+            continue;
+        };
+
+        match send(client, webhook, &delivery.payload).await {
+            Ok(()) => {
+                diesel::update(webhook_deliveries::table.find(delivery.id))
+                    .set((
+                        webhook_deliveries::status.eq("SENT"),
+                        webhook_deliveries::attempts.eq(delivery.attempts + 1),
+                        webhook_deliveries::last_error.eq(None::<String>),
+                    ))
+                    .execute(conn)
+                    .await
+                    .context("Failed to mark webhook delivery as sent")?;
+                sent += 1;
+            }
+            Err(err) => {
+                let attempts = delivery.attempts + 1;
+                let status = if attempts >= MAX_ATTEMPTS {
+                    "FAILED"
+                } else {
+                    "PENDING"
+                };
+                tracing::warn!(
+                    "Webhook delivery #{} to {} failed (attempt {}): {}",
+                    delivery.id,
+                    webhook.url,
+                    attempts,
+                    err
+                );
+
+                diesel::update(webhook_deliveries::table.find(delivery.id))
+                    .set((
+                        webhook_deliveries::status.eq(status),
+                        webhook_deliveries::attempts.eq(attempts),
+                        webhook_deliveries::last_error.eq(err.to_string()),
+                        webhook_deliveries::next_attempt_at
+                            .eq(Utc::now() + retry_backoff(attempts)),
+                    ))
+                    .execute(conn)
+                    .await
+                    .context("Failed to reschedule webhook delivery")?;
+
+                diesel::update(order_webhooks::table.find(webhook.id))
+                    .set(order_webhooks::failure_count.eq(order_webhooks::failure_count + 1))
+                    .execute(conn)
+                    .await
+                    .context("Failed to update webhook failure count")?;
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Claims a batch of due deliveries for this replica, along with the webhooks they go to.
+async fn claim_due_deliveries(
+    conn: &mut AsyncPgConnection,
+) -> Result<(Vec<WebhookDeliveryEntity>, Vec<OrderWebhookEntity>)> {
+    conn.transaction(|conn| {
+        Box::pin(async move {
+            let deliveries: Vec<WebhookDeliveryEntity> = webhook_deliveries::table
+                .filter(webhook_deliveries::status.eq("PENDING"))
+                .filter(webhook_deliveries::next_attempt_at.le(diesel::dsl::now))
+                .order_by(webhook_deliveries::id.asc())
+                .limit(RELAY_BATCH_SIZE)
+                .for_update()
+                .skip_locked()
+                .get_results(conn)
+                .await
+                .context("Failed to get due webhook deliveries")?;
+
+            let delivery_ids: Vec<i32> = deliveries.iter().map(|d| d.id).collect();
+            let lease = chrono::Duration::from_std(CLAIM_LEASE).context("Invalid claim lease")?;
+            diesel::update(
+                webhook_deliveries::table.filter(webhook_deliveries::id.eq_any(&delivery_ids)),
+            )
+            .set(webhook_deliveries::next_attempt_at.eq(Utc::now() + lease))
+            .execute(conn)
+            .await
+            .context("Failed to claim webhook deliveries")?;
+
+            let webhook_ids: Vec<i32> = deliveries.iter().map(|d| d.webhook_id).collect();
+            let webhooks: Vec<OrderWebhookEntity> = order_webhooks::table
+                .filter(order_webhooks::id.eq_any(&webhook_ids))
+                .get_results(conn)
+                .await
+                .context("Failed to get order webhooks")?;
+
+            Ok::<_, anyhow::Error>((deliveries, webhooks))
+        })
+    })
+    .await
+}
+
+async fn send(client: &Client, webhook: &OrderWebhookEntity, payload: &str) -> Result<()> {
+    client
+        .post(&webhook.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, payload.as_bytes()))
+        .body(payload.to_string())
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+/// Exponential backoff starting at 30 seconds, capped at one hour.
+fn retry_backoff(attempts: i32) -> chrono::Duration {
+    let seconds = 30i64.saturating_mul(1 << attempts.clamp(0, 7));
+    chrono::Duration::seconds(seconds.min(3600))
+}